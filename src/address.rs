@@ -1,6 +1,6 @@
 use arrayref::array_mut_ref;
 use byteorder::{BigEndian, ByteOrder};
-use std::fmt;
+use core::fmt;
 
 #[derive(PartialEq, Eq)]
 pub struct Address {
@@ -27,6 +27,11 @@ impl Address {
         self.instance
     }
 
+    pub fn get_layer(&self) -> u32 {
+        self.layer
+    }
+
+    #[cfg(feature = "alloc")]
     pub fn incr_instance(&mut self) {
         self.instance += 1;
     }
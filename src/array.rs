@@ -0,0 +1,62 @@
+use core::ops::{Index, IndexMut};
+
+/// Fixed-size array, used by [`GravityParams`](crate::GravityParams) to size the components of a
+/// signature without relying on generic constant expressions. It is implemented for all arrays
+/// `[T; N]`.
+pub trait Array<T>:
+    AsRef<[T]> + AsMut<[T]> + Index<usize, Output = T> + IndexMut<usize> + Sized
+{
+    fn from_fn<F: FnMut(usize) -> T>(f: F) -> Self;
+
+    // Stops at the first `None`, like `core::array::try_from_fn`.
+    fn try_from_fn<F: FnMut(usize) -> Option<T>>(f: F) -> Option<Self>;
+}
+
+impl<T, const N: usize> Array<T> for [T; N] {
+    fn from_fn<F: FnMut(usize) -> T>(f: F) -> Self {
+        core::array::from_fn(f)
+    }
+
+    fn try_from_fn<F: FnMut(usize) -> Option<T>>(mut f: F) -> Option<Self> {
+        let mut ok = true;
+        let array: [Option<T>; N] = core::array::from_fn(|i| {
+            if ok {
+                let x = f(i);
+                ok = x.is_some();
+                x
+            } else {
+                None
+            }
+        });
+        if ok {
+            Some(array.map(Option::unwrap))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_fn() {
+        let array: [usize; 4] = Array::from_fn(|i| 2 * i);
+        assert_eq!(array, [0, 2, 4, 6]);
+    }
+
+    #[test]
+    fn test_try_from_fn() {
+        let array: Option<[usize; 4]> = Array::try_from_fn(|i| Some(2 * i));
+        assert_eq!(array, Some([0, 2, 4, 6]));
+
+        let mut calls = 0;
+        let array: Option<[usize; 4]> = Array::try_from_fn(|i| {
+            calls += 1;
+            if i == 1 { None } else { Some(i) }
+        });
+        assert_eq!(array, None);
+        assert_eq!(calls, 2);
+    }
+}
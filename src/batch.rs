@@ -0,0 +1,302 @@
+use crate::address;
+use crate::config::GravityParams;
+use crate::gravity::{PubKey, SecKey, Signature};
+use crate::hash::{Hash, HashFunction, Tweak, TweakKind};
+use crate::merkle;
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::{BigEndian, ByteOrder};
+use core::marker::PhantomData;
+
+// Batches are limited to 2^MAX_HEIGHT messages, so that the index of a message fits in 32 bits.
+const MAX_HEIGHT: usize = 32;
+
+/// Proof that a message belongs to a batch signed by [`SecKey::sign_batch`]: the index of the
+/// message in the batch, and its authentication path in the Merkle tree of the batch.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BatchProof<P: GravityParams> {
+    index: usize,
+    auth: Vec<Hash>,
+    _phantom: PhantomData<P>,
+}
+
+impl<P: GravityParams> SecKey<P> {
+    /// Signs a batch of messages at once. The hashes of the messages are the leaves of a Merkle
+    /// tree, padded with empty leaves to a power of two, and only the root of this tree is
+    /// signed, together with its height. Each message is then authenticated by the signature of
+    /// the root together with its proof, which holds `ceil(log2(msgs.len()))` hashes.
+    ///
+    /// The messages are hashed with a prefix byte, so that a leaf differs from the hash of an
+    /// inner node, and the height makes proofs of another length invalid, so that an inner node
+    /// cannot be passed off as a message. The signed hash of the root is not the digest of any
+    /// known message, so that a batch signature and a [`sign_bytes`](Self::sign_bytes) signature
+    /// are never valid for each other.
+    ///
+    /// Returns `None` if the batch is empty or has more than `2^32` messages.
+    pub fn sign_batch(&self, msgs: &[&[u8]]) -> Option<(Signature<P>, Vec<BatchProof<P>>)> {
+        if msgs.is_empty() {
+            return None;
+        }
+        let height = msgs.len().next_power_of_two().trailing_zeros() as usize;
+        if height > MAX_HEIGHT {
+            return None;
+        }
+
+        let mut tree = merkle::MerkleTree::new(height);
+        for (leaf, msg) in tree.leaves().iter_mut().zip(msgs) {
+            *leaf = batch_leaf::<P>(msg);
+        }
+        tree.generate(&batch_tweak::<P>(self.pub_seed()));
+
+        let proofs = (0..msgs.len())
+            .map(|index| {
+                let mut auth = vec![Default::default(); height];
+                tree.gen_auth(&mut auth, index);
+                BatchProof {
+                    index,
+                    auth,
+                    _phantom: PhantomData,
+                }
+            })
+            .collect();
+        Some((
+            self.sign_hash(&batch_root::<P>(&tree.root(), height)),
+            proofs,
+        ))
+    }
+}
+
+impl<P: GravityParams> PubKey<P> {
+    /// Verifies that `msg` belongs to the batch whose root is signed by `sign`.
+    pub fn verify_batch(&self, sign: &Signature<P>, proof: &BatchProof<P>, msg: &[u8]) -> bool {
+        let mut h = batch_leaf::<P>(msg);
+        merkle::merkle_compress_auth(
+            &batch_tweak::<P>(sign.pub_seed()),
+            &mut h,
+            &proof.auth,
+            proof.auth.len(),
+            proof.index,
+        );
+        self.verify_hash(sign, &batch_root::<P>(&h, proof.auth.len()))
+    }
+}
+
+const LEAF_PREFIX: u8 = 0x00;
+const ROOT_PREFIX: u8 = 0x01;
+
+fn batch_leaf<P: GravityParams>(msg: &[u8]) -> Hash {
+    let mut bytes = Vec::with_capacity(1 + msg.len());
+    bytes.push(LEAF_PREFIX);
+    bytes.extend_from_slice(msg);
+    P::HashFunction::long_hash(&bytes)
+}
+
+// The signed hash binds the root to the height of the batch. It is the complement of a long
+// hash, because `sign_bytes` signs the long hash of any message: a message signature then holds
+// for a batch only if its digest is the complement of a root hash, which takes a preimage. A
+// tweak would not do, as the tweaks and the SHAKE256 short hashes are also long hashes.
+fn batch_root<P: GravityParams>(root: &Hash, height: usize) -> Hash {
+    let mut bytes = vec![ROOT_PREFIX, height as u8];
+    root.serialize(P::HASH_BYTES, &mut bytes);
+    let mut h = P::HashFunction::long_hash(&bytes);
+    for x in h.h[..P::HASH_BYTES].iter_mut() {
+        *x = !*x;
+    }
+    h
+}
+
+fn batch_tweak<P: GravityParams>(seed: Option<&Hash>) -> Tweak<'_, P::HashFunction> {
+    Tweak::new(seed, &address::Address::new(0, 0), TweakKind::BatchNode)
+}
+
+impl<P: GravityParams> BatchProof<P> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn serialize(&self, output: &mut Vec<u8>) {
+        let mut index = [0; 4];
+        BigEndian::write_u32(&mut index, self.index as u32);
+        output.extend(index);
+        output.push(self.auth.len() as u8);
+        for x in self.auth.iter() {
+            x.serialize(P::HASH_BYTES, output);
+        }
+    }
+
+    pub fn deserialize<'a, I>(it: &mut I) -> Option<Self>
+    where
+        I: Iterator<Item = &'a u8>,
+    {
+        let mut index = [0; 4];
+        for x in index.iter_mut() {
+            *x = *it.next()?;
+        }
+        let index = BigEndian::read_u32(&index) as usize;
+        let height = *it.next()? as usize;
+        if height > MAX_HEIGHT || (index as u64) >> height != 0 {
+            return None;
+        }
+        let auth = (0..height)
+            .map(|_| Hash::deserialize(P::HASH_BYTES, it))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            index,
+            auth,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GravitySmall, GravitySmall128, GravitySmallShake, GravitySmallTweaked};
+
+    crate::tests::param_tests!(small, GravitySmall, test_sign_verify_batch,);
+    crate::tests::param_tests!(small_tweaked, GravitySmallTweaked, test_sign_verify_batch,);
+    crate::tests::param_tests!(small_128, GravitySmall128, test_sign_verify_batch,);
+
+    fn test_sign_verify_batch<P: GravityParams>() {
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let sk = SecKey::<P>::new(&random);
+        let pk = sk.genpk();
+
+        assert!(sk.sign_batch(&[]).is_none());
+
+        for count in [1, 2, 5, 8] {
+            let msgs: Vec<Vec<u8>> = (0..count).map(|i| vec![i as u8; i]).collect();
+            let msgs: Vec<&[u8]> = msgs.iter().map(Vec::as_slice).collect();
+            let (sign, proofs) = sk.sign_batch(&msgs).unwrap();
+            assert_eq!(proofs.len(), count);
+
+            for (i, (proof, msg)) in proofs.iter().zip(&msgs).enumerate() {
+                assert_eq!(proof.index(), i);
+                assert_eq!(
+                    proof.auth.len(),
+                    count.next_power_of_two().trailing_zeros() as usize
+                );
+                assert!(pk.verify_batch(&sign, proof, msg));
+                assert!(!pk.verify_batch(&sign, proof, b"Goodbye"));
+
+                let mut bytes = Vec::new();
+                proof.serialize(&mut bytes);
+                assert_eq!(bytes.len(), 5 + proof.auth.len() * P::HASH_BYTES);
+                let deserialized = BatchProof::<P>::deserialize(&mut bytes.iter()).unwrap();
+                assert_eq!(&deserialized, proof);
+            }
+
+            // The signature is only valid with a proof, for the right message.
+            assert!(!pk.verify_bytes(&sign, msgs[0]));
+            if count > 1 {
+                assert!(!pk.verify_batch(&sign, &proofs[0], msgs[1]));
+            }
+        }
+    }
+
+    // With an untweaked hash function, the hash of two concatenated hashes may be the hash of
+    // their parent node, which must not be accepted as a message of the batch.
+    #[test]
+    fn test_verify_batch_inner_node() {
+        type P = GravitySmallShake;
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let sk = SecKey::<P>::new(&random);
+        let pk = sk.genpk();
+
+        let msgs: [&[u8]; 4] = [b"a", b"b", b"c", b"d"];
+        let (sign, proofs) = sk.sign_batch(&msgs).unwrap();
+        let leaves: Vec<Hash> = msgs.iter().map(|msg| batch_leaf::<P>(msg)).collect();
+        let node = |l: &Hash, r: &Hash| {
+            let mut forged = Vec::new();
+            l.serialize(P::HASH_BYTES, &mut forged);
+            r.serialize(P::HASH_BYTES, &mut forged);
+            forged
+        };
+
+        // The inner nodes as messages, with shorter proofs.
+        let forged = node(&leaves[0], &leaves[1]);
+        let proof = BatchProof::<P> {
+            index: 0,
+            auth: proofs[0].auth[1..].to_vec(),
+            _phantom: PhantomData,
+        };
+        assert!(!pk.verify_batch(&sign, &proof, &forged));
+        assert!(!pk.verify_batch(&sign, &proof, &forged[1..]));
+
+        let h01 = <P as GravityParams>::HashFunction::hash_2n_to_n_ret(&leaves[0], &leaves[1]);
+        let h23 = <P as GravityParams>::HashFunction::hash_2n_to_n_ret(&leaves[2], &leaves[3]);
+        let proof = BatchProof::<P> {
+            index: 0,
+            auth: Vec::new(),
+            _phantom: PhantomData,
+        };
+        assert!(!pk.verify_batch(&sign, &proof, &node(&h01, &h23)));
+    }
+
+    // A message signature must not verify as a batch signature, nor the converse, even for the
+    // bytes that the root of a batch is hashed from.
+    #[test]
+    fn test_batch_message_domains() {
+        type P = GravitySmallShake;
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let sk = SecKey::<P>::new(&random);
+        let pk = sk.genpk();
+
+        let evil: &[u8] = b"evil";
+        let mut msg = vec![ROOT_PREFIX, 0];
+        batch_leaf::<P>(evil).serialize(P::HASH_BYTES, &mut msg);
+        let sign = sk.sign_bytes(&msg);
+        assert!(pk.verify_bytes(&sign, &msg));
+        let proof = BatchProof::<P> {
+            index: 0,
+            auth: Vec::new(),
+            _phantom: PhantomData,
+        };
+        assert!(!pk.verify_batch(&sign, &proof, evil));
+
+        let (sign, proofs) = sk.sign_batch(&[evil]).unwrap();
+        assert!(pk.verify_batch(&sign, &proofs[0], evil));
+        assert!(!pk.verify_bytes(&sign, &msg));
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        type P = GravitySmall;
+        let mut bytes = vec![0, 0, 0, 2, 1];
+        bytes.extend([0; 32]);
+        assert!(BatchProof::<P>::deserialize(&mut bytes.iter()).is_none());
+        bytes[3] = 1;
+        assert!(BatchProof::<P>::deserialize(&mut bytes.iter()).is_some());
+        assert!(BatchProof::<P>::deserialize(&mut bytes[..36].iter()).is_none());
+
+        let mut bytes = vec![0, 0, 0, 0, 33];
+        bytes.extend([0; 33 * 32]);
+        assert!(BatchProof::<P>::deserialize(&mut bytes.iter()).is_none());
+    }
+
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+        use test::{Bencher, black_box};
+
+        #[bench]
+        fn bench_sign_batch_1024_small(b: &mut Bencher) {
+            let seed = [0u8; 64];
+            let sk = SecKey::<GravitySmall>::new(&seed);
+            let msgs: Vec<[u8; 8]> = (0..1024u64).map(u64::to_be_bytes).collect();
+            let msgs: Vec<&[u8]> = msgs.iter().map(|x| x.as_slice()).collect();
+            b.iter(|| sk.sign_batch(black_box(&msgs)));
+        }
+
+        #[bench]
+        fn bench_verify_batch_1024_small(b: &mut Bencher) {
+            let seed = [0u8; 64];
+            let sk = SecKey::<GravitySmall>::new(&seed);
+            let pk = sk.genpk();
+            let msgs: Vec<[u8; 8]> = (0..1024u64).map(u64::to_be_bytes).collect();
+            let msgs: Vec<&[u8]> = msgs.iter().map(|x| x.as_slice()).collect();
+            let (sign, proofs) = sk.sign_batch(&msgs).unwrap();
+            b.iter(|| pk.verify_batch(&sign, &proofs[0], black_box(msgs[0])));
+        }
+    }
+}
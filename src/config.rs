@@ -1,4 +1,9 @@
-use std::fmt::Debug;
+use crate::array::Array;
+use crate::hash::{self, Haraka, HashFunction, Sha2, Shake, Truncated};
+use crate::prng::{self, AesCtrPrng};
+#[cfg(test)]
+use crate::prng::{ChaCha20Prng, ShakePrng};
+use core::fmt::Debug;
 
 pub trait GravityParams: Debug + PartialEq {
     #[cfg(test)]
@@ -7,16 +12,52 @@ pub trait GravityParams: Debug + PartialEq {
 
     /// 16 is often good.
     const TAU: usize;
+    /// Number of leaves of the PORS tree, whose height is `TAU`. Values between `2^(TAU-1) + 1`
+    /// and `2^TAU` allow finer tuning than `TAU` alone.
+    const T: usize = 1 << Self::TAU;
     const K: usize;
     /// 5 is often good.
     const H: usize;
     const D: usize;
     const C: usize;
+    /// Object identifier of this parameter set, used in DER/PEM key containers.
+    /// No official OIDs are assigned yet, so the provided parameter sets use a
+    /// placeholder private arc (1.3.6.1.4.1.99999.1).
+    const OID: &'static [u32];
+    /// Whether hashes are tweaked by a public seed and by their position in the hypertree, in
+    /// the style of the SPHINCS+ "simple" instances. This prevents multi-target attacks on the
+    /// hash function, at the cost of one extra hash in each signature (the public seed) and of
+    /// an extra Haraka call per hash. Keys and signatures are not compatible between the two
+    /// modes.
+    const TWEAKED: bool = false;
+    /// Winternitz parameter of the WOTS signatures, either 4, 16 or 256. A larger value gives
+    /// shorter signatures, but slower signing and verification.
+    const W: usize = 16;
+    /// Hash function, see [`HashFunction`](crate::HashFunction).
+    type HashFunction: hash::HashFunction;
+    /// Generator of the secret values from the secret seed, see
+    /// [`SecretPrng`](crate::SecretPrng).
+    type Prng: prng::SecretPrng;
+
+    /// Arrays of `PORS_K`, `PORS_OCTOPUS_CAPACITY`, `MERKLE_H`, `GRAVITY_C` and `GRAVITY_D`
+    /// elements, which should be defined as `[T; Self::PORS_K]` and so on.
+    type PorsKArray<T: Copy>: Array<T> + Copy;
+    type OctopusArray<T: Copy>: Array<T> + Copy;
+    type MerkleHArray<T: Copy>: Array<T> + Copy;
+    type GravityCArray<T: Copy>: Array<T> + Copy;
+    type GravityDArray<T>: Array<T>;
+    /// Array of `WOTS_ELL` elements, which should be defined as `[T; Self::WOTS_ELL]`.
+    type WotsArray<T: Copy>: Array<T> + Copy;
 
     /* Don't modify */
+    // Size of the hashes in bytes, which is at most HASH_SIZE.
+    const HASH_BYTES: usize = <Self::HashFunction as HashFunction>::OUTPUT_BYTES;
+
     const PORS_TAU: usize = Self::TAU;
-    const PORS_T: usize = 1 << Self::PORS_TAU;
+    const PORS_T: usize = Self::T;
     const PORS_K: usize = Self::K;
+    // Number of hash slots reserved for the octopus in a signature.
+    const PORS_OCTOPUS_CAPACITY: usize = Self::PORS_K * Self::PORS_TAU;
 
     // Implicit constraint: GRAVITY_C + MERKLE_H * GRAVITY_D <= 64
     const MERKLE_H: usize = Self::H;
@@ -28,6 +69,31 @@ pub trait GravityParams: Debug + PartialEq {
     // Note: dirty hack to avoid shift overflow when GRAVITY_H = 64
     const GRAVITY_MASK: u64 = 0xFFFF_FFFF_FFFF_FFFF_u64
         ^ ((0xFFFF_FFFF_FFFF_FFFF_u64 << Self::GRAVITY_HD) << Self::GRAVITY_C);
+
+    const WOTS_W: usize = Self::W;
+    const WOTS_LOG_W: usize = Self::WOTS_W.trailing_zeros() as usize;
+    // Number of base-W digits of the message, and of its checksum.
+    const WOTS_ELL1: usize = Self::HASH_BYTES * 8 / Self::WOTS_LOG_W;
+    const WOTS_CHKSUM: usize =
+        ((usize::BITS - (Self::WOTS_ELL1 * (Self::WOTS_W - 1)).leading_zeros()) as usize)
+            .div_ceil(Self::WOTS_LOG_W);
+    const WOTS_ELL: usize = Self::WOTS_ELL1 + Self::WOTS_CHKSUM;
+
+    // Sizes of serialized signatures. The octopus is padded to its capacity and followed by a
+    // 16-byte block holding its length.
+    const PORS_SIGNATURE_BYTES: usize =
+        (1 + Self::PORS_K + Self::PORS_OCTOPUS_CAPACITY) * Self::HASH_BYTES + 16;
+    const SUBTREE_SIGNATURE_BYTES: usize = (Self::WOTS_ELL + Self::MERKLE_H) * Self::HASH_BYTES;
+    const SIGNATURE_BYTES: usize = (Self::TWEAKED as usize) * Self::HASH_BYTES
+        + Self::PORS_SIGNATURE_BYTES
+        + Self::SUBTREE_SIGNATURE_BYTES * Self::GRAVITY_D
+        + Self::GRAVITY_C * Self::HASH_BYTES;
+    // In the stateful mode, the PORS signature is replaced by an 8-byte leaf index, a randomizer
+    // and a WOTS signature.
+    const STATEFUL_SIGNATURE_BYTES: usize = 8
+        + (Self::TWEAKED as usize + 1 + Self::WOTS_ELL) * Self::HASH_BYTES
+        + Self::SUBTREE_SIGNATURE_BYTES * Self::GRAVITY_D
+        + Self::GRAVITY_C * Self::HASH_BYTES;
 }
 
 #[derive(Debug, PartialEq)]
@@ -44,7 +110,11 @@ impl GravityParams for GravitySmall {
         const {
             assert!(Self::PORS_K > 0);
             assert!(Self::PORS_K <= Self::PORS_T);
+            assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+            assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
             assert!(Self::GRAVITY_C + Self::MERKLE_H * Self::GRAVITY_D <= 64);
+            assert!(Self::WOTS_W == 4 || Self::WOTS_W == 16 || Self::WOTS_W == 256);
+            assert!(Self::HASH_BYTES == 16 || Self::HASH_BYTES == 24 || Self::HASH_BYTES == 32);
         };
     }
 
@@ -53,6 +123,16 @@ impl GravityParams for GravitySmall {
     const H: usize = 5;
     const D: usize = 1;
     const C: usize = 10;
+    const OID: &'static [u32] = &[1, 3, 6, 1, 4, 1, 99999, 1, 1];
+
+    type HashFunction = Haraka;
+    type Prng = AesCtrPrng;
+    type PorsKArray<T: Copy> = [T; Self::PORS_K];
+    type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+    type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+    type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+    type GravityDArray<T> = [T; Self::GRAVITY_D];
+    type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
 }
 
 #[derive(Debug, PartialEq)]
@@ -69,7 +149,11 @@ impl GravityParams for GravityMedium {
         const {
             assert!(Self::PORS_K > 0);
             assert!(Self::PORS_K <= Self::PORS_T);
+            assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+            assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
             assert!(Self::GRAVITY_C + Self::MERKLE_H * Self::GRAVITY_D <= 64);
+            assert!(Self::WOTS_W == 4 || Self::WOTS_W == 16 || Self::WOTS_W == 256);
+            assert!(Self::HASH_BYTES == 16 || Self::HASH_BYTES == 24 || Self::HASH_BYTES == 32);
         };
     }
 
@@ -78,6 +162,16 @@ impl GravityParams for GravityMedium {
     const H: usize = 5;
     const D: usize = 7;
     const C: usize = 15;
+    const OID: &'static [u32] = &[1, 3, 6, 1, 4, 1, 99999, 1, 2];
+
+    type HashFunction = Haraka;
+    type Prng = AesCtrPrng;
+    type PorsKArray<T: Copy> = [T; Self::PORS_K];
+    type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+    type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+    type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+    type GravityDArray<T> = [T; Self::GRAVITY_D];
+    type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
 }
 
 #[derive(Debug, PartialEq)]
@@ -94,7 +188,11 @@ impl GravityParams for GravityLarge {
         const {
             assert!(Self::PORS_K > 0);
             assert!(Self::PORS_K <= Self::PORS_T);
+            assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+            assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
             assert!(Self::GRAVITY_C + Self::MERKLE_H * Self::GRAVITY_D <= 64);
+            assert!(Self::WOTS_W == 4 || Self::WOTS_W == 16 || Self::WOTS_W == 256);
+            assert!(Self::HASH_BYTES == 16 || Self::HASH_BYTES == 24 || Self::HASH_BYTES == 32);
         };
     }
 
@@ -103,16 +201,280 @@ impl GravityParams for GravityLarge {
     const H: usize = 5;
     const D: usize = 10;
     const C: usize = 14;
+    const OID: &'static [u32] = &[1, 3, 6, 1, 4, 1, 99999, 1, 3];
+
+    type HashFunction = Haraka;
+    type Prng = AesCtrPrng;
+    type PorsKArray<T: Copy> = [T; Self::PORS_K];
+    type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+    type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+    type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+    type GravityDArray<T> = [T; Self::GRAVITY_D];
+    type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
+}
+
+/// Same parameters as [`GravitySmall`], with tweaked hashes.
+#[derive(Debug, PartialEq)]
+pub struct GravitySmallTweaked;
+
+impl GravityParams for GravitySmallTweaked {
+    #[cfg(test)]
+    fn config_type() -> ConfigType {
+        ConfigType::ST
+    }
+
+    fn check_params() {
+        // TODO: Move this implementation to the trait when supported.
+        const {
+            assert!(Self::PORS_K > 0);
+            assert!(Self::PORS_K <= Self::PORS_T);
+            assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+            assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
+            assert!(Self::GRAVITY_C + Self::MERKLE_H * Self::GRAVITY_D <= 64);
+            assert!(Self::WOTS_W == 4 || Self::WOTS_W == 16 || Self::WOTS_W == 256);
+            assert!(Self::HASH_BYTES == 16 || Self::HASH_BYTES == 24 || Self::HASH_BYTES == 32);
+        };
+    }
+
+    const TAU: usize = 16;
+    const K: usize = 24;
+    const H: usize = 5;
+    const D: usize = 1;
+    const C: usize = 10;
+    const OID: &'static [u32] = &[1, 3, 6, 1, 4, 1, 99999, 1, 4];
+    const TWEAKED: bool = true;
+
+    type HashFunction = Haraka;
+    type Prng = AesCtrPrng;
+    type PorsKArray<T: Copy> = [T; Self::PORS_K];
+    type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+    type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+    type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+    type GravityDArray<T> = [T; Self::GRAVITY_D];
+    type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
+}
+
+/// Same parameters as [`GravitySmall`], with SHA-256 as the hash function (Gravity-SHA2).
+#[derive(Debug, PartialEq)]
+pub struct GravitySmallSha2;
+
+impl GravityParams for GravitySmallSha2 {
+    #[cfg(test)]
+    fn config_type() -> ConfigType {
+        ConfigType::SSha2
+    }
+
+    fn check_params() {
+        // TODO: Move this implementation to the trait when supported.
+        const {
+            assert!(Self::PORS_K > 0);
+            assert!(Self::PORS_K <= Self::PORS_T);
+            assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+            assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
+            assert!(Self::GRAVITY_C + Self::MERKLE_H * Self::GRAVITY_D <= 64);
+            assert!(Self::WOTS_W == 4 || Self::WOTS_W == 16 || Self::WOTS_W == 256);
+            assert!(Self::HASH_BYTES == 16 || Self::HASH_BYTES == 24 || Self::HASH_BYTES == 32);
+        };
+    }
+
+    const TAU: usize = 16;
+    const K: usize = 24;
+    const H: usize = 5;
+    const D: usize = 1;
+    const C: usize = 10;
+    const OID: &'static [u32] = &[1, 3, 6, 1, 4, 1, 99999, 1, 5];
+
+    type HashFunction = Sha2;
+    type Prng = AesCtrPrng;
+    type PorsKArray<T: Copy> = [T; Self::PORS_K];
+    type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+    type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+    type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+    type GravityDArray<T> = [T; Self::GRAVITY_D];
+    type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
+}
+
+/// Same parameters as [`GravitySmall`], with SHAKE256 as the hash function (Gravity-SHAKE).
+#[derive(Debug, PartialEq)]
+pub struct GravitySmallShake;
+
+impl GravityParams for GravitySmallShake {
+    #[cfg(test)]
+    fn config_type() -> ConfigType {
+        ConfigType::SShake
+    }
+
+    fn check_params() {
+        // TODO: Move this implementation to the trait when supported.
+        const {
+            assert!(Self::PORS_K > 0);
+            assert!(Self::PORS_K <= Self::PORS_T);
+            assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+            assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
+            assert!(Self::GRAVITY_C + Self::MERKLE_H * Self::GRAVITY_D <= 64);
+            assert!(Self::WOTS_W == 4 || Self::WOTS_W == 16 || Self::WOTS_W == 256);
+            assert!(Self::HASH_BYTES == 16 || Self::HASH_BYTES == 24 || Self::HASH_BYTES == 32);
+        };
+    }
+
+    const TAU: usize = 16;
+    const K: usize = 24;
+    const H: usize = 5;
+    const D: usize = 1;
+    const C: usize = 10;
+    const OID: &'static [u32] = &[1, 3, 6, 1, 4, 1, 99999, 1, 6];
+
+    type HashFunction = Shake;
+    type Prng = AesCtrPrng;
+    type PorsKArray<T: Copy> = [T; Self::PORS_K];
+    type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+    type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+    type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+    type GravityDArray<T> = [T; Self::GRAVITY_D];
+    type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
+}
+
+/// Same parameters as [`GravitySmall`], with Haraka truncated to 16 bytes for smaller
+/// signatures, at the NIST security category 1.
+#[derive(Debug, PartialEq)]
+pub struct GravitySmall128;
+
+impl GravityParams for GravitySmall128 {
+    #[cfg(test)]
+    fn config_type() -> ConfigType {
+        ConfigType::S128
+    }
+
+    fn check_params() {
+        // TODO: Move this implementation to the trait when supported.
+        const {
+            assert!(Self::PORS_K > 0);
+            assert!(Self::PORS_K <= Self::PORS_T);
+            assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+            assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
+            assert!(Self::GRAVITY_C + Self::MERKLE_H * Self::GRAVITY_D <= 64);
+            assert!(Self::WOTS_W == 4 || Self::WOTS_W == 16 || Self::WOTS_W == 256);
+            assert!(Self::HASH_BYTES == 16 || Self::HASH_BYTES == 24 || Self::HASH_BYTES == 32);
+        };
+    }
+
+    const TAU: usize = 16;
+    const K: usize = 24;
+    const H: usize = 5;
+    const D: usize = 1;
+    const C: usize = 10;
+    const OID: &'static [u32] = &[1, 3, 6, 1, 4, 1, 99999, 1, 7];
+
+    type HashFunction = Truncated<Haraka, 16>;
+    type Prng = AesCtrPrng;
+    type PorsKArray<T: Copy> = [T; Self::PORS_K];
+    type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+    type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+    type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+    type GravityDArray<T> = [T; Self::GRAVITY_D];
+    type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
+}
+
+/// Same parameters as [`GravitySmall`], with Haraka truncated to 24 bytes for smaller
+/// signatures, at the NIST security category 3.
+#[derive(Debug, PartialEq)]
+pub struct GravitySmall192;
+
+impl GravityParams for GravitySmall192 {
+    #[cfg(test)]
+    fn config_type() -> ConfigType {
+        ConfigType::S192
+    }
+
+    fn check_params() {
+        // TODO: Move this implementation to the trait when supported.
+        const {
+            assert!(Self::PORS_K > 0);
+            assert!(Self::PORS_K <= Self::PORS_T);
+            assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+            assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
+            assert!(Self::GRAVITY_C + Self::MERKLE_H * Self::GRAVITY_D <= 64);
+            assert!(Self::WOTS_W == 4 || Self::WOTS_W == 16 || Self::WOTS_W == 256);
+            assert!(Self::HASH_BYTES == 16 || Self::HASH_BYTES == 24 || Self::HASH_BYTES == 32);
+        };
+    }
+
+    const TAU: usize = 16;
+    const K: usize = 24;
+    const H: usize = 5;
+    const D: usize = 1;
+    const C: usize = 10;
+    const OID: &'static [u32] = &[1, 3, 6, 1, 4, 1, 99999, 1, 8];
+
+    type HashFunction = Truncated<Haraka, 24>;
+    type Prng = AesCtrPrng;
+    type PorsKArray<T: Copy> = [T; Self::PORS_K];
+    type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+    type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+    type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+    type GravityDArray<T> = [T; Self::GRAVITY_D];
+    type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
 }
 
 /* Don't modify */
-pub const HASH_SIZE: usize = 32; // Only implemented for 32
+// Size of the `Hash` storage. Smaller hashes are padded with zeros.
+pub const HASH_SIZE: usize = 32;
 
-pub const WOTS_W: usize = 16; // Only implemented for 16
-pub const WOTS_LOG_ELL1: usize = 6; // Implicitly depends on HASH_SIZE and W
-pub const WOTS_ELL1: usize = 1 << WOTS_LOG_ELL1;
-pub const WOTS_CHKSUM: usize = 3; // Implicitly depends on W and ELL1
-pub const WOTS_ELL: usize = WOTS_ELL1 + WOTS_CHKSUM;
+// Variants of GravitySmall, to test other choices of Winternitz parameter and of PRNG.
+#[cfg(test)]
+macro_rules! small_variant {
+    ( $name:ident, $config:ident, $w:expr, $prng:ty ) => {
+        #[derive(Debug, PartialEq)]
+        pub struct $name;
+
+        impl GravityParams for $name {
+            fn config_type() -> ConfigType {
+                ConfigType::$config
+            }
+
+            fn check_params() {
+                // TODO: Move this implementation to the trait when supported.
+                const {
+                    assert!(Self::PORS_K > 0);
+                    assert!(Self::PORS_K <= Self::PORS_T);
+                    assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+                    assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
+                    assert!(Self::GRAVITY_C + Self::MERKLE_H * Self::GRAVITY_D <= 64);
+                    assert!(Self::WOTS_W == 4 || Self::WOTS_W == 16 || Self::WOTS_W == 256);
+                    assert!(
+                        Self::HASH_BYTES == 16 || Self::HASH_BYTES == 24 || Self::HASH_BYTES == 32
+                    );
+                };
+            }
+
+            const TAU: usize = GravitySmall::TAU;
+            const K: usize = GravitySmall::K;
+            const H: usize = GravitySmall::H;
+            const D: usize = GravitySmall::D;
+            const C: usize = GravitySmall::C;
+            const OID: &'static [u32] = &[];
+            const W: usize = $w;
+
+            type HashFunction = Haraka;
+            type Prng = $prng;
+            type PorsKArray<T: Copy> = [T; Self::PORS_K];
+            type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+            type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+            type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+            type GravityDArray<T> = [T; Self::GRAVITY_D];
+            type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
+        }
+    };
+}
+
+#[cfg(test)]
+small_variant!(SmallW4, SW4, 4, AesCtrPrng);
+#[cfg(test)]
+small_variant!(SmallW256, SW256, 256, AesCtrPrng);
+#[cfg(test)]
+small_variant!(SmallChaCha20, Unknown, 16, ChaCha20Prng);
+#[cfg(test)]
+small_variant!(SmallShakePrng, Unknown, 16, ShakePrng);
 
 #[cfg(test)]
 #[derive(Debug, PartialEq)]
@@ -120,6 +482,13 @@ pub enum ConfigType {
     S,
     M,
     L,
+    ST,
+    SSha2,
+    SShake,
+    S128,
+    S192,
+    SW4,
+    SW256,
     Unknown,
 }
 
@@ -130,19 +499,44 @@ mod tests {
     #[test]
     fn test_fixed() {
         assert_eq!(HASH_SIZE, 32);
-        assert_eq!(WOTS_W, 16);
     }
 
     fn is_power_of_two(x: usize) -> bool {
         1 << x.trailing_zeros() == x
     }
 
+    fn test_winternitz<P: GravityParams>() {
+        assert!(is_power_of_two(P::WOTS_W));
+        assert_eq!(P::WOTS_ELL1 * P::WOTS_LOG_W, P::HASH_BYTES * 8);
+        // The checksum fits in WOTS_CHKSUM digits, but not in one less.
+        let max_checksum = P::WOTS_ELL1 * (P::WOTS_W - 1);
+        assert!(max_checksum < 1 << (P::WOTS_CHKSUM * P::WOTS_LOG_W));
+        assert!(max_checksum >= 1 << ((P::WOTS_CHKSUM - 1) * P::WOTS_LOG_W));
+    }
+
     #[test]
-    fn test_winternitz() {
-        assert!(is_power_of_two(WOTS_W));
-        assert_eq!(
-            WOTS_ELL1 * (WOTS_W.trailing_zeros() as usize),
-            HASH_SIZE * 8
-        );
+    fn test_wots_lengths() {
+        assert_eq!(GravitySmall::WOTS_ELL1, 64);
+        assert_eq!(GravitySmall::WOTS_CHKSUM, 3);
+        assert_eq!(SmallW4::WOTS_ELL1, 128);
+        assert_eq!(SmallW4::WOTS_CHKSUM, 5);
+        assert_eq!(SmallW256::WOTS_ELL1, 32);
+        assert_eq!(SmallW256::WOTS_CHKSUM, 2);
+        assert_eq!(GravitySmall128::WOTS_ELL1, 32);
+        assert_eq!(GravitySmall128::WOTS_CHKSUM, 3);
+        assert_eq!(GravitySmall192::WOTS_ELL1, 48);
+        assert_eq!(GravitySmall192::WOTS_CHKSUM, 3);
     }
+
+    macro_rules! all_tests {
+        ( $mod:ident, $params:ty ) => {
+            crate::tests::param_tests!($mod, $params, test_winternitz,);
+        };
+    }
+
+    all_tests!(small, GravitySmall);
+    all_tests!(small_w4, SmallW4);
+    all_tests!(small_w256, SmallW256);
+    all_tests!(small_128, GravitySmall128);
+    all_tests!(small_192, GravitySmall192);
 }
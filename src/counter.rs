@@ -0,0 +1,316 @@
+use crate::config::GravityParams;
+#[cfg(feature = "alloc")]
+use crate::gravity::{SecKey, Signature};
+#[cfg(feature = "alloc")]
+use crate::hash::{Hash, HashFunction};
+use core::f64::consts::{LN_2, LOG2_E};
+
+/// Estimates the security left in bits against forgeries, after `count` messages have been
+/// signed by a key.
+///
+/// Each signature uses a PORS key at a uniformly random leaf among `2^(GRAVITY_C + MERKLE_H *
+/// GRAVITY_D)`, and reveals `PORS_K` of its `PORS_T` secret values. A forgery at a leaf used `r`
+/// times succeeds if the `PORS_K` values it needs were all revealed, which happens with
+/// probability at most `(r * PORS_K / PORS_T)^PORS_K`. The result is minus the logarithm of this
+/// probability averaged over the leaves, for one attempt of the attacker.
+pub fn security_bits<P: GravityParams>(count: u64) -> f64 {
+    estimate_bits(P::PORS_K, P::PORS_T, P::GRAVITY_C + P::GRAVITY_HD, count)
+}
+
+fn estimate_bits(k: usize, t: usize, h: usize, count: u64) -> f64 {
+    if count == 0 {
+        return f64::INFINITY;
+    }
+
+    // The number of signatures at a leaf follows a Poisson distribution of mean `lambda`. The
+    // probabilities are computed in the log domain, as they underflow for large `lambda`.
+    let lambda = count as f64 * exp2(-(h as f64));
+    let log2_lambda = log2(lambda);
+    let mut log2_p = -lambda * LOG2_E;
+    let mut below = exp2(log2_p);
+    let mut forgery = 0.0;
+
+    // From `r0` signatures on, the revealed values may cover any subset.
+    let r0 = t.div_ceil(k) as u64;
+    let mut r = 0;
+    while r + 1 < r0 {
+        r += 1;
+        log2_p += log2_lambda - log2(r as f64);
+        let revealed = (r as usize * k) as f64 / t as f64;
+        forgery += exp2(log2_p + k as f64 * log2(revealed));
+        below += exp2(log2_p);
+    }
+
+    if lambda >= r0 as f64 {
+        forgery += (1.0 - below).max(0.0);
+    } else {
+        // Past the mean, the terms decrease quickly.
+        loop {
+            r += 1;
+            log2_p += log2_lambda - log2(r as f64);
+            let p = exp2(log2_p);
+            forgery += p;
+            if p <= forgery * exp2(-64.0) {
+                break;
+            }
+        }
+    }
+
+    if forgery == 0.0 {
+        f64::INFINITY
+    } else {
+        (-log2(forgery.min(1.0))).max(0.0)
+    }
+}
+
+// The floating-point functions of `std` are not available in `core`, so these are computed with
+// series, which are precise enough for an estimate.
+fn log2(x: f64) -> f64 {
+    debug_assert!(x > 0.0);
+    if x < f64::MIN_POSITIVE {
+        return log2(x * exp2(64.0)) - 64.0;
+    }
+    let bits = x.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i64 - 1023;
+    let mantissa = f64::from_bits((bits & ((1 << 52) - 1)) | (1023 << 52));
+
+    // ln(m) = 2 * atanh(z) with z = (m - 1) / (m + 1) <= 1/3.
+    let z = (mantissa - 1.0) / (mantissa + 1.0);
+    let mut term = z;
+    let mut sum = 0.0;
+    for i in 0..20 {
+        sum += term / (2 * i + 1) as f64;
+        term *= z * z;
+    }
+    exponent as f64 + 2.0 * sum * LOG2_E
+}
+
+fn exp2(x: f64) -> f64 {
+    if x < -1100.0 {
+        return 0.0;
+    }
+    if x >= 1024.0 {
+        return f64::INFINITY;
+    }
+    let mut n = x as i64;
+    if n as f64 > x {
+        n -= 1;
+    }
+
+    // e^y for y = (x - n) * ln(2) in [0, ln(2)).
+    let y = (x - n as f64) * LN_2;
+    let mut term = 1.0;
+    let mut result = 1.0;
+    for i in 1..20 {
+        term *= y / i as f64;
+        result += term;
+    }
+
+    // Scale by 2^n in steps, to reach subnormal numbers.
+    while n < -1000 {
+        result *= f64::from_bits(23 << 52);
+        n += 1000;
+    }
+    result * f64::from_bits(((n + 1023) as u64) << 52)
+}
+
+/// Persistent count of the signatures made by a key.
+pub trait SignatureCounter {
+    fn count(&self) -> u64;
+
+    /// Persists a count one higher, before a signature is released. Returns `false` if this
+    /// failed, in which case nothing is signed.
+    fn increment(&mut self) -> bool;
+}
+
+/// A counter kept in memory only.
+impl SignatureCounter for u64 {
+    fn count(&self) -> u64 {
+        *self
+    }
+
+    fn increment(&mut self) -> bool {
+        *self += 1;
+        true
+    }
+}
+
+/// Secret key that counts its signatures, and checks the security left after each of them with
+/// [`security_bits`]. Past a warning threshold, a callback is notified of each signature, and
+/// past a refusal threshold, nothing is signed anymore.
+#[cfg(feature = "alloc")]
+pub struct CountingSecKey<P: GravityParams, C: SignatureCounter> {
+    sk: SecKey<P>,
+    counter: C,
+    refuse_bits: f64,
+    warn_bits: f64,
+    warn: Option<fn(u64, f64)>,
+}
+
+#[cfg(feature = "alloc")]
+impl<P: GravityParams, C: SignatureCounter> CountingSecKey<P, C> {
+    /// Counts the signatures of `sk` in `counter`, without any threshold.
+    pub fn new(sk: SecKey<P>, counter: C) -> Self {
+        Self {
+            sk,
+            counter,
+            refuse_bits: 0.0,
+            warn_bits: 0.0,
+            warn: None,
+        }
+    }
+
+    /// Refuses to sign if the security left afterwards would be below `bits`.
+    pub fn refuse_below(self, bits: f64) -> Self {
+        Self {
+            refuse_bits: bits,
+            ..self
+        }
+    }
+
+    /// Calls `warn` with the count and the security left after each signature that leaves less
+    /// than `bits`.
+    pub fn warn_below(self, bits: f64, warn: fn(u64, f64)) -> Self {
+        Self {
+            warn_bits: bits,
+            warn: Some(warn),
+            ..self
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counter.count()
+    }
+
+    /// Security left after the signatures made so far.
+    pub fn security_bits(&self) -> f64 {
+        security_bits::<P>(self.counter.count())
+    }
+
+    /// Signs, or returns `None` if this is refused or if the count could not be persisted.
+    pub fn sign_hash(&mut self, msg: &Hash) -> Option<Signature<P>> {
+        let count = self.counter.count().checked_add(1)?;
+        let bits = security_bits::<P>(count);
+        if bits < self.refuse_bits || !self.counter.increment() {
+            return None;
+        }
+        if bits < self.warn_bits {
+            if let Some(warn) = self.warn {
+                warn(count, bits);
+            }
+        }
+        Some(self.sk.sign_hash(msg))
+    }
+
+    pub fn sign_bytes(&mut self, msg: &[u8]) -> Option<Signature<P>> {
+        let h = P::HashFunction::long_hash(msg);
+        self.sign_hash(&h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GravityLarge, GravityMedium, GravitySmall};
+
+    fn assert_close(x: f64, y: f64) {
+        assert!((x - y).abs() < 1e-9, "{x} != {y}");
+    }
+
+    #[test]
+    fn test_log2_exp2() {
+        assert_eq!(log2(1.0), 0.0);
+        assert_eq!(log2(1024.0), 10.0);
+        assert_close(log2(3.0), 1.584962500721156);
+        assert_close(log2(1e-310), -1029.7977094150824);
+        assert_eq!(exp2(0.0), 1.0);
+        assert_eq!(exp2(-3.0), 0.125);
+        assert_close(exp2(0.5), core::f64::consts::SQRT_2);
+        assert_eq!(exp2(-1074.0), f64::from_bits(1));
+        assert_eq!(exp2(-1200.0), 0.0);
+    }
+
+    #[test]
+    fn test_estimate_bits() {
+        // A single leaf with 2 values, one of which is revealed per signature. After one
+        // signature on average, a forgery succeeds with probability
+        // P[r = 1] / 2 + P[r >= 2] = 1 - 3 / (2e).
+        let p: f64 = 1.0 - 1.5 / core::f64::consts::E;
+        assert_close(estimate_bits(1, 2, 0, 1), -log2(p));
+
+        assert_eq!(estimate_bits(1, 2, 0, 0), f64::INFINITY);
+        assert_eq!(estimate_bits(1, 2, 0, 1 << 20), 0.0);
+    }
+
+    #[test]
+    fn test_security_bits() {
+        for (count, bits) in [(1 << 10, 248), (1 << 20, 144), (1 << 25, 33), (1 << 30, 0)] {
+            assert_eq!(security_bits::<GravitySmall>(count) as u64, bits);
+        }
+        assert!(security_bits::<GravityMedium>(1 << 50) > 128.0);
+        assert!(security_bits::<GravityLarge>(1 << 63) > 128.0);
+
+        // The security decreases with the count.
+        let mut last = f64::INFINITY;
+        for i in 0..64 {
+            let bits = security_bits::<GravitySmall>(1 << i);
+            assert!(bits <= last);
+            last = bits;
+        }
+    }
+
+    #[test]
+    fn test_counting_sign() {
+        type P = GravitySmall;
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let pk = SecKey::<P>::new(&random).genpk();
+        let mut sk = CountingSecKey::new(SecKey::<P>::new(&random), 0u64);
+
+        let sign = sk.sign_bytes(b"Hello world").unwrap();
+        assert!(pk.verify_bytes(&sign, b"Hello world"));
+        assert_eq!(sk.count(), 1);
+        assert_eq!(sk.security_bits(), security_bits::<P>(1));
+
+        // Refused signatures are not counted.
+        let mut sk = sk.refuse_below(security_bits::<P>(2));
+        assert!(sk.sign_bytes(b"").is_some());
+        assert!(sk.sign_bytes(b"").is_none());
+        assert_eq!(sk.count(), 2);
+    }
+
+    #[test]
+    fn test_counting_warn() {
+        use core::sync::atomic::{AtomicU64, Ordering};
+        static WARNED: AtomicU64 = AtomicU64::new(0);
+
+        type P = GravitySmall;
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let bits = (security_bits::<P>(2) + security_bits::<P>(3)) / 2.0;
+        let mut sk = CountingSecKey::new(SecKey::<P>::new(&random), 1u64)
+            .warn_below(bits, |count, _| WARNED.store(count, Ordering::Relaxed));
+
+        sk.sign_bytes(b"").unwrap();
+        assert_eq!(WARNED.load(Ordering::Relaxed), 0);
+        sk.sign_bytes(b"").unwrap();
+        assert_eq!(WARNED.load(Ordering::Relaxed), 3);
+    }
+
+    struct FailingCounter;
+
+    impl SignatureCounter for FailingCounter {
+        fn count(&self) -> u64 {
+            0
+        }
+
+        fn increment(&mut self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_counter_failure() {
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let mut sk = CountingSecKey::new(SecKey::<GravitySmall>::new(&random), FailingCounter);
+        assert!(sk.sign_bytes(b"").is_none());
+    }
+}
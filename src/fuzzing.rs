@@ -0,0 +1,144 @@
+//! Entry points of the fuzzing harnesses in the `fuzz` directory. They run the parsers and
+//! verifiers of the octopus on arbitrary inputs, and panic if one of their invariants is broken.
+//! They are only built with the `fuzzing` feature, and are not part of the stable API.
+
+use crate::config::{GravityParams, GravitySmall};
+use crate::hash::{Hash, HashFunction, Sha2, Tweak};
+use crate::merkle;
+use crate::octopus::{self, Octopus};
+use alloc::vec::Vec;
+
+/// Deserializes an octopus of [`GravitySmall`] from `data`, and checks that the parser of
+/// serialized signatures accepts the same bytes, with the same nodes, and that the octopus
+/// serializes back to these bytes.
+pub fn octopus_deserialize(data: &[u8]) {
+    type P = GravitySmall;
+    let len = P::PORS_OCTOPUS_CAPACITY * P::HASH_BYTES + 16;
+
+    let octopus = Octopus::<P>::deserialize(&mut data.iter());
+    let nodes = data.get(..len).and_then(octopus::serialized_nodes::<P>);
+    assert_eq!(octopus.is_some(), nodes.is_some());
+
+    if let (Some(octopus), Some(nodes)) = (octopus, nodes) {
+        assert!(octopus.nodes().len() <= P::PORS_OCTOPUS_CAPACITY);
+        let nodes = nodes.chunks_exact(P::HASH_BYTES).map(Hash::from_slice);
+        assert!(nodes.eq(octopus.nodes().iter().copied()));
+
+        let mut bytes = Vec::new();
+        octopus.serialize(&mut bytes);
+        assert_eq!(bytes, data[..len]);
+    }
+}
+
+/// Builds a tree of up to 256 leaves from `data`, with the octopus of the leaves at the indices
+/// given by `data`, and checks that [`octopus::merkle_compress_octopus`] returns its root if and
+/// only if the indices are valid. The remaining bytes of `data` then modify the octopus, which
+/// must not give the root anymore.
+pub fn merkle_compress_octopus(data: &[u8]) {
+    let [n, k, rest @ ..] = data else {
+        return;
+    };
+    let leaf_count = *n as usize + 1;
+    let (indices, rest) = rest.split_at((*k as usize).min(rest.len()));
+    let indices: Vec<usize> = indices.iter().map(|x| *x as usize).collect();
+
+    let tweak = Tweak::<Sha2>::plain();
+    let leaves: Vec<Hash> = (0..leaf_count as u32)
+        .map(|i| Sha2::long_hash(&i.to_be_bytes()))
+        .collect();
+    let mut tree = merkle::MerkleTree::with_leaf_count(leaf_count);
+    tree.leaves().copy_from_slice(&leaves);
+    tree.generate(&tweak);
+    let root = tree.root();
+
+    // The octopus of the valid indices, sorted and deduplicated.
+    let mut sorted: Vec<usize> = indices
+        .iter()
+        .copied()
+        .filter(|i| *i < leaf_count)
+        .collect();
+    sorted.sort_unstable();
+    sorted.dedup();
+    let mut buf = merkle::MerkleBuf::with_leaf_count(leaf_count);
+    buf.slice_leaves_mut().copy_from_slice(&leaves);
+    let mut oct = Vec::new();
+    let gen_root =
+        octopus::merkle_gen_octopus_with(&tweak, &mut buf, &mut sorted.clone(), |node| {
+            oct.push(node)
+        });
+    assert_eq!(gen_root, (!sorted.is_empty()).then_some(root));
+    assert_eq!(oct.len(), octopus::octopus_len(&sorted, leaf_count));
+
+    let compress = |oct: &[Hash]| {
+        let mut nodes: Vec<Hash> = indices
+            .iter()
+            .map(|i| leaves.get(*i).copied().unwrap_or_default())
+            .collect();
+        octopus::merkle_compress_octopus(
+            &tweak,
+            &mut nodes,
+            oct.iter().copied(),
+            leaf_count,
+            &mut indices.clone(),
+        )
+    };
+    let valid = octopus::valid_indices(&indices, leaf_count);
+    assert_eq!(compress(&oct), valid.then_some(root));
+
+    // Remove, insert or modify a node.
+    let [op, pos, x, ..] = rest else {
+        return;
+    };
+    let pos = *pos as usize;
+    match op % 3 {
+        0 if !oct.is_empty() => {
+            oct.remove(pos % oct.len());
+        }
+        1 => oct.insert(pos % (oct.len() + 1), leaves[*x as usize % leaf_count]),
+        2 if !oct.is_empty() => {
+            let i = pos % oct.len();
+            oct[i].h[*x as usize % Sha2::OUTPUT_BYTES] ^= 1;
+        }
+        _ => return,
+    }
+    assert_ne!(compress(&oct), Some(root));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs the harnesses on a few inputs, as the fuzzer isn't run with the other tests.
+    #[test]
+    fn test_octopus_deserialize() {
+        type P = GravitySmall;
+        let len = P::PORS_OCTOPUS_CAPACITY * P::HASH_BYTES + 16;
+        let node = |i: u32| Sha2::long_hash(&i.to_be_bytes());
+        let octopus = Octopus::<P>::from_slice(&[node(0), node(1), node(2)]);
+        let mut bytes = Vec::new();
+        octopus.serialize(&mut bytes);
+
+        octopus_deserialize(&[]);
+        octopus_deserialize(&bytes);
+        octopus_deserialize(&bytes[..len - 1]);
+        for i in [0, 3 * P::HASH_BYTES, len - 16, len - 13, len - 12, len - 1] {
+            let mut invalid = bytes.clone();
+            invalid[i] ^= 0xff;
+            octopus_deserialize(&invalid);
+        }
+    }
+
+    #[test]
+    fn test_merkle_compress_octopus() {
+        merkle_compress_octopus(&[]);
+        merkle_compress_octopus(&[7, 3, 1, 4, 6]);
+        merkle_compress_octopus(&[6, 2, 6, 7]);
+        merkle_compress_octopus(&[255, 4, 200, 100, 50, 0]);
+        for op in 0..3 {
+            for pos in 0..8 {
+                merkle_compress_octopus(&[9, 3, 2, 3, 8, op, pos, 5]);
+                merkle_compress_octopus(&[9, 2, 3, 2, op, pos, 5]);
+            }
+        }
+    }
+}
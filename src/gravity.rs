@@ -1,19 +1,32 @@
 use crate::address;
-use crate::config::{self, GravityParams};
+use crate::array::Array;
+use crate::config::GravityParams;
 use crate::hash;
-use crate::hash::Hash;
+use crate::hash::{Hash, HashFunction, Tweak, TweakKind};
 use crate::merkle;
 use crate::pors;
-use crate::prng;
+#[cfg(feature = "alloc")]
+use crate::prng::SecretPrng;
 use crate::subtree;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
 use arrayref::array_ref;
-use std::array;
-use std::marker::PhantomData;
+#[cfg(feature = "alloc")]
+use byteorder::{BigEndian, ByteOrder};
+use core::marker::PhantomData;
+use subtle::{Choice, ConstantTimeEq};
 
+/// Size of a [`PubKey::fingerprint`], in bytes.
+pub const FINGERPRINT_BYTES: usize = 16;
+
+#[cfg(feature = "alloc")]
 pub struct SecKey<P: GravityParams> {
     seed: Hash,
     salt: Hash,
+    pub_seed: Option<Hash>,
     cache: merkle::MerkleTree,
+    subtree_cache: Option<subtree::Cache<P>>,
+    stream_pors: bool,
     _phantom: PhantomData<P>,
 }
 
@@ -22,64 +35,133 @@ pub struct PubKey<P: GravityParams> {
     _phantom: PhantomData<P>,
 }
 
-pub struct Signature<P: GravityParams>
-where
-    [(); P::GRAVITY_D]:,
-    [(); P::GRAVITY_C]:,
-    [(); P::MERKLE_H]:,
-    [(); P::PORS_K]:,
-{
+pub struct Signature<P: GravityParams> {
+    pub_seed: Option<Hash>,
     pors_sign: pors::Signature<P>,
-    subtrees: [subtree::Signature<P>; P::GRAVITY_D],
-    auth_c: [Hash; P::GRAVITY_C],
+    subtrees: P::GravityDArray<subtree::Signature<P>>,
+    auth_c: P::GravityCArray<Hash>,
 }
 
+#[cfg(feature = "alloc")]
 impl<P: GravityParams> SecKey<P> {
     pub fn new(random: &[u8; 64]) -> Self {
-        let mut sk = SecKey {
-            seed: Hash {
-                h: *array_ref![random, 0, 32],
-            },
+        let mut builder = SecKeyBuilder::new(random);
+        builder.step(builder.total());
+        builder.finish().unwrap()
+    }
+
+    // Restore a secret key from its seed and previously computed cache leaves, without
+    // regenerating the subtrees. The leaves are rejected unless they yield the public key `pk`,
+    // as a wrong leaf would otherwise go unnoticed until verifiers reject the signatures.
+    pub(crate) fn from_cache(random: &[u8; 64], leaves: &[Hash], pk: &Hash) -> Option<Self> {
+        let mut sk = Self::empty(random);
+        if leaves.len() != sk.cache.leaves().len() {
+            return None;
+        }
+        sk.cache.leaves().copy_from_slice(leaves);
+        sk.cache.generate(&cache_tweak::<P>(sk.pub_seed.as_ref()));
+        if sk.genpk().h != *pk {
+            return None;
+        }
+        Some(sk)
+    }
+
+    fn empty(random: &[u8; 64]) -> Self {
+        let seed = Hash {
+            h: *array_ref![random, 0, 32],
+        };
+        // The public seed is derived from the secret seed at an address unused by the subtrees.
+        let pub_seed = P::TWEAKED.then(|| {
+            let mut pub_seed = Default::default();
+            let prng = P::Prng::new(&seed);
+            prng.genblock(&mut pub_seed, &address::Address::new(u32::MAX, 0), 0);
+            P::HashFunction::truncate(&mut pub_seed);
+            pub_seed
+        });
+        SecKey {
+            seed,
             salt: Hash {
                 h: *array_ref![random, 32, 32],
             },
+            pub_seed,
             cache: merkle::MerkleTree::new(P::GRAVITY_C),
+            subtree_cache: None,
+            stream_pors: false,
             _phantom: PhantomData,
-        };
+        }
+    }
 
-        let layer = 0u32;
-        let prng = prng::Prng::new(&sk.seed);
-        let subtree_sk = subtree::SecKey::<'_, P>::new(&prng);
+    pub fn get_bytes(&self) -> [u8; 64] {
+        let mut random = [0; 64];
+        random[..32].copy_from_slice(&self.seed.h);
+        random[32..].copy_from_slice(&self.salt.h);
+        random
+    }
 
-        for (i, leaf) in sk.cache.leaves().iter_mut().enumerate() {
-            let address = address::Address::new(layer, (i << P::MERKLE_H) as u64);
-            let pk = subtree_sk.genpk(&address);
-            *leaf = pk.h;
-        }
+    /// Caches the Merkle trees of all the subtrees in as many top layers of the hypertree as fit
+    /// in `memory_budget` bytes, and returns the number of cached layers. Signing then reads the
+    /// authentication paths of these layers instead of regenerating their subtrees, which saves
+    /// most of the signing time when all the layers are cached. Each layer has `2^MERKLE_H`
+    /// times more subtrees than the previous one, so the cache takes a while to build.
+    pub fn cache_subtrees(&mut self, memory_budget: usize) -> usize {
+        let layers = subtree::Cache::<P>::layers_within(memory_budget);
+        let prng = P::Prng::new(&self.seed);
+        let subtree_sk = subtree::SecKey::<P>::new(&prng, self.pub_seed.as_ref());
+        self.subtree_cache = Some(subtree::Cache::new(&subtree_sk, layers));
+        layers
+    }
 
-        sk.cache.generate();
-        sk
+    /// Generates the PORS tree on the fly when signing, so that its `PORS_T` secret values
+    /// and nodes are never stored. This saves several MiB per signature, but signing is about a
+    /// third slower. Signatures are unchanged.
+    pub fn stream_pors(&mut self, enable: bool) {
+        self.stream_pors = enable;
+    }
+
+    pub(crate) fn cache_leaves(&self) -> &[Hash] {
+        self.cache.get_leaves()
     }
 
     pub fn genpk(&self) -> PubKey<P> {
         PubKey {
-            h: self.cache.root(),
+            h: commit_root::<P>(self.pub_seed.as_ref(), &self.cache.root()),
             _phantom: PhantomData,
         }
     }
 
-    pub fn sign_hash(&self, msg: &Hash) -> Signature<P>
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        let prng = prng::Prng::new(&self.seed);
-        let (mut address, mut h, pors_sign) = pors::sign(&prng, &self.salt, msg);
+    pub fn sign_hash(&self, msg: &Hash) -> Signature<P> {
+        let prng = P::Prng::new(&self.seed);
+        let pub_seed = self.pub_seed.as_ref();
+        let (address, h, pors_sign) = if self.stream_pors {
+            pors::sign_streaming(&prng, pub_seed, &self.salt, msg)
+        } else {
+            pors::sign(&prng, pub_seed, &self.salt, msg)
+        };
+
+        let (subtrees, auth_c) = self.sign_hypertree(&prng, address, h);
 
-        let subtree_sk = subtree::SecKey::<P>::new(&prng);
-        let subtrees = array::from_fn(|_| {
+        Signature {
+            pub_seed: self.pub_seed,
+            pors_sign,
+            subtrees,
+            auth_c,
+        }
+    }
+
+    // Signs `h`, the root of the few-time or one-time signature at `address` on the bottom layer,
+    // with the subtrees of the hypertree and the authentication path in the cache.
+    pub(crate) fn sign_hypertree(
+        &self,
+        prng: &P::Prng,
+        mut address: address::Address,
+        mut h: Hash,
+    ) -> (
+        P::GravityDArray<subtree::Signature<P>>,
+        P::GravityCArray<Hash>,
+    ) {
+        let subtree_sk = subtree::SecKey::<P>::new(prng, self.pub_seed.as_ref())
+            .with_cache(self.subtree_cache.as_ref());
+        let subtrees = Array::from_fn(|_| {
             address.next_layer();
             let (root, subtree_sign) = subtree_sk.sign(&address, &h);
             h = root;
@@ -90,122 +172,364 @@ impl<P: GravityParams> SecKey<P> {
         // For compatibility with 32-bit architectures, the index must fit in 32 bits here.
         let index: u64 = address.get_instance();
         debug_assert!(index <= u32::MAX as u64);
-        let mut auth_c = [Default::default(); P::GRAVITY_C];
-        self.cache.gen_auth(&mut auth_c, index as usize);
+        let mut auth_c: P::GravityCArray<Hash> = Array::from_fn(|_| Default::default());
+        self.cache.gen_auth(auth_c.as_mut(), index as usize);
+        (subtrees, auth_c)
+    }
 
-        Signature {
-            pors_sign,
-            subtrees,
-            auth_c,
-        }
+    pub(crate) fn prng(&self) -> P::Prng {
+        P::Prng::new(&self.seed)
     }
 
-    pub fn sign_bytes(&self, msg: &[u8]) -> Signature<P>
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        let h = hash::long_hash(msg);
+    pub(crate) fn salt(&self) -> &Hash {
+        &self.salt
+    }
+
+    pub(crate) fn pub_seed(&self) -> Option<&Hash> {
+        self.pub_seed.as_ref()
+    }
+
+    pub fn sign_bytes(&self, msg: &[u8]) -> Signature<P> {
+        let h = P::HashFunction::long_hash(msg);
         self.sign_hash(&h)
     }
 }
 
-impl<P: GravityParams> PubKey<P> {
-    pub fn new(h: [u8; config::HASH_SIZE]) -> Self {
+/// Generates a secret key incrementally, by computing the leaves of the cache in chunks. This
+/// allows reporting progress, and saving the leaves computed so far to resume an interrupted
+/// generation later. The resulting key is the same as with [`SecKey::new`].
+#[cfg(feature = "alloc")]
+pub struct SecKeyBuilder<P: GravityParams> {
+    sk: SecKey<P>,
+    done: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl<P: GravityParams> SecKeyBuilder<P> {
+    pub fn new(random: &[u8; 64]) -> Self {
         Self {
-            h: Hash { h },
-            _phantom: PhantomData,
+            sk: SecKey::empty(random),
+            done: 0,
+        }
+    }
+
+    /// Resumes a generation from a checkpoint obtained with [`checkpoint`](Self::checkpoint).
+    /// Returns `None` if the checkpoint is malformed, corrupted, or was made with another key.
+    pub fn resume(random: &[u8; 64], checkpoint: &[u8]) -> Option<Self> {
+        let mut builder = Self::new(random);
+        let (data, tag) =
+            checkpoint.split_at_checked(checkpoint.len().checked_sub(P::HASH_BYTES)?)?;
+        let tag = Hash::deserialize(P::HASH_BYTES, &mut tag.iter())?;
+        if checkpoint_tag::<P>(&builder.sk.seed, data) != tag {
+            return None;
         }
+        let (count, leaves) = data.split_at_checked(8)?;
+        let done = BigEndian::read_u64(count);
+        if done > builder.total() as u64 || leaves.len() as u64 != done * P::HASH_BYTES as u64 {
+            return None;
+        }
+        builder.done = done as usize;
+
+        let mut it = leaves.iter();
+        for leaf in builder.sk.cache.leaves()[..builder.done].iter_mut() {
+            *leaf = Hash::deserialize(P::HASH_BYTES, &mut it)?;
+        }
+        Some(builder)
     }
 
-    pub fn get_bytes(&self) -> [u8; config::HASH_SIZE] {
-        self.h.h
+    /// Number of cache leaves computed so far.
+    pub fn progress(&self) -> usize {
+        self.done
     }
 
-    fn verify_hash(&self, sign: &Signature<P>, msg: &Hash) -> bool
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        if let Some(h) = sign.extract_hash(msg) {
-            self.h == h
-        } else {
-            false
+    /// Total number of cache leaves, each of which is the root of a subtree.
+    pub fn total(&self) -> usize {
+        1 << P::GRAVITY_C
+    }
+
+    /// Computes the next `count` leaves (or fewer, if there are not as many left), and returns
+    /// the number of leaves computed so far.
+    pub fn step(&mut self, count: usize) -> usize {
+        let end = self.done + count.min(self.total() - self.done);
+        let prng = P::Prng::new(&self.sk.seed);
+        let subtree_sk = subtree::SecKey::<'_, P>::new(&prng, self.sk.pub_seed.as_ref());
+
+        let layer = 0u32;
+        for i in self.done..end {
+            let address = address::Address::new(layer, (i << P::MERKLE_H) as u64);
+            self.sk.cache.leaves()[i] = subtree_sk.genpk(&address).h;
         }
+        self.done = end;
+        self.done
     }
 
-    pub fn verify_bytes(&self, sign: &Signature<P>, msg: &[u8]) -> bool
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        let h = hash::long_hash(msg);
+    /// Computes all the remaining leaves by chunks of `chunk` leaves, calling `progress` after
+    /// each chunk. The callback can for example save a [`checkpoint`](Self::checkpoint).
+    pub fn run<F: FnMut(&Self)>(&mut self, chunk: usize, mut progress: F) {
+        assert!(chunk > 0);
+        while self.done < self.total() {
+            self.step(chunk);
+            progress(self);
+        }
+    }
+
+    /// Serializes the leaves computed so far. The secret key is not part of the checkpoint, but
+    /// the checkpoint ends with a tag keyed by it, which [`resume`](Self::resume) verifies.
+    pub fn checkpoint(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut count = [0; 8];
+        BigEndian::write_u64(&mut count, self.done as u64);
+        output.extend(count);
+        for leaf in self.sk.cache.get_leaves()[..self.done].iter() {
+            leaf.serialize(P::HASH_BYTES, &mut output);
+        }
+        checkpoint_tag::<P>(&self.sk.seed, &output).serialize(P::HASH_BYTES, &mut output);
+        output
+    }
+
+    /// Returns the secret key, or `None` if some leaves are still missing.
+    pub fn finish(mut self) -> Option<SecKey<P>> {
+        if self.done != self.total() {
+            return None;
+        }
+        self.sk
+            .cache
+            .generate(&cache_tweak::<P>(self.sk.pub_seed.as_ref()));
+        Some(self.sk)
+    }
+}
+
+impl<P: GravityParams> PubKey<P> {
+    /// Decodes a public key from its `HASH_BYTES` bytes, or returns `None` if `bytes` has
+    /// another length.
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != P::HASH_BYTES {
+            return None;
+        }
+        Some(Self {
+            h: Hash::from_slice(bytes),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Encodes the public key on `HASH_BYTES` bytes, i.e. 16 or 24 bytes with truncated hash
+    /// functions.
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.h.h[..P::HASH_BYTES]
+    }
+
+    /// Short hash of the public key, which identifies it among the keys of the same parameter
+    /// set. A peer that knows only the fingerprint can confirm a key recovered with
+    /// [`recover_public_key`] by comparing their fingerprints.
+    pub fn fingerprint(&self) -> [u8; FINGERPRINT_BYTES] {
+        let h = P::HashFunction::long_hash(&self.h.h[..P::HASH_BYTES]);
+        *array_ref![h.h, 0, FINGERPRINT_BYTES]
+    }
+
+    pub(crate) fn verify_hash(&self, sign: &Signature<P>, msg: &Hash) -> bool {
+        self.ct_verify_hash(sign, msg).into()
+    }
+
+    // A malformed signature is rejected early, which only depends on the public signature.
+    fn ct_verify_hash(&self, sign: &Signature<P>, msg: &Hash) -> Choice {
+        match sign.extract_hash(msg) {
+            Some(h) => self.h.ct_eq(&h),
+            None => Choice::from(0),
+        }
+    }
+
+    pub fn verify_bytes(&self, sign: &Signature<P>, msg: &[u8]) -> bool {
+        let h = P::HashFunction::long_hash(msg);
         self.verify_hash(sign, &h)
     }
+
+    /// Same as [`verify_bytes`](Self::verify_bytes), but returns a [`Choice`] for callers that
+    /// combine the result with other checks in constant time.
+    pub fn ct_verify_bytes(&self, sign: &Signature<P>, msg: &[u8]) -> Choice {
+        let h = P::HashFunction::long_hash(msg);
+        self.ct_verify_hash(sign, &h)
+    }
+
+    /// Verifies a serialized signature directly, without deserializing it into a [`Signature`].
+    ///
+    /// This never allocates, and only keeps one layer of the signature on the stack at a time,
+    /// which makes it suitable for microcontrollers. The stack usage depends on the parameters
+    /// but not on the signature, and stays below 16 KiB for the provided parameter sets. It is
+    /// below 32 KiB with `W = 4`, because WOTS signatures are twice as long.
+    pub fn verify_serialized(&self, sign_bytes: &[u8], msg: &[u8]) -> bool {
+        let h = P::HashFunction::long_hash(msg);
+        match extract_hash_serialized::<P>(sign_bytes, &h) {
+            Some(h) => self.h.ct_eq(&h).into(),
+            None => false,
+        }
+    }
 }
 
-impl<P: GravityParams> Signature<P>
-where
-    [(); P::GRAVITY_D]:,
-    [(); P::GRAVITY_C]:,
-    [(); P::MERKLE_H]:,
-    [(); P::PORS_K]:,
-{
-    fn extract_hash(&self, msg: &Hash) -> Option<Hash> {
-        if let Some((mut address, mut h)) = self.pors_sign.extract(msg) {
-            for i in 0..P::GRAVITY_D {
-                address.next_layer();
-                h = self.subtrees[i].extract(&address, &h);
-                address.shift(P::MERKLE_H);
-            }
+/// Recovers the public key under which `sign` is a valid signature of `msg`. Any well-formed
+/// signature yields a key, so the result must be compared to a trusted key or fingerprint before
+/// it is used. Returns `None` if the signature is malformed.
+pub fn recover_public_key<P: GravityParams>(msg: &[u8], sign: &Signature<P>) -> Option<PubKey<P>> {
+    let h = P::HashFunction::long_hash(msg);
+    let h = sign.extract_hash(&h)?;
+    Some(PubKey {
+        h,
+        _phantom: PhantomData,
+    })
+}
 
-            // For compatibility with 32-bit architectures, the index must fit in 32 bits here.
-            let index: u64 = address.get_instance();
-            debug_assert!(index <= u32::MAX as u64);
-            merkle::merkle_compress_auth(&mut h, &self.auth_c, P::GRAVITY_C, index as usize);
-            Some(h)
-        } else {
-            None
+// In tweaked mode, the public key is a commitment to the public seed and the root, so that the
+// public seed can be carried by the signatures.
+fn commit_root<P: GravityParams>(seed: Option<&Hash>, root: &Hash) -> Hash {
+    match seed {
+        None => *root,
+        Some(_) => {
+            let address = address::Address::new(0, 0);
+            let tweak = Tweak::<P::HashFunction>::new(seed, &address, TweakKind::PublicKey);
+            hash::tweak_n_to_n_ret(&tweak, root)
         }
     }
+}
+
+fn cache_tweak<P: GravityParams>(seed: Option<&Hash>) -> Tweak<'_, P::HashFunction> {
+    Tweak::new(seed, &address::Address::new(0, 0), TweakKind::CacheNode)
+}
+
+// Authenticates the data of a checkpoint with the secret seed, so that leaves that were corrupted
+// or computed with another key are not silently used. The data starts with its own length, so
+// prefixing the key is enough.
+#[cfg(feature = "alloc")]
+fn checkpoint_tag<P: GravityParams>(seed: &Hash, data: &[u8]) -> Hash {
+    let mut input = Vec::with_capacity(seed.h.len() + data.len());
+    input.extend(seed.h.iter());
+    input.extend(data.iter());
+    P::HashFunction::long_hash(&input)
+}
+
+fn extract_hash_serialized<P: GravityParams>(sign_bytes: &[u8], msg: &Hash) -> Option<Hash> {
+    if sign_bytes.len() != P::SIGNATURE_BYTES {
+        return None;
+    }
+    let mut it = sign_bytes.iter();
+    let pub_seed = if P::TWEAKED {
+        Some(Hash::deserialize(P::HASH_BYTES, &mut it)?)
+    } else {
+        None
+    };
+    let seed = pub_seed.as_ref();
+    let (pors_bytes, mut sign_bytes) = it.as_slice().split_at(P::PORS_SIGNATURE_BYTES);
+    let (mut address, mut h) = pors::extract_serialized::<P>(seed, pors_bytes, msg)?;
+
+    for _ in 0..P::GRAVITY_D {
+        let (subtree_bytes, rest) = sign_bytes.split_at(P::SUBTREE_SIGNATURE_BYTES);
+        sign_bytes = rest;
+        let subtree_sign = subtree::Signature::<P>::deserialize(&mut subtree_bytes.iter())?;
+
+        address.next_layer();
+        h = subtree_sign.extract(seed, &address, &h);
+        address.shift(P::MERKLE_H);
+    }
+
+    let mut it = sign_bytes.iter();
+    let auth_c: P::GravityCArray<Hash> =
+        Array::try_from_fn(|_| Hash::deserialize(P::HASH_BYTES, &mut it))?;
+
+    // For compatibility with 32-bit architectures, the index must fit in 32 bits here.
+    let index: u64 = address.get_instance();
+    debug_assert!(index <= u32::MAX as u64);
+    let tweak = cache_tweak::<P>(seed);
+    merkle::merkle_compress_auth(
+        &tweak,
+        &mut h,
+        auth_c.as_ref(),
+        P::GRAVITY_C,
+        index as usize,
+    );
+    Some(commit_root::<P>(seed, &h))
+}
+
+// Extracts the public key from the root `h` of the few-time or one-time signature at `address`
+// on the bottom layer, and the signatures of the hypertree.
+pub(crate) fn extract_hypertree<P: GravityParams>(
+    seed: Option<&Hash>,
+    subtrees: &P::GravityDArray<subtree::Signature<P>>,
+    auth_c: &P::GravityCArray<Hash>,
+    mut address: address::Address,
+    mut h: Hash,
+) -> Hash {
+    for subtree_sign in subtrees.as_ref().iter() {
+        address.next_layer();
+        h = subtree_sign.extract(seed, &address, &h);
+        address.shift(P::MERKLE_H);
+    }
+
+    // For compatibility with 32-bit architectures, the index must fit in 32 bits here.
+    let index: u64 = address.get_instance();
+    debug_assert!(index <= u32::MAX as u64);
+    merkle::merkle_compress_auth(
+        &cache_tweak::<P>(seed),
+        &mut h,
+        auth_c.as_ref(),
+        P::GRAVITY_C,
+        index as usize,
+    );
+    commit_root::<P>(seed, &h)
+}
+
+impl<P: GravityParams> Signature<P> {
+    #[cfg(feature = "alloc")]
+    pub(crate) fn pub_seed(&self) -> Option<&Hash> {
+        self.pub_seed.as_ref()
+    }
+
+    fn extract_hash(&self, msg: &Hash) -> Option<Hash> {
+        let seed = self.pub_seed.as_ref();
+        let (address, h) = self.pors_sign.extract(seed, msg)?;
+        Some(extract_hypertree::<P>(
+            seed,
+            &self.subtrees,
+            &self.auth_c,
+            address,
+            h,
+        ))
+    }
 
     #[cfg(test)]
     pub fn min_size_bytes() -> usize {
-        Self::min_size_hashes() * config::HASH_SIZE
+        Self::min_size_hashes() * P::HASH_BYTES
     }
 
     #[cfg(test)]
     pub fn max_size_bytes() -> usize {
-        Self::max_size_hashes() * config::HASH_SIZE
+        Self::max_size_hashes() * P::HASH_BYTES
     }
 
     #[cfg(test)]
     pub fn max_size_hashes() -> usize {
-        pors::Signature::<P>::max_size_hashes()
+        P::TWEAKED as usize
+            + pors::Signature::<P>::max_size_hashes()
             + subtree::Signature::<P>::size_hashes() * P::GRAVITY_D
             + P::GRAVITY_C
     }
 
     #[cfg(test)]
     pub fn min_size_hashes() -> usize {
-        pors::Signature::<P>::min_size_hashes()
+        P::TWEAKED as usize
+            + pors::Signature::<P>::min_size_hashes()
             + subtree::Signature::<P>::size_hashes() * P::GRAVITY_D
             + P::GRAVITY_C
     }
 
+    #[cfg(feature = "alloc")]
     pub fn serialize(&self, output: &mut Vec<u8>) {
+        if let Some(pub_seed) = &self.pub_seed {
+            pub_seed.serialize(P::HASH_BYTES, output);
+        }
         self.pors_sign.serialize(output);
-        for t in self.subtrees.iter() {
+        for t in self.subtrees.as_ref().iter() {
             t.serialize(output);
         }
-        for x in self.auth_c.iter() {
-            x.serialize(output);
+        for x in self.auth_c.as_ref().iter() {
+            x.serialize(P::HASH_BYTES, output);
         }
     }
 
@@ -213,11 +537,17 @@ where
     where
         I: Iterator<Item = &'a u8>,
     {
+        let pub_seed = if P::TWEAKED {
+            Some(Hash::deserialize(P::HASH_BYTES, it)?)
+        } else {
+            None
+        };
         let pors_sign = pors::Signature::deserialize(it)?;
-        let subtrees = array::try_from_fn(|_| subtree::Signature::deserialize(it))?;
-        let auth_c = array::try_from_fn(|_| Hash::deserialize(it))?;
+        let subtrees = Array::try_from_fn(|_| subtree::Signature::deserialize(it))?;
+        let auth_c = Array::try_from_fn(|_| Hash::deserialize(P::HASH_BYTES, it))?;
 
         Some(Signature {
+            pub_seed,
             pors_sign,
             subtrees,
             auth_c,
@@ -228,7 +558,10 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{ConfigType, GravityLarge, GravityMedium, GravitySmall};
+    use crate::config::{
+        ConfigType, GravityLarge, GravityMedium, GravitySmall, GravitySmall128, GravitySmall192,
+        GravitySmallSha2, GravitySmallShake, GravitySmallTweaked,
+    };
 
     macro_rules! all_tests {
         ( $mod:ident, $params:ty ) => {
@@ -241,6 +574,7 @@ mod tests {
                 test_sign_zeros,
                 test_genkey_kat,
                 test_sign_kat,
+                test_recover_kat,
             );
         };
     }
@@ -248,58 +582,184 @@ mod tests {
     all_tests!(small, GravitySmall);
     all_tests!(medium, GravityMedium);
     all_tests!(large, GravityLarge);
+    all_tests!(small_tweaked, GravitySmallTweaked);
+    all_tests!(small_sha2, GravitySmallSha2);
+    all_tests!(small_shake, GravitySmallShake);
+    all_tests!(small_128, GravitySmall128);
+    all_tests!(small_192, GravitySmall192);
 
-    fn test_signature_size<P: GravityParams>()
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
+    fn test_signature_size<P: GravityParams>() {
         let (expected_min_hashes, expected_max_hashes) = match P::config_type() {
-            ConfigType::S => (118, 395),
+            ConfigType::S | ConfigType::SSha2 | ConfigType::SShake => (118, 395),
             ConfigType::M => (563, 904),
             ConfigType::L => (774, 1099),
-            ConfigType::Unknown => unimplemented!(),
+            ConfigType::ST => (119, 396),
+            ConfigType::S128 => (86, 363),
+            ConfigType::S192 => (102, 379),
+            ConfigType::SW4 | ConfigType::SW256 | ConfigType::Unknown => unimplemented!(),
         };
         assert_eq!(Signature::<P>::min_size_hashes(), expected_min_hashes);
         assert_eq!(Signature::<P>::max_size_hashes(), expected_max_hashes);
 
         let (expected_min_bytes, expected_max_bytes) = match P::config_type() {
-            ConfigType::S => (3776, 12640),
+            ConfigType::S | ConfigType::SSha2 | ConfigType::SShake => (3776, 12640),
             ConfigType::M => (18016, 28928),
             ConfigType::L => (24768, 35168),
-            ConfigType::Unknown => unimplemented!(),
+            ConfigType::ST => (3808, 12672),
+            ConfigType::S128 => (1376, 5808),
+            ConfigType::S192 => (2448, 9096),
+            ConfigType::SW4 | ConfigType::SW256 | ConfigType::Unknown => unimplemented!(),
         };
         assert_eq!(Signature::<P>::min_size_bytes(), expected_min_bytes);
         assert_eq!(Signature::<P>::max_size_bytes(), expected_max_bytes);
     }
 
-    fn test_sign_verify<P: GravityParams>()
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        let random: [u8; 64] = array::from_fn(|i| i as u8);
+    crate::tests::param_tests!(cached_small, GravitySmall, test_sign_cached,);
+    crate::tests::param_tests!(cached_small_tweaked, GravitySmallTweaked, test_sign_cached,);
+
+    fn test_sign_cached<P: GravityParams>() {
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let sk = SecKey::<P>::new(&random);
+        let mut cached_sk = SecKey::<P>::new(&random);
+        assert_eq!(cached_sk.cache_subtrees(0), 0);
+        assert_eq!(cached_sk.cache_subtrees(usize::MAX), P::GRAVITY_D);
+
+        for msg in [b"Hello world".as_slice(), b"", &[0xff; 100]] {
+            let mut bytes = Vec::new();
+            sk.sign_bytes(msg).serialize(&mut bytes);
+            let mut cached_bytes = Vec::new();
+            cached_sk.sign_bytes(msg).serialize(&mut cached_bytes);
+            assert_eq!(bytes, cached_bytes);
+        }
+    }
+
+    crate::tests::param_tests!(streaming_small, GravitySmall, test_sign_streaming,);
+    crate::tests::param_tests!(streaming_small_128, GravitySmall128, test_sign_streaming,);
+
+    fn test_sign_streaming<P: GravityParams>() {
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let sk = SecKey::<P>::new(&random);
+        let mut streaming_sk = SecKey::<P>::new(&random);
+        streaming_sk.stream_pors(true);
+
+        for msg in [b"Hello world".as_slice(), b"", &[0xff; 100]] {
+            let mut bytes = Vec::new();
+            sk.sign_bytes(msg).serialize(&mut bytes);
+            let mut streaming_bytes = Vec::new();
+            streaming_sk.sign_bytes(msg).serialize(&mut streaming_bytes);
+            assert_eq!(bytes, streaming_bytes);
+        }
+    }
+
+    #[test]
+    fn test_builder_progress() {
+        type P = GravitySmall;
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+
+        let mut builder = SecKeyBuilder::<P>::new(&random);
+        assert_eq!(builder.total(), 1 << P::GRAVITY_C);
+        let mut reports = Vec::new();
+        builder.run(300, |b| reports.push((b.progress(), b.total())));
+        assert_eq!(
+            reports,
+            [(300, 1024), (600, 1024), (900, 1024), (1024, 1024)]
+        );
+
+        let sk = builder.finish().unwrap();
+        let expect = SecKey::<P>::new(&random);
+        assert_eq!(sk.cache_leaves(), expect.cache_leaves());
+        assert_eq!(sk.genpk().h, expect.genpk().h);
+    }
+
+    #[test]
+    fn test_builder_resume() {
+        type P = GravitySmallTweaked;
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+
+        let mut builder = SecKeyBuilder::<P>::new(&random);
+        assert_eq!(builder.step(100), 100);
+        let checkpoint = builder.checkpoint();
+        assert_eq!(checkpoint.len(), 8 + 101 * P::HASH_BYTES);
+        drop(builder);
+
+        let mut builder = SecKeyBuilder::<P>::resume(&random, &checkpoint).unwrap();
+        assert_eq!(builder.progress(), 100);
+        assert_eq!(builder.checkpoint(), checkpoint);
+        assert_eq!(builder.step(1000), 1024);
+        assert_eq!(builder.step(1), 1024);
+
+        let sk = builder.finish().unwrap();
+        let expect = SecKey::<P>::new(&random);
+        assert_eq!(sk.cache_leaves(), expect.cache_leaves());
+        assert_eq!(sk.genpk().h, expect.genpk().h);
+    }
+
+    #[test]
+    fn test_builder_invalid() {
+        type P = GravitySmall;
+        let random = [0u8; 64];
+
+        let mut builder = SecKeyBuilder::<P>::new(&random);
+        builder.step(2);
+        let checkpoint = builder.checkpoint();
+        assert!(builder.finish().is_none());
+
+        // Truncated, extended or inconsistent checkpoints
+        assert!(SecKeyBuilder::<P>::resume(&random, &checkpoint[..7]).is_none());
+        assert!(SecKeyBuilder::<P>::resume(&random, &checkpoint[..checkpoint.len() - 1]).is_none());
+        let mut extended = checkpoint.clone();
+        extended.push(0);
+        assert!(SecKeyBuilder::<P>::resume(&random, &extended).is_none());
+        let mut data = checkpoint[..checkpoint.len() - P::HASH_BYTES].to_vec();
+        data[7] = 3;
+        assert!(SecKeyBuilder::<P>::resume(&random, &with_tag::<P>(&random, data)).is_none());
+
+        // More leaves than the cache
+        let mut data = Vec::new();
+        data.extend(1025u64.to_be_bytes());
+        data.extend(core::iter::repeat_n(0, 1025 * P::HASH_BYTES));
+        assert!(SecKeyBuilder::<P>::resume(&random, &with_tag::<P>(&random, data)).is_none());
+
+        // Corrupted leaves or tag, or checkpoint of another key
+        for i in 8..checkpoint.len() {
+            let mut corrupted = checkpoint.clone();
+            corrupted[i] ^= 1;
+            assert!(SecKeyBuilder::<P>::resume(&random, &corrupted).is_none());
+        }
+        assert!(SecKeyBuilder::<P>::resume(&[1; 64], &checkpoint).is_none());
+    }
+
+    fn with_tag<P: GravityParams>(random: &[u8; 64], mut data: Vec<u8>) -> Vec<u8> {
+        let seed = SecKey::<P>::empty(random).seed;
+        checkpoint_tag::<P>(&seed, &data).serialize(P::HASH_BYTES, &mut data);
+        data
+    }
+
+    fn test_sign_verify<P: GravityParams>() {
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
 
         let sk = SecKey::<P>::new(&random);
         let pk = sk.genpk();
         let msg = hash::tests::HASH_ELEMENT;
         let sign = sk.sign_hash(&msg);
         assert!(pk.verify_hash(&sign, &msg));
+
+        let sign = sk.sign_bytes(b"Hello world");
+        assert!(bool::from(pk.ct_verify_bytes(&sign, b"Hello world")));
+        assert!(!bool::from(pk.ct_verify_bytes(&sign, b"Goodbye")));
+        let sign = sk.sign_hash(&msg);
+
+        let mut bytes = Vec::new();
+        sign.serialize(&mut bytes);
+        assert_eq!(bytes.len(), P::SIGNATURE_BYTES);
+        assert_eq!(extract_hash_serialized::<P>(&bytes, &msg), Some(pk.h));
+        assert_eq!(extract_hash_serialized::<P>(&bytes[1..], &msg), None);
+        bytes[P::SIGNATURE_BYTES - 1] ^= 1;
+        assert_ne!(extract_hash_serialized::<P>(&bytes, &msg), Some(pk.h));
     }
 
-    fn test_genkey_zeros<P: GravityParams>()
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        let random: [u8; 64] = [0u8; 64];
-        let pkh: [u8; 32] = match P::config_type() {
+    fn zeros_pkh<P: GravityParams>() -> [u8; 32] {
+        match P::config_type() {
             ConfigType::S => {
                 *b"\x57\x03\x58\x87\x1a\x7a\x2c\xfe\
                    \x1e\xab\xf1\x3b\x4c\x11\x3a\x81\
@@ -318,40 +778,78 @@ mod tests {
                    \x84\x68\x1c\xa8\x03\x75\x4c\xc2\
                    \x1f\x50\x69\x68\x41\xc1\xb3\x03"
             }
-            ConfigType::Unknown => unimplemented!(),
-        };
+            ConfigType::ST => {
+                *b"\x41\x76\x85\xc5\xda\xf0\x66\xcc\
+                   \x0e\x4d\xb0\xca\x79\x05\xf1\xbd\
+                   \x18\xa8\xe6\xbb\x04\x71\x69\xd7\
+                   \x3f\xf8\x6b\xe4\xd9\xf6\x56\xe6"
+            }
+            ConfigType::SSha2 => {
+                *b"\x82\xb2\x6d\x90\x0f\xeb\x6f\x6e\
+                   \xfc\xbb\x88\xca\xa5\x0c\x59\xf6\
+                   \x78\x1c\x17\x0a\x7c\x75\x38\x28\
+                   \x42\x42\xd6\x1c\x6d\x71\x43\xed"
+            }
+            ConfigType::SShake => {
+                *b"\x30\x15\xee\x6c\xe2\xf4\x9d\x05\
+                   \xd7\x6b\x3c\x41\x0c\x04\x57\x6a\
+                   \x88\xee\x0b\xe4\x78\xd8\x29\xc2\
+                   \x9c\xf2\x45\xcd\x9e\x69\x90\x01"
+            }
+            ConfigType::S128 => {
+                *b"\x83\xaf\xb0\x53\x8e\x60\x5b\xde\
+                   \x27\xb2\x7c\x1b\xb8\xb1\xd6\x03\
+                   \x00\x00\x00\x00\x00\x00\x00\x00\
+                   \x00\x00\x00\x00\x00\x00\x00\x00"
+            }
+            ConfigType::S192 => {
+                *b"\x78\x62\x40\xdc\xa2\xb7\x8c\x83\
+                   \xf9\xd5\x34\x98\xb3\x8d\xaf\x16\
+                   \x88\xc9\xf7\x7f\x26\xfa\x5d\x53\
+                   \x00\x00\x00\x00\x00\x00\x00\x00"
+            }
+            ConfigType::SW4 | ConfigType::SW256 | ConfigType::Unknown => unimplemented!(),
+        }
+    }
+
+    fn test_genkey_zeros<P: GravityParams>() {
+        let random: [u8; 64] = [0u8; 64];
+        let pkh = zeros_pkh::<P>();
 
         let sk = SecKey::<P>::new(&random);
         let pk = sk.genpk();
         assert_eq!(pk.h.h, pkh);
     }
 
-    fn test_sign_zeros<P: GravityParams>()
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        use hex;
-
-        let random: [u8; 64] = [0u8; 64];
-        let msg: [u8; 32] = *b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                               \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                               \x10\x11\x12\x13\x14\x15\x16\x17\
-                               \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+    fn zeros_signature<P: GravityParams>() -> Vec<u8> {
         let hex_file = match P::config_type() {
             ConfigType::S => include_str!("../test_files/test_sign_zero_S.hex"),
             ConfigType::M => include_str!("../test_files/test_sign_zero_M.hex"),
             ConfigType::L => include_str!("../test_files/test_sign_zero_L.hex"),
-            ConfigType::Unknown => unimplemented!(),
+            ConfigType::ST => include_str!("../test_files/test_sign_zero_ST.hex"),
+            ConfigType::SSha2 => include_str!("../test_files/test_sign_zero_SSha2.hex"),
+            ConfigType::SShake => include_str!("../test_files/test_sign_zero_SShake.hex"),
+            ConfigType::S128 => include_str!("../test_files/test_sign_zero_S128.hex"),
+            ConfigType::S192 => include_str!("../test_files/test_sign_zero_S192.hex"),
+            ConfigType::SW4 | ConfigType::SW256 | ConfigType::Unknown => unimplemented!(),
         };
 
         let mut hex: Vec<u8> = vec![];
         for x in hex_file.split_whitespace() {
             hex.extend(x.bytes())
         }
-        let expect: Vec<u8> = hex::decode(hex).unwrap();
+        hex::decode(hex).unwrap()
+    }
+
+    fn test_sign_zeros<P: GravityParams>() {
+        use hex;
+
+        let random: [u8; 64] = [0u8; 64];
+        let msg: [u8; 32] = *b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                               \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                               \x10\x11\x12\x13\x14\x15\x16\x17\
+                               \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+        let expect = zeros_signature::<P>();
 
         let sk = SecKey::<P>::new(&random);
         let sign = sk.sign_bytes(&msg);
@@ -367,22 +865,8 @@ mod tests {
         assert!(sign_bytes == expect);
     }
 
-    fn test_genkey_kat<P: GravityParams>()
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        let random: [u8; 64] = *b"\x7C\x99\x35\xA0\xB0\x76\x94\xAA\
-                                  \x0C\x6D\x10\xE4\xDB\x6B\x1A\xDD\
-                                  \x2F\xD8\x1A\x25\xCC\xB1\x48\x03\
-                                  \x2D\xCD\x73\x99\x36\x73\x7F\x2D\
-                                  \x86\x26\xED\x79\xD4\x51\x14\x08\
-                                  \x00\xE0\x3B\x59\xB9\x56\xF8\x21\
-                                  \x0E\x55\x60\x67\x40\x7D\x13\xDC\
-                                  \x90\xFA\x9E\x8B\x87\x2B\xFB\x8F";
-        let pkh: [u8; 32] = match P::config_type() {
+    fn kat_pkh<P: GravityParams>() -> [u8; 32] {
+        match P::config_type() {
             ConfigType::S => {
                 *b"\xDB\x9E\xBB\x0D\xB2\xB1\xD2\x31\
                    \x9E\xFB\x26\xCD\xA6\x5C\x0F\x50\
@@ -401,23 +885,41 @@ mod tests {
                    \xF0\xF7\xC3\xC5\x3D\x0E\x21\xFE\
                    \xD8\xDF\x4C\xD6\xCB\x20\x69\xD9"
             }
-            ConfigType::Unknown => unimplemented!(),
-        };
-
-        let sk = SecKey::<P>::new(&random);
-        let pk = sk.genpk();
-        assert_eq!(pk.h.h, pkh);
+            ConfigType::ST => {
+                *b"\xF9\x25\x85\xF9\x1B\x6D\xEA\x87\
+                   \xB1\xF1\xBD\xF5\xF0\x72\x55\x15\
+                   \xEB\x57\x71\x7B\x86\x97\xB9\x98\
+                   \x26\x5F\xB4\xF1\xA6\x4E\x5A\x0D"
+            }
+            ConfigType::SSha2 => {
+                *b"\x3F\x5A\x2F\xB5\x8D\xE8\xAD\x67\
+                   \x21\xFB\x95\xD8\x5B\x03\x60\x3A\
+                   \x45\xC4\xB9\x86\x20\xF2\x67\x32\
+                   \x3F\xA0\xC2\x6D\xAB\xA7\xE5\xEE"
+            }
+            ConfigType::SShake => {
+                *b"\xFF\x27\xCA\x08\xD1\x26\x0C\xBE\
+                   \xD7\x9A\x79\x8A\x4A\x11\x29\x5D\
+                   \xB8\x60\x13\xAA\x0E\x20\x76\x27\
+                   \x4A\xF0\x42\x92\xEF\x26\x9A\xE6"
+            }
+            ConfigType::S128 => {
+                *b"\x14\xC1\x3F\xF3\x51\x5C\x57\xD9\
+                   \x43\x98\x15\xCA\xE6\x68\x72\xB9\
+                   \x00\x00\x00\x00\x00\x00\x00\x00\
+                   \x00\x00\x00\x00\x00\x00\x00\x00"
+            }
+            ConfigType::S192 => {
+                *b"\xB1\x5A\x79\xF7\xA3\x01\x59\xAB\
+                   \xB1\x9B\x2D\x20\xF9\x57\x9B\x6A\
+                   \xB8\x95\xF5\xE4\x16\x02\xB1\x99\
+                   \x00\x00\x00\x00\x00\x00\x00\x00"
+            }
+            ConfigType::SW4 | ConfigType::SW256 | ConfigType::Unknown => unimplemented!(),
+        }
     }
 
-    fn test_sign_kat<P: GravityParams>()
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        use hex;
-
+    fn test_genkey_kat<P: GravityParams>() {
         let random: [u8; 64] = *b"\x7C\x99\x35\xA0\xB0\x76\x94\xAA\
                                   \x0C\x6D\x10\xE4\xDB\x6B\x1A\xDD\
                                   \x2F\xD8\x1A\x25\xCC\xB1\x48\x03\
@@ -426,20 +928,47 @@ mod tests {
                                   \x00\xE0\x3B\x59\xB9\x56\xF8\x21\
                                   \x0E\x55\x60\x67\x40\x7D\x13\xDC\
                                   \x90\xFA\x9E\x8B\x87\x2B\xFB\x8F";
-        let msg = hex::decode("D81C4D8D734FCBFBEADE3D3F8A039FAA2A2C9957E835AD55B22E75BF57BB556AC8")
-            .unwrap();
+        let pkh = kat_pkh::<P>();
+
+        let sk = SecKey::<P>::new(&random);
+        let pk = sk.genpk();
+        assert_eq!(pk.h.h, pkh);
+    }
+
+    fn kat_signature<P: GravityParams>() -> Vec<u8> {
         let hex_file = match P::config_type() {
             ConfigType::S => include_str!("../test_files/test_sign_kat_S.hex"),
             ConfigType::M => include_str!("../test_files/test_sign_kat_M.hex"),
             ConfigType::L => include_str!("../test_files/test_sign_kat_L.hex"),
-            ConfigType::Unknown => unimplemented!(),
+            ConfigType::ST => include_str!("../test_files/test_sign_kat_ST.hex"),
+            ConfigType::SSha2 => include_str!("../test_files/test_sign_kat_SSha2.hex"),
+            ConfigType::SShake => include_str!("../test_files/test_sign_kat_SShake.hex"),
+            ConfigType::S128 => include_str!("../test_files/test_sign_kat_S128.hex"),
+            ConfigType::S192 => include_str!("../test_files/test_sign_kat_S192.hex"),
+            ConfigType::SW4 | ConfigType::SW256 | ConfigType::Unknown => unimplemented!(),
         };
 
         let mut hex: Vec<u8> = vec![];
         for x in hex_file.split_whitespace() {
             hex.extend(x.bytes())
         }
-        let expect: Vec<u8> = hex::decode(hex).unwrap();
+        hex::decode(hex).unwrap()
+    }
+
+    fn test_sign_kat<P: GravityParams>() {
+        use hex;
+
+        let random: [u8; 64] = *b"\x7C\x99\x35\xA0\xB0\x76\x94\xAA\
+                                  \x0C\x6D\x10\xE4\xDB\x6B\x1A\xDD\
+                                  \x2F\xD8\x1A\x25\xCC\xB1\x48\x03\
+                                  \x2D\xCD\x73\x99\x36\x73\x7F\x2D\
+                                  \x86\x26\xED\x79\xD4\x51\x14\x08\
+                                  \x00\xE0\x3B\x59\xB9\x56\xF8\x21\
+                                  \x0E\x55\x60\x67\x40\x7D\x13\xDC\
+                                  \x90\xFA\x9E\x8B\x87\x2B\xFB\x8F";
+        let msg = hex::decode("D81C4D8D734FCBFBEADE3D3F8A039FAA2A2C9957E835AD55B22E75BF57BB556AC8")
+            .unwrap();
+        let expect = kat_signature::<P>();
 
         let sk = SecKey::<P>::new(&random);
         let sign = sk.sign_bytes(&msg);
@@ -455,66 +984,92 @@ mod tests {
         assert!(sign_bytes == expect);
     }
 
-    macro_rules! all_benches {
-        ( $mod:ident, $params:ty ) => {
-            crate::tests::param_benches!(
-                $mod,
-                $params,
-                #[cfg(feature = "bigbench")]
-                bench_keypair,
-                bench_sign,
-                bench_verify,
-            );
-        };
-    }
+    fn test_recover_kat<P: GravityParams>() {
+        let msg = hex::decode("D81C4D8D734FCBFBEADE3D3F8A039FAA2A2C9957E835AD55B22E75BF57BB556AC8")
+            .unwrap();
+        let pk = PubKey::<P>::new(&kat_pkh::<P>()[..P::HASH_BYTES]).unwrap();
+        let sign = Signature::<P>::deserialize(&mut kat_signature::<P>().iter()).unwrap();
 
-    all_benches!(benches_small, GravitySmall);
-    all_benches!(benches_medium, GravityMedium);
-    all_benches!(benches_large, GravityLarge);
+        let recovered = recover_public_key(&msg, &sign).unwrap();
+        assert_eq!(recovered.get_bytes(), pk.get_bytes());
+        assert_eq!(recovered.fingerprint(), pk.fingerprint());
+        assert!(pk.verify_bytes(&sign, &msg));
 
-    use std::hint::black_box;
-    use test::Bencher;
+        // Another message selects other PORS leaves, which the octopus doesn't authenticate.
+        assert!(recover_public_key(b"Hello world", &sign).is_none());
 
-    #[cfg(feature = "bigbench")]
-    fn bench_keypair<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        let seed = [0u8; 64];
-        b.iter(|| {
-            let sk = SecKey::<P>::new(black_box(&seed));
-            sk.genpk()
-        });
+        // The fingerprints of the zeros and KAT keys differ.
+        let other = PubKey::<P>::new(&zeros_pkh::<P>()[..P::HASH_BYTES]).unwrap();
+        assert_ne!(other.fingerprint(), pk.fingerprint());
     }
 
-    fn bench_sign<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        let seed = [0u8; 64];
-        let sk = SecKey::<P>::new(&seed);
-        let msg = hash::tests::HASH_ELEMENT;
-        b.iter(|| sk.sign_hash(black_box(&msg)));
-    }
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
 
-    fn bench_verify<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
-        let seed = [0u8; 64];
-        let sk = SecKey::<P>::new(&seed);
-        let pk = sk.genpk();
-        let msg = hash::tests::HASH_ELEMENT;
-        let sign = sk.sign_hash(&msg);
-        b.iter(|| pk.verify_hash(black_box(&sign), black_box(&msg)));
+        macro_rules! all_benches {
+            ( $mod:ident, $params:ty ) => {
+                crate::tests::param_benches!(
+                    $mod,
+                    $params,
+                    #[cfg(feature = "bigbench")]
+                    bench_keypair,
+                    bench_sign,
+                    bench_verify,
+                );
+            };
+        }
+
+        all_benches!(benches_small, GravitySmall);
+        all_benches!(benches_medium, GravityMedium);
+        all_benches!(benches_large, GravityLarge);
+        all_benches!(benches_small_w4, crate::config::SmallW4);
+        all_benches!(benches_small_w256, crate::config::SmallW256);
+
+        use std::hint::black_box;
+        use test::Bencher;
+
+        #[cfg(feature = "bigbench")]
+        fn bench_keypair<P: GravityParams>(b: &mut Bencher) {
+            let seed = [0u8; 64];
+            b.iter(|| {
+                let sk = SecKey::<P>::new(black_box(&seed));
+                sk.genpk()
+            });
+        }
+
+        fn bench_sign<P: GravityParams>(b: &mut Bencher) {
+            let seed = [0u8; 64];
+            let sk = SecKey::<P>::new(&seed);
+            let msg = hash::tests::HASH_ELEMENT;
+            b.iter(|| sk.sign_hash(black_box(&msg)));
+        }
+
+        #[bench]
+        fn bench_sign_cached_small(b: &mut Bencher) {
+            let seed = [0u8; 64];
+            let mut sk = SecKey::<GravitySmall>::new(&seed);
+            sk.cache_subtrees(usize::MAX);
+            let msg = hash::tests::HASH_ELEMENT;
+            b.iter(|| sk.sign_hash(black_box(&msg)));
+        }
+
+        #[bench]
+        fn bench_sign_streaming_small(b: &mut Bencher) {
+            let seed = [0u8; 64];
+            let mut sk = SecKey::<GravitySmall>::new(&seed);
+            sk.stream_pors(true);
+            let msg = hash::tests::HASH_ELEMENT;
+            b.iter(|| sk.sign_hash(black_box(&msg)));
+        }
+
+        fn bench_verify<P: GravityParams>(b: &mut Bencher) {
+            let seed = [0u8; 64];
+            let sk = SecKey::<P>::new(&seed);
+            let pk = sk.genpk();
+            let msg = hash::tests::HASH_ELEMENT;
+            let sign = sk.sign_hash(&msg);
+            b.iter(|| pk.verify_hash(black_box(&sign), black_box(&msg)));
+        }
     }
 }
@@ -1,15 +1,39 @@
+use crate::address;
 use crate::config;
 use crate::primitives::haraka256;
 use crate::primitives::haraka512;
-use arrayref::array_ref;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use arrayref::{array_mut_ref, array_ref};
+use byteorder::{BigEndian, ByteOrder};
+use core::fmt;
+use core::marker::PhantomData;
 use sha2::{Digest, Sha256};
-use std::fmt;
+use sha3::Shake256;
+use sha3::digest::{ExtendableOutput, Update, XofReader};
+use subtle::{Choice, ConstantTimeEq};
 
-#[derive(Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Default)]
 pub struct Hash {
     pub h: [u8; config::HASH_SIZE],
 }
 
+// Hashes are always compared in constant time, even though most of them are public, so that
+// no comparison needs to be audited separately.
+impl ConstantTimeEq for Hash {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.h.ct_eq(&other.h)
+    }
+}
+
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for Hash {}
+
 impl fmt::Debug for Hash {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for x in self.h.iter() {
@@ -19,103 +43,423 @@ impl fmt::Debug for Hash {
     }
 }
 
+// Hashes of `n < HASH_SIZE` bytes are serialized on `n` bytes, the remaining bytes being zero.
 impl Hash {
-    pub fn serialize(&self, output: &mut Vec<u8>) {
-        output.extend(self.h.iter());
+    #[cfg(feature = "alloc")]
+    pub fn serialize(&self, n: usize, output: &mut Vec<u8>) {
+        output.extend(self.h[..n].iter());
     }
 
-    pub fn deserialize<'a, I>(it: &mut I) -> Option<Self>
+    pub fn deserialize<'a, I>(n: usize, it: &mut I) -> Option<Self>
     where
         I: Iterator<Item = &'a u8>,
     {
         let mut hash: Hash = Default::default();
-        for x in hash.h.iter_mut() {
+        for x in hash.h[..n].iter_mut() {
             *x = *it.next()?;
         }
         Some(hash)
     }
+
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        let mut hash: Hash = Default::default();
+        hash.h[..bytes.len()].copy_from_slice(bytes);
+        hash
+    }
+}
+
+/// Hash function used by the scheme, for the chains, trees and tweaks, which only hash 32 and
+/// 64 bytes, and for the message digest.
+pub trait HashFunction {
+    /// Size of the outputs, in bytes. Outputs shorter than 32 bytes are stored in the first
+    /// bytes of a [`Hash`], followed by zeros.
+    const OUTPUT_BYTES: usize = config::HASH_SIZE;
+
+    fn hash_n_to_n(dst: &mut Hash, src: &Hash);
+    fn hash_2n_to_n(dst: &mut Hash, src0: &Hash, src1: &Hash);
+    fn long_hash(src: &[u8]) -> Hash;
+
+    #[inline(always)]
+    fn hash_n_to_n_ret(src: &Hash) -> Hash {
+        let mut dst = Default::default();
+        Self::hash_n_to_n(&mut dst, src);
+        dst
+    }
+
+    #[inline(always)]
+    fn hash_2n_to_n_ret(src0: &Hash, src1: &Hash) -> Hash {
+        let mut dst = Default::default();
+        Self::hash_2n_to_n(&mut dst, src0, src1);
+        dst
+    }
+
+    // Zero the bytes after the output size.
+    #[inline(always)]
+    fn truncate(h: &mut Hash) {
+        h.h[Self::OUTPUT_BYTES..].fill(0);
+    }
+}
+
+/// Haraka v2 with 6 rounds for the short inputs, and SHA-256 for the message digest.
+#[derive(Debug, PartialEq)]
+pub struct Haraka;
+
+impl HashFunction for Haraka {
+    #[inline(always)]
+    fn hash_n_to_n(dst: &mut Hash, src: &Hash) {
+        haraka256::haraka256::<6>(&mut dst.h, &src.h)
+    }
+
+    #[inline(always)]
+    fn hash_2n_to_n(dst: &mut Hash, src0: &Hash, src1: &Hash) {
+        haraka512::haraka512::<6>(&mut dst.h, &src0.h, &src1.h)
+    }
+
+    fn long_hash(src: &[u8]) -> Hash {
+        sha256(src)
+    }
+}
+
+/// SHA-256. Each short input fits in a single call to the compression function: 32-byte inputs
+/// are padded as in SHA-256, and 64-byte inputs are compressed without padding.
+#[derive(Debug, PartialEq)]
+pub struct Sha2;
+
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+#[inline(always)]
+fn sha256_compress(dst: &mut Hash, block: &[u8; 64]) {
+    let mut state = SHA256_IV;
+    sha2::compress256(&mut state, &[(*block).into()]);
+    BigEndian::write_u32_into(&state, &mut dst.h);
 }
 
-pub fn long_hash(src: &[u8]) -> Hash {
+impl HashFunction for Sha2 {
+    #[inline(always)]
+    fn hash_n_to_n(dst: &mut Hash, src: &Hash) {
+        let mut block = [0; 64];
+        block[..32].copy_from_slice(&src.h);
+        block[32] = 0x80;
+        BigEndian::write_u64(array_mut_ref![block, 56, 8], 256);
+        sha256_compress(dst, &block);
+    }
+
+    #[inline(always)]
+    fn hash_2n_to_n(dst: &mut Hash, src0: &Hash, src1: &Hash) {
+        let mut block = [0; 64];
+        block[..32].copy_from_slice(&src0.h);
+        block[32..].copy_from_slice(&src1.h);
+        sha256_compress(dst, &block);
+    }
+
+    fn long_hash(src: &[u8]) -> Hash {
+        sha256(src)
+    }
+}
+
+/// SHAKE256 with a 32-byte output, for all inputs.
+#[derive(Debug, PartialEq)]
+pub struct Shake;
+
+impl HashFunction for Shake {
+    #[inline(always)]
+    fn hash_n_to_n(dst: &mut Hash, src: &Hash) {
+        shake256(dst, &[&src.h]);
+    }
+
+    #[inline(always)]
+    fn hash_2n_to_n(dst: &mut Hash, src0: &Hash, src1: &Hash) {
+        shake256(dst, &[&src0.h, &src1.h]);
+    }
+
+    fn long_hash(src: &[u8]) -> Hash {
+        let mut dst = Default::default();
+        shake256(&mut dst, &[src]);
+        dst
+    }
+}
+
+/// The hash function `H`, truncated to `N` bytes (16 or 24), for smaller signatures at the
+/// category 1 or 3 security levels.
+#[derive(Debug, PartialEq)]
+pub struct Truncated<H: HashFunction, const N: usize>(PhantomData<H>);
+
+impl<H: HashFunction, const N: usize> HashFunction for Truncated<H, N> {
+    const OUTPUT_BYTES: usize = N;
+
+    #[inline(always)]
+    fn hash_n_to_n(dst: &mut Hash, src: &Hash) {
+        H::hash_n_to_n(dst, src);
+        Self::truncate(dst);
+    }
+
+    #[inline(always)]
+    fn hash_2n_to_n(dst: &mut Hash, src0: &Hash, src1: &Hash) {
+        H::hash_2n_to_n(dst, src0, src1);
+        Self::truncate(dst);
+    }
+
+    fn long_hash(src: &[u8]) -> Hash {
+        let mut dst = H::long_hash(src);
+        Self::truncate(&mut dst);
+        dst
+    }
+}
+
+fn sha256(src: &[u8]) -> Hash {
     let digest = Sha256::digest(src);
     Hash {
         h: *array_ref![digest, 0, config::HASH_SIZE],
     }
 }
 
-pub fn hash_n_to_n(dst: &mut Hash, src: &Hash) {
-    haraka256::haraka256::<6>(&mut dst.h, &src.h)
+#[inline(always)]
+fn shake256(dst: &mut Hash, src: &[&[u8]]) {
+    let mut hasher = Shake256::default();
+    for x in src {
+        hasher.update(x);
+    }
+    hasher.finalize_xof().read(&mut dst.h);
+}
+
+// Kinds of hashes, to separate their domains in tweaked mode.
+#[derive(Clone, Copy)]
+pub enum TweakKind {
+    WotsChain = 0,
+    LTree = 1,
+    SubtreeNode = 2,
+    PorsLeaf = 3,
+    PorsNode = 4,
+    CacheNode = 5,
+    Message = 6,
+    PublicKey = 7,
+    #[cfg(feature = "alloc")]
+    BatchNode = 8,
+}
+
+// In parameter sets with tweaked hashes, each short hash is keyed by the public seed and by
+// its position in the hypertree, to prevent multi-target attacks. The position is an address
+// together with a height and an index, which are the chain and the step for WOTS chains.
+// Without a public seed, the tweak is ignored and the plain hash functions are used.
+pub struct Tweak<'a, H: HashFunction> {
+    seed: Option<&'a Hash>,
+    instance: u64,
+    layer: u32,
+    kind: u32,
+    height: u32,
+    index: u32,
+    _phantom: PhantomData<H>,
+}
+
+impl<H: HashFunction> Clone for Tweak<'_, H> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
+impl<H: HashFunction> Copy for Tweak<'_, H> {}
+
 #[cfg(test)]
-pub fn hash_n_to_n_ret(src: &Hash) -> Hash {
+impl Tweak<'static, Haraka> {
+    pub const NONE: Self = Tweak {
+        seed: None,
+        instance: 0,
+        layer: 0,
+        kind: 0,
+        height: 0,
+        index: 0,
+        _phantom: PhantomData,
+    };
+}
+
+impl<'a, H: HashFunction> Tweak<'a, H> {
+    // A tweak without public seed, for trees of the plain hash function.
+    #[cfg(feature = "alloc")]
+    pub fn plain() -> Self {
+        Self {
+            seed: None,
+            instance: 0,
+            layer: 0,
+            kind: 0,
+            height: 0,
+            index: 0,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn new(seed: Option<&'a Hash>, address: &address::Address, kind: TweakKind) -> Self {
+        Self {
+            seed,
+            instance: address.get_instance(),
+            layer: address.get_layer(),
+            kind: kind as u32,
+            height: 0,
+            index: 0,
+            _phantom: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    pub fn with_height(&self, height: usize) -> Self {
+        Self {
+            height: height as u32,
+            ..*self
+        }
+    }
+
+    #[inline(always)]
+    pub fn with_index(&self, index: usize) -> Self {
+        Self {
+            index: index as u32,
+            ..*self
+        }
+    }
+
+    // Derive the key of this position, as H(seed || address).
+    #[inline(always)]
+    fn key(&self, seed: &Hash) -> Hash {
+        let mut block = Hash::default();
+        BigEndian::write_u64(array_mut_ref![block.h, 0, 8], self.instance);
+        BigEndian::write_u32(array_mut_ref![block.h, 8, 4], self.layer);
+        BigEndian::write_u32(array_mut_ref![block.h, 12, 4], self.kind);
+        BigEndian::write_u32(array_mut_ref![block.h, 16, 4], self.height);
+        BigEndian::write_u32(array_mut_ref![block.h, 20, 4], self.index);
+        H::hash_2n_to_n_ret(seed, &block)
+    }
+}
+
+// F(key, src) = H(key || src)
+#[inline(always)]
+pub fn tweak_n_to_n<H: HashFunction>(tweak: &Tweak<H>, dst: &mut Hash, src: &Hash) {
+    match tweak.seed {
+        None => H::hash_n_to_n(dst, src),
+        Some(seed) => H::hash_2n_to_n(dst, &tweak.key(seed), src),
+    }
+}
+
+#[inline(always)]
+pub fn tweak_n_to_n_ret<H: HashFunction>(tweak: &Tweak<H>, src: &Hash) -> Hash {
     let mut dst = Default::default();
-    hash_n_to_n(&mut dst, src);
+    tweak_n_to_n(tweak, &mut dst, src);
     dst
 }
 
-pub fn hash_2n_to_n(dst: &mut Hash, src0: &Hash, src1: &Hash) {
-    haraka512::haraka512::<6>(&mut dst.h, &src0.h, &src1.h)
+// H(key, src0, src1) = H(H(key || src0) || src1)
+#[inline(always)]
+pub fn tweak_2n_to_n<H: HashFunction>(tweak: &Tweak<H>, dst: &mut Hash, src0: &Hash, src1: &Hash) {
+    match tweak.seed {
+        None => H::hash_2n_to_n(dst, src0, src1),
+        Some(seed) => {
+            let tmp = H::hash_2n_to_n_ret(&tweak.key(seed), src0);
+            H::hash_2n_to_n(dst, &tmp, src1)
+        }
+    }
 }
 
 #[inline(always)]
-pub fn hash_2n_to_n_ret(src0: &Hash, src1: &Hash) -> Hash {
+pub fn tweak_2n_to_n_ret<H: HashFunction>(tweak: &Tweak<H>, src0: &Hash, src1: &Hash) -> Hash {
     let mut dst = Default::default();
-    hash_2n_to_n(&mut dst, src0, src1);
+    tweak_2n_to_n(tweak, &mut dst, src0, src1);
     dst
 }
 
+// Compute the steps `start..(start + count)` of a chain.
 #[inline(always)]
-pub fn hash_n_to_n_chain(dst: &mut Hash, src: &Hash, count: usize) {
+pub fn hash_n_to_n_chain<H: HashFunction>(
+    tweak: &Tweak<H>,
+    dst: &mut Hash,
+    src: &Hash,
+    start: usize,
+    count: usize,
+) {
     *dst = *src;
-    for _ in 0..count {
+    for i in start..(start + count) {
         let tmp = *dst;
-        hash_n_to_n(dst, &tmp);
+        tweak_n_to_n(&tweak.with_index(i), dst, &tmp);
     }
 }
 
 #[cfg(test)]
-pub fn hash_n_to_n_chain_ret(src: &Hash, count: usize) -> Hash {
+pub fn hash_n_to_n_chain_ret<H: HashFunction>(
+    tweak: &Tweak<H>,
+    src: &Hash,
+    start: usize,
+    count: usize,
+) -> Hash {
     let mut dst = Default::default();
-    hash_n_to_n_chain(&mut dst, src, count);
+    hash_n_to_n_chain(tweak, &mut dst, src, start, count);
     dst
 }
 
+#[cfg(feature = "alloc")]
 #[inline(always)]
-pub fn hash_parallel(dst: &mut [Hash], src: &[Hash], count: usize) {
+pub fn hash_parallel<H: HashFunction>(
+    tweak: &Tweak<H>,
+    dst: &mut [Hash],
+    src: &[Hash],
+    count: usize,
+) {
     for i in 0..count {
-        hash_n_to_n(&mut dst[i], &src[i]);
+        tweak_n_to_n(&tweak.with_index(i), &mut dst[i], &src[i]);
     }
 }
 
+#[cfg(test)]
 #[inline(always)]
-pub fn hash_parallel_all(dst: &mut [Hash], src: &[Hash]) {
+pub fn hash_parallel_all<H: HashFunction>(tweak: &Tweak<H>, dst: &mut [Hash], src: &[Hash]) {
     let count = dst.len();
-    hash_parallel(dst, src, count);
+    hash_parallel(tweak, dst, src, count);
 }
 
+// Compute `count` chains of length `chainlen`, the i-th chain being tweaked with height i.
+#[cfg(feature = "alloc")]
 #[inline(always)]
 #[allow(clippy::needless_range_loop)]
-fn hash_parallel_chains(dst: &mut [Hash], src: &[Hash], count: usize, chainlen: usize) {
+fn hash_parallel_chains<H: HashFunction>(
+    tweak: &Tweak<H>,
+    dst: &mut [Hash],
+    src: &[Hash],
+    count: usize,
+    chainlen: usize,
+) {
     dst[..count].copy_from_slice(&src[..count]);
-    for _ in 0..chainlen {
+    for j in 0..chainlen {
         for i in 0..count {
             let tmp = dst[i];
-            hash_n_to_n(&mut dst[i], &tmp);
+            tweak_n_to_n(&tweak.with_height(i).with_index(j), &mut dst[i], &tmp);
         }
     }
 }
 
+#[cfg(feature = "alloc")]
 #[inline(always)]
-pub fn hash_parallel_chains_all(dst: &mut [Hash], src: &[Hash], chainlen: usize) {
+pub fn hash_parallel_chains_all<H: HashFunction>(
+    tweak: &Tweak<H>,
+    dst: &mut [Hash],
+    src: &[Hash],
+    chainlen: usize,
+) {
     let count = dst.len();
-    hash_parallel_chains(dst, src, count, chainlen);
+    hash_parallel_chains(tweak, dst, src, count, chainlen);
 }
 
+#[cfg(feature = "alloc")]
 #[inline(always)]
-pub fn hash_compress_pairs(dst: &mut [Hash], src: &[Hash], count: usize) {
+pub fn hash_compress_pairs<H: HashFunction>(
+    tweak: &Tweak<H>,
+    dst: &mut [Hash],
+    src: &[Hash],
+    count: usize,
+) {
     for i in 0..count {
-        hash_2n_to_n(&mut dst[i], &src[2 * i], &src[2 * i + 1]);
+        tweak_2n_to_n(
+            &tweak.with_index(i),
+            &mut dst[i],
+            &src[2 * i],
+            &src[2 * i + 1],
+        );
     }
 }
 
@@ -131,18 +475,31 @@ pub mod tests {
               \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f",
     };
 
+    #[test]
+    fn test_hash_eq() {
+        let mut other = HASH_ELEMENT;
+        assert_eq!(other, HASH_ELEMENT);
+        assert!(bool::from(other.ct_eq(&HASH_ELEMENT)));
+        for i in [0, 15, 31] {
+            other.h[i] ^= 0x80;
+            assert_ne!(other, HASH_ELEMENT);
+            assert!(!bool::from(other.ct_eq(&HASH_ELEMENT)));
+            other.h[i] ^= 0x80;
+        }
+    }
+
     #[test]
     fn test_chain_0() {
         let src = HASH_ELEMENT;
-        let dst = hash_n_to_n_chain_ret(&src, 0);
+        let dst = hash_n_to_n_chain_ret(&Tweak::NONE, &src, 0, 0);
         assert_eq!(dst, src);
     }
 
     #[test]
     fn test_chain_1() {
         let src = HASH_ELEMENT;
-        let expect = hash_n_to_n_ret(&src);
-        let dst = hash_n_to_n_chain_ret(&src, 1);
+        let expect = Haraka::hash_n_to_n_ret(&src);
+        let dst = hash_n_to_n_chain_ret(&Tweak::NONE, &src, 0, 1);
         assert_eq!(dst, expect);
     }
 
@@ -160,32 +517,32 @@ pub mod tests {
                   \x8f\xe9\x76\xbe\xa2\xee\xb5\xce\
                   \x1c\x2e\xeb\xaa\xf7\x00\x46\x36",
         };
-        let dst = hash_n_to_n_chain_ret(&src, 5);
+        let dst = hash_n_to_n_chain_ret(&Tweak::NONE, &src, 0, 5);
         assert_eq!(dst, expect);
     }
 
     #[test]
     fn test_parallel() {
         let src = [HASH_ELEMENT; 5];
-        let expect = hash_n_to_n_ret(&HASH_ELEMENT);
+        let expect = Haraka::hash_n_to_n_ret(&HASH_ELEMENT);
         let mut dst = [Default::default(); 5];
-        hash_parallel_all(&mut dst, &src);
+        hash_parallel_all(&Tweak::NONE, &mut dst, &src);
         assert_eq!(dst, [expect; 5]);
     }
 
     #[test]
     fn test_parallel_mix() {
         let h0 = HASH_ELEMENT;
-        let h1 = hash_n_to_n_ret(&h0);
-        let h2 = hash_n_to_n_ret(&h1);
-        let h3 = hash_n_to_n_ret(&h2);
-        let h4 = hash_n_to_n_ret(&h3);
-        let h5 = hash_n_to_n_ret(&h4);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
+        let h4 = Haraka::hash_n_to_n_ret(&h3);
+        let h5 = Haraka::hash_n_to_n_ret(&h4);
 
         let src = [h0, h1, h2, h3, h4];
         let expect = [h1, h2, h3, h4, h5];
         let mut dst = [Default::default(); 5];
-        hash_parallel_all(&mut dst, &src);
+        hash_parallel_all(&Tweak::NONE, &mut dst, &src);
         assert_eq!(dst, expect);
     }
 
@@ -193,35 +550,37 @@ pub mod tests {
     fn test_parallel_chains_0() {
         let src = [HASH_ELEMENT; 5];
         let mut dst = [Default::default(); 5];
-        hash_parallel_chains_all(&mut dst, &src, 0);
+        hash_parallel_chains_all(&Tweak::NONE, &mut dst, &src, 0);
         assert_eq!(dst, src);
     }
 
     #[test]
     fn test_parallel_chains_1() {
         let src = [HASH_ELEMENT; 5];
-        let expect = hash_n_to_n_ret(&HASH_ELEMENT);
+        let expect = Haraka::hash_n_to_n_ret(&HASH_ELEMENT);
         let mut dst = [Default::default(); 5];
-        hash_parallel_chains_all(&mut dst, &src, 1);
+        hash_parallel_chains_all(&Tweak::NONE, &mut dst, &src, 1);
         assert_eq!(dst, [expect; 5]);
     }
 
     #[test]
     fn test_parallel_chains_3() {
         let src = [HASH_ELEMENT; 5];
-        let expect = hash_n_to_n_ret(&hash_n_to_n_ret(&hash_n_to_n_ret(&HASH_ELEMENT)));
+        let expect = Haraka::hash_n_to_n_ret(&Haraka::hash_n_to_n_ret(&Haraka::hash_n_to_n_ret(
+            &HASH_ELEMENT,
+        )));
         let mut dst = [Default::default(); 5];
-        hash_parallel_chains_all(&mut dst, &src, 3);
+        hash_parallel_chains_all(&Tweak::NONE, &mut dst, &src, 3);
         assert_eq!(dst, [expect; 5]);
     }
 
     #[test]
     fn test_compress_pairs_1() {
         let src = [HASH_ELEMENT; 2];
-        let expect = hash_2n_to_n_ret(&HASH_ELEMENT, &HASH_ELEMENT);
+        let expect = Haraka::hash_2n_to_n_ret(&HASH_ELEMENT, &HASH_ELEMENT);
 
         let mut dst = [Default::default(); 1];
-        hash_compress_pairs(&mut dst, &src, 1);
+        hash_compress_pairs(&Tweak::NONE, &mut dst, &src, 1);
 
         assert_eq!(dst, [expect]);
     }
@@ -229,87 +588,256 @@ pub mod tests {
     #[test]
     fn test_compress_pairs_2() {
         let src = [HASH_ELEMENT; 4];
-        let expect = hash_2n_to_n_ret(&HASH_ELEMENT, &HASH_ELEMENT);
+        let expect = Haraka::hash_2n_to_n_ret(&HASH_ELEMENT, &HASH_ELEMENT);
 
         let mut dst = [Default::default(); 2];
-        hash_compress_pairs(&mut dst, &src, 2);
+        hash_compress_pairs(&Tweak::NONE, &mut dst, &src, 2);
 
         assert_eq!(dst, [expect, expect]);
     }
 
-    use std::hint::black_box;
-    use test::Bencher;
+    #[test]
+    fn test_long_hash() {
+        assert_eq!(
+            Haraka::long_hash(b"abc").h,
+            *b"\xba\x78\x16\xbf\x8f\x01\xcf\xea\
+               \x41\x41\x40\xde\x5d\xae\x22\x23\
+               \xb0\x03\x61\xa3\x96\x17\x7a\x9c\
+               \xb4\x10\xff\x61\xf2\x00\x15\xad"
+        );
+        assert_eq!(Sha2::long_hash(b"abc"), Haraka::long_hash(b"abc"));
+        assert_eq!(
+            Shake::long_hash(b"").h,
+            *b"\x46\xb9\xdd\x2b\x0b\xa8\x8d\x13\
+               \x23\x3b\x3f\xeb\x74\x3e\xeb\x24\
+               \x3f\xcd\x52\xea\x62\xb8\x1b\x82\
+               \xb5\x0c\x27\x64\x6e\xd5\x76\x2f"
+        );
+    }
 
-    #[bench]
-    fn bench_chain_1(b: &mut Bencher) {
-        let src = HASH_ELEMENT;
-        b.iter(|| hash_n_to_n_chain_ret(black_box(&src), 1));
+    #[test]
+    fn test_sha2() {
+        let zero = Hash::default();
+        assert_eq!(
+            Sha2::hash_n_to_n_ret(&zero).h,
+            *b"\x66\x68\x7a\xad\xf8\x62\xbd\x77\
+               \x6c\x8f\xc1\x8b\x8e\x9f\x8e\x20\
+               \x08\x97\x14\x85\x6e\xe2\x33\xb3\
+               \x90\x2a\x59\x1d\x0d\x5f\x29\x25"
+        );
+        assert_eq!(
+            Sha2::hash_n_to_n_ret(&HASH_ELEMENT),
+            Sha2::long_hash(&HASH_ELEMENT.h)
+        );
+
+        // Compressing the padding block after the 64-byte hash gives the full SHA-256.
+        let h = Sha2::hash_2n_to_n_ret(&HASH_ELEMENT, &zero);
+        let mut state = [0; 8];
+        BigEndian::read_u32_into(&h.h, &mut state);
+        let mut padding = [0; 64];
+        padding[0] = 0x80;
+        BigEndian::write_u64(array_mut_ref![padding, 56, 8], 512);
+        sha2::compress256(&mut state, &[padding.into()]);
+        let mut full = Hash::default();
+        BigEndian::write_u32_into(&state, &mut full.h);
+
+        let mut input = [0; 64];
+        input[..32].copy_from_slice(&HASH_ELEMENT.h);
+        assert_eq!(full, Sha2::long_hash(&input));
     }
 
-    #[bench]
-    fn bench_chain_5(b: &mut Bencher) {
-        let src = HASH_ELEMENT;
-        b.iter(|| hash_n_to_n_chain_ret(black_box(&src), 5));
+    #[test]
+    fn test_shake() {
+        let h0 = HASH_ELEMENT;
+        let h1 = Shake::hash_n_to_n_ret(&h0);
+        assert_eq!(h1, Shake::long_hash(&h0.h));
+
+        let mut input = [0; 64];
+        input[..32].copy_from_slice(&h0.h);
+        input[32..].copy_from_slice(&h1.h);
+        assert_eq!(Shake::hash_2n_to_n_ret(&h0, &h1), Shake::long_hash(&input));
     }
 
-    #[bench]
-    fn bench_parallel_5(b: &mut Bencher) {
-        let src = [HASH_ELEMENT; 5];
-        b.iter(|| {
-            let mut dst = [Default::default(); 5];
-            hash_parallel_all(&mut dst, black_box(&src));
-            dst
-        });
+    #[test]
+    fn test_truncated() {
+        type H16 = Truncated<Haraka, 16>;
+        let h0 = HASH_ELEMENT;
+
+        let h1 = H16::hash_n_to_n_ret(&h0);
+        let mut expect = Haraka::hash_n_to_n_ret(&h0);
+        expect.h[16..].fill(0);
+        assert_eq!(h1, expect);
+
+        let h2 = Truncated::<Sha2, 24>::hash_2n_to_n_ret(&h0, &h1);
+        assert_eq!(h2.h[..24], Sha2::hash_2n_to_n_ret(&h0, &h1).h[..24]);
+        assert_eq!(h2.h[24..], [0; 8]);
+
+        let h3 = H16::long_hash(b"abc");
+        assert_eq!(h3.h[..16], Haraka::long_hash(b"abc").h[..16]);
+        assert_eq!(h3.h[16..], [0; 16]);
     }
 
-    #[bench]
-    fn bench_parallel_chains_5x5(b: &mut Bencher) {
-        let src = [HASH_ELEMENT; 5];
-        b.iter(|| {
-            let mut dst = [Default::default(); 5];
-            hash_parallel_chains_all(&mut dst, black_box(&src), 5);
-            dst
-        });
+    #[test]
+    fn test_serialize_truncated() {
+        let mut h = HASH_ELEMENT;
+        Truncated::<Haraka, 16>::truncate(&mut h);
+
+        let mut bytes = Vec::new();
+        h.serialize(16, &mut bytes);
+        assert_eq!(bytes, h.h[..16]);
+        assert_eq!(Hash::deserialize(16, &mut bytes.iter()), Some(h));
+        assert_eq!(Hash::from_slice(&bytes), h);
+        assert_eq!(Hash::deserialize(16, &mut bytes[1..].iter()), None);
     }
 
-    #[bench]
-    fn bench_parallel_columns_5x5(b: &mut Bencher) {
-        let src = [HASH_ELEMENT; 5];
-        b.iter(|| {
-            let mut dst = [Default::default(); 5];
-            for i in 0..5 {
-                hash_n_to_n_chain(&mut dst[i], black_box(&src[i]), 5);
-            }
-            dst
-        });
+    #[test]
+    fn test_tweak_none() {
+        let h = HASH_ELEMENT;
+        assert_eq!(
+            tweak_n_to_n_ret(&Tweak::NONE, &h),
+            Haraka::hash_n_to_n_ret(&h)
+        );
+        assert_eq!(
+            tweak_2n_to_n_ret(&Tweak::NONE, &h, &h),
+            Haraka::hash_2n_to_n_ret(&h, &h)
+        );
     }
 
-    #[bench]
-    fn bench_parallel_rows_5x5(b: &mut Bencher) {
-        let src = [HASH_ELEMENT; 5];
-        b.iter(|| {
-            let mut dst = black_box(src);
-            for _ in 0..5 {
-                let tmp = dst;
-                hash_parallel_all(&mut dst, &tmp);
-            }
-            dst
-        });
-    }
-
-    #[bench]
-    fn bench_parallel_mix_5(b: &mut Bencher) {
-        let h0 = HASH_ELEMENT;
-        let h1 = hash_n_to_n_ret(&h0);
-        let h2 = hash_n_to_n_ret(&h1);
-        let h3 = hash_n_to_n_ret(&h2);
-        let h4 = hash_n_to_n_ret(&h3);
+    #[test]
+    fn test_tweak_separation() {
+        let seed = HASH_ELEMENT;
+        let other_seed = Haraka::hash_n_to_n_ret(&seed);
+        let address = address::Address::new(1, 2);
+        let tweak = Tweak::<Haraka>::new(Some(&seed), &address, TweakKind::SubtreeNode);
+        let h = HASH_ELEMENT;
+
+        let expect = tweak_n_to_n_ret(&tweak, &h);
+        assert_ne!(expect, Haraka::hash_n_to_n_ret(&h));
+        let tweaks = [
+            Tweak::<Haraka>::new(Some(&other_seed), &address, TweakKind::SubtreeNode),
+            Tweak::<Haraka>::new(
+                Some(&seed),
+                &address::Address::new(0, 2),
+                TweakKind::SubtreeNode,
+            ),
+            Tweak::<Haraka>::new(
+                Some(&seed),
+                &address::Address::new(1, 3),
+                TweakKind::SubtreeNode,
+            ),
+            Tweak::<Haraka>::new(Some(&seed), &address, TweakKind::PorsNode),
+            tweak.with_height(1),
+            tweak.with_index(1),
+        ];
+        for t in tweaks.iter() {
+            assert_ne!(tweak_n_to_n_ret(t, &h), expect);
+            assert_ne!(
+                tweak_2n_to_n_ret(t, &h, &h),
+                tweak_2n_to_n_ret(&tweak, &h, &h)
+            );
+        }
+    }
 
-        let src = [h0, h1, h2, h3, h4];
-        b.iter(|| {
-            let mut dst = [Default::default(); 5];
-            hash_parallel_all(&mut dst, black_box(&src));
-            dst
-        });
+    #[test]
+    fn test_tweak_chain() {
+        let seed = HASH_ELEMENT;
+        let tweak = Tweak::<Haraka>::new(
+            Some(&seed),
+            &address::Address::new(0, 0),
+            TweakKind::WotsChain,
+        );
+        let src = HASH_ELEMENT;
+
+        let h2 = hash_n_to_n_chain_ret(&tweak, &src, 0, 2);
+        let h5 = hash_n_to_n_chain_ret(&tweak, &h2, 2, 3);
+        assert_eq!(hash_n_to_n_chain_ret(&tweak, &src, 0, 5), h5);
+        assert_ne!(hash_n_to_n_chain_ret(&tweak, &h2, 0, 3), h5);
+
+        let mut dst = [Default::default(); 3];
+        hash_parallel_chains_all(&tweak, &mut dst, &[src; 3], 5);
+        for (i, x) in dst.iter().enumerate() {
+            assert_eq!(*x, hash_n_to_n_chain_ret(&tweak.with_height(i), &src, 0, 5));
+        }
+    }
+
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+
+        use std::hint::black_box;
+        use test::Bencher;
+
+        #[bench]
+        fn bench_chain_1(b: &mut Bencher) {
+            let src = HASH_ELEMENT;
+            b.iter(|| hash_n_to_n_chain_ret(&Tweak::NONE, black_box(&src), 0, 1));
+        }
+
+        #[bench]
+        fn bench_chain_5(b: &mut Bencher) {
+            let src = HASH_ELEMENT;
+            b.iter(|| hash_n_to_n_chain_ret(&Tweak::NONE, black_box(&src), 0, 5));
+        }
+
+        #[bench]
+        fn bench_parallel_5(b: &mut Bencher) {
+            let src = [HASH_ELEMENT; 5];
+            b.iter(|| {
+                let mut dst = [Default::default(); 5];
+                hash_parallel_all(&Tweak::NONE, &mut dst, black_box(&src));
+                dst
+            });
+        }
+
+        #[bench]
+        fn bench_parallel_chains_5x5(b: &mut Bencher) {
+            let src = [HASH_ELEMENT; 5];
+            b.iter(|| {
+                let mut dst = [Default::default(); 5];
+                hash_parallel_chains_all(&Tweak::NONE, &mut dst, black_box(&src), 5);
+                dst
+            });
+        }
+
+        #[bench]
+        fn bench_parallel_columns_5x5(b: &mut Bencher) {
+            let src = [HASH_ELEMENT; 5];
+            b.iter(|| {
+                let mut dst = [Default::default(); 5];
+                for i in 0..5 {
+                    hash_n_to_n_chain(&Tweak::NONE, &mut dst[i], black_box(&src[i]), 0, 5);
+                }
+                dst
+            });
+        }
+
+        #[bench]
+        fn bench_parallel_rows_5x5(b: &mut Bencher) {
+            let src = [HASH_ELEMENT; 5];
+            b.iter(|| {
+                let mut dst = black_box(src);
+                for _ in 0..5 {
+                    let tmp = dst;
+                    hash_parallel_all(&Tweak::NONE, &mut dst, &tmp);
+                }
+                dst
+            });
+        }
+
+        #[bench]
+        fn bench_parallel_mix_5(b: &mut Bencher) {
+            let h0 = HASH_ELEMENT;
+            let h1 = Haraka::hash_n_to_n_ret(&h0);
+            let h2 = Haraka::hash_n_to_n_ret(&h1);
+            let h3 = Haraka::hash_n_to_n_ret(&h2);
+            let h4 = Haraka::hash_n_to_n_ret(&h3);
+
+            let src = [h0, h1, h2, h3, h4];
+            b.iter(|| {
+                let mut dst = [Default::default(); 5];
+                hash_parallel_all(&Tweak::NONE, &mut dst, black_box(&src));
+                dst
+            });
+        }
     }
 }
@@ -1,44 +1,80 @@
-#![allow(incomplete_features)]
-#![feature(array_try_from_fn, generic_const_exprs)]
-#![cfg_attr(test, feature(test))]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(all(test, feature = "bench"), feature(test))]
 
-#[cfg(test)]
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+#[cfg(all(test, feature = "bench"))]
 extern crate test;
 
 mod address;
+mod array;
+#[cfg(feature = "alloc")]
+mod batch;
 mod config;
+mod counter;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
 mod gravity;
 mod hash;
 mod ltree;
 mod merkle;
+#[cfg(feature = "alloc")]
+mod merkle_tree;
 mod octopus;
+#[cfg(feature = "alloc")]
+mod pkcs;
 mod pors;
+// The Haraka primitives run AES-NI instructions unconditionally, so they are only public when
+// the target is known to have them.
+#[cfg(target_feature = "aes")]
+pub mod primitives;
+#[cfg(not(target_feature = "aes"))]
 mod primitives;
 mod prng;
+#[cfg(feature = "serde")]
+mod serde_impls;
+mod stateful;
 mod subtree;
 mod wots;
 
-pub use config::{GravityLarge, GravityMedium, GravityParams, GravitySmall};
+pub use array::Array;
+#[cfg(feature = "alloc")]
+pub use batch::BatchProof;
+pub use config::{
+    GravityLarge, GravityMedium, GravityParams, GravitySmall, GravitySmall128, GravitySmall192,
+    GravitySmallSha2, GravitySmallShake, GravitySmallTweaked,
+};
+#[cfg(feature = "alloc")]
+pub use counter::CountingSecKey;
+pub use counter::{SignatureCounter, security_bits};
+pub use gravity::{FINGERPRINT_BYTES, PubKey, Signature, recover_public_key};
+#[cfg(feature = "alloc")]
+pub use gravity::{SecKey, SecKeyBuilder};
+pub use hash::{Haraka, Hash, HashFunction, Sha2, Shake, Truncated};
+#[cfg(feature = "alloc")]
+pub use merkle_tree::{InclusionProof, MerkleTree, MultiProof};
+pub use prng::{AesCtrPrng, ChaCha20Prng, SecretPrng, ShakePrng};
+#[cfg(feature = "std")]
+pub use stateful::StateFile;
+#[cfg(feature = "alloc")]
+pub use stateful::StatefulSecKey;
+pub use stateful::{Reservation, State, StatefulPubKey, StatefulSignature};
+pub use subtle::Choice;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
 
-pub fn gravity_genpk<P: GravityParams>(public: &mut [u8; 32], secret: &[u8; 64])
-where
-    [(); P::GRAVITY_D]:,
-    [(); P::GRAVITY_C]:,
-    [(); P::MERKLE_H]:,
-    [(); P::PORS_K]:,
-{
+#[cfg(feature = "alloc")]
+pub fn gravity_genpk<P: GravityParams>(secret: &[u8; 64]) -> Vec<u8> {
     let sk = gravity::SecKey::<P>::new(secret);
     let pk = sk.genpk();
-    *public = pk.get_bytes();
+    pk.get_bytes().to_vec()
 }
 
-pub fn gravity_sign<P: GravityParams>(secret: &[u8; 64], msg: &[u8]) -> Vec<u8>
-where
-    [(); P::GRAVITY_D]:,
-    [(); P::GRAVITY_C]:,
-    [(); P::MERKLE_H]:,
-    [(); P::PORS_K]:,
-{
+#[cfg(feature = "alloc")]
+pub fn gravity_sign<P: GravityParams>(secret: &[u8; 64], msg: &[u8]) -> Vec<u8> {
     let sk = gravity::SecKey::<P>::new(secret);
     let sign = sk.sign_bytes(msg);
     let mut sign_bytes = Vec::<u8>::new();
@@ -46,14 +82,11 @@ where
     sign_bytes
 }
 
-pub fn gravity_verify<P: GravityParams>(public: &[u8; 32], msg: &[u8], sign_bytes: Vec<u8>) -> bool
-where
-    [(); P::GRAVITY_D]:,
-    [(); P::GRAVITY_C]:,
-    [(); P::MERKLE_H]:,
-    [(); P::PORS_K]:,
-{
-    let pk = gravity::PubKey::<P>::new(*public);
+#[cfg(feature = "alloc")]
+pub fn gravity_verify<P: GravityParams>(public: &[u8], msg: &[u8], sign_bytes: Vec<u8>) -> bool {
+    let Some(pk) = gravity::PubKey::<P>::new(public) else {
+        return false;
+    };
     if let Some(sign) = gravity::Signature::<P>::deserialize(&mut sign_bytes.iter()) {
         pk.verify_bytes(&sign, msg)
     } else {
@@ -64,6 +97,7 @@ where
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::config::{SmallChaCha20, SmallShakePrng, SmallW4, SmallW256};
 
     macro_rules! param_tests {
         (
@@ -85,6 +119,7 @@ pub mod tests {
         };
     }
 
+    #[cfg(feature = "bench")]
     macro_rules! param_benches {
         (
             $mod:ident,
@@ -105,7 +140,9 @@ pub mod tests {
         };
     }
 
-    pub(crate) use {param_benches, param_tests};
+    #[cfg(feature = "bench")]
+    pub(crate) use param_benches;
+    pub(crate) use param_tests;
 
     macro_rules! all_tests {
         ( $mod:ident, $params:ty ) => {
@@ -116,14 +153,49 @@ pub mod tests {
     all_tests!(small, GravitySmall);
     all_tests!(medium, GravityMedium);
     all_tests!(large, GravityLarge);
+    all_tests!(small_tweaked, GravitySmallTweaked);
+    all_tests!(small_sha2, GravitySmallSha2);
+    all_tests!(small_shake, GravitySmallShake);
+    all_tests!(small_128, GravitySmall128);
+    all_tests!(small_192, GravitySmall192);
+    all_tests!(small_chacha20, SmallChaCha20);
+    all_tests!(small_shake_prng, SmallShakePrng);
+    all_tests!(small_w4, SmallW4);
+    all_tests!(small_w256, SmallW256);
+
+    // The verifier is the same code for all parameter sets, so the allocations and the stack are
+    // only checked with the small ones, whose keys are fast to generate.
+    crate::tests::param_tests!(no_alloc_small, GravitySmall, test_verify_no_alloc,);
+    crate::tests::param_tests!(
+        no_alloc_small_tweaked,
+        GravitySmallTweaked,
+        test_verify_no_alloc,
+    );
+    crate::tests::param_tests!(no_alloc_small_sha2, GravitySmallSha2, test_verify_no_alloc,);
+    crate::tests::param_tests!(
+        no_alloc_small_shake,
+        GravitySmallShake,
+        test_verify_no_alloc,
+    );
+    crate::tests::param_tests!(no_alloc_small_128, GravitySmall128, test_verify_no_alloc,);
+    crate::tests::param_tests!(no_alloc_small_192, GravitySmall192, test_verify_no_alloc,);
+    crate::tests::param_tests!(no_alloc_small_w4, SmallW4, test_verify_no_alloc,);
+    crate::tests::param_tests!(no_alloc_small_w256, SmallW256, test_verify_no_alloc,);
+
+    #[test]
+    fn test_prng_changes_keys() {
+        let secret = [0; 64];
+        let public = [
+            gravity_genpk::<GravitySmall>(&secret),
+            gravity_genpk::<SmallChaCha20>(&secret),
+            gravity_genpk::<SmallShakePrng>(&secret),
+        ];
+        assert_ne!(public[0], public[1]);
+        assert_ne!(public[0], public[2]);
+        assert_ne!(public[1], public[2]);
+    }
 
-    fn test_sign_verify<P: GravityParams>()
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
+    fn test_sign_verify<P: GravityParams>() {
         let secret: &[u8; 64] = b"\x00\x01\x02\x03\x04\x05\x06\x07\
                                   \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
                                   \x10\x11\x12\x13\x14\x15\x16\x17\
@@ -134,9 +206,68 @@ pub mod tests {
                                   \x38\x39\x3a\x3b\x3c\x3d\x3e\x3f";
         let msg: &[u8] = b"Hello world";
 
-        let mut public = [0; 32];
-        gravity_genpk::<P>(&mut public, secret);
-        let sign = gravity_sign(secret, msg);
-        assert!(gravity_verify(&public, msg, sign));
+        let public = gravity_genpk::<P>(secret);
+        assert_eq!(public.len(), P::HASH_BYTES);
+        let sign = gravity_sign::<P>(secret, msg);
+        assert!(gravity_verify::<P>(&public, msg, sign.clone()));
+
+        // Public keys with padding or truncated are rejected.
+        let mut padded = public.clone();
+        padded.push(0);
+        assert!(!gravity_verify::<P>(&padded, msg, sign.clone()));
+        assert!(!gravity_verify::<P>(
+            &public[..P::HASH_BYTES - 1],
+            msg,
+            sign
+        ));
+    }
+
+    // Counts the allocations made by each thread, so that tests running in parallel don't
+    // interfere with each other.
+    struct CountingAllocator;
+
+    std::thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            ALLOCATIONS.with(|x| x.set(x.get() + 1));
+            unsafe { std::alloc::System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            unsafe { std::alloc::System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn test_verify_no_alloc<P: GravityParams>() {
+        let secret = [0; 64];
+        let msg: &[u8] = b"Hello world";
+
+        let sk = SecKey::<P>::new(&secret);
+        let public = sk.genpk().get_bytes().to_vec();
+        let mut sign = Vec::new();
+        sk.sign_bytes(msg).serialize(&mut sign);
+
+        // Run the verification on a thread with a small stack, to check the documented bound.
+        let stack_size = if P::WOTS_W < 16 { 32 * 1024 } else { 16 * 1024 };
+        let valid = std::thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || {
+                let pk = PubKey::<P>::new(&public).unwrap();
+                let before = ALLOCATIONS.with(|x| x.get());
+                let valid = pk.verify_serialized(&sign, msg);
+                let after = ALLOCATIONS.with(|x| x.get());
+                assert_eq!(before, after);
+                valid
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(valid);
     }
 }
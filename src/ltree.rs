@@ -1,130 +1,132 @@
 use crate::hash;
-use crate::hash::Hash;
-use std::mem;
+use crate::hash::{Hash, HashFunction, Tweak};
 
-fn ltree(root: &mut Hash, buf: &mut [Hash], mut count: usize) {
-    let (mut dst, mut src) = buf.split_at_mut(count);
+// The tree is computed in place, so that no allocation is needed.
+#[allow(clippy::needless_range_loop)]
+pub fn ltree_leaves_mut<H: HashFunction>(tweak: &Tweak<H>, leaves: &mut [Hash]) -> Hash {
+    let mut count = leaves.len();
+    let mut height = 0;
 
     while count > 1 {
-        mem::swap(&mut dst, &mut src);
-
+        height += 1;
+        let tweak = tweak.with_height(height);
         let mut newcount = count >> 1;
-        hash::hash_compress_pairs(dst, src, newcount);
+        for i in 0..newcount {
+            leaves[i] =
+                hash::tweak_2n_to_n_ret(&tweak.with_index(i), &leaves[2 * i], &leaves[2 * i + 1]);
+        }
         if count & 1 != 0 {
-            dst[newcount] = src[count - 1];
+            leaves[newcount] = leaves[count - 1];
             newcount += 1;
         }
 
         count = newcount;
     }
 
-    *root = dst[0]
-}
-
-pub fn ltree_leaves(root: &mut Hash, leaves: &[Hash]) {
-    let count = leaves.len();
-    let mut buf = vec![Default::default(); 2 * count];
-
-    buf[..count].copy_from_slice(&leaves[..count]);
-
-    ltree(root, buf.as_mut_slice(), count)
+    leaves[0]
 }
 
-pub fn ltree_leaves_ret(leaves: &[Hash]) -> Hash {
-    let mut root = Default::default();
-    ltree_leaves(&mut root, leaves);
-    root
+#[cfg(test)]
+pub fn ltree_leaves_ret<H: HashFunction>(tweak: &Tweak<H>, leaves: &[Hash]) -> Hash {
+    let mut buf = leaves.to_vec();
+    ltree_leaves_mut(tweak, &mut buf)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hash::Haraka;
 
     // Notation for these tests: H(h_i, h_j) = h_{2^i*3^j}
     #[test]
     fn test_ltree_1() {
         let h0 = hash::tests::HASH_ELEMENT;
 
-        let dst = ltree_leaves_ret(&[h0; 1]);
+        let dst = ltree_leaves_ret(&Tweak::NONE, &[h0; 1]);
         assert_eq!(dst, h0);
     }
 
     #[test]
     fn test_ltree_2() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_2n_to_n_ret(&h0, &h0);
+        let h1 = Haraka::hash_2n_to_n_ret(&h0, &h0);
 
-        let dst = ltree_leaves_ret(&[h0; 2]);
+        let dst = ltree_leaves_ret(&Tweak::NONE, &[h0; 2]);
         assert_eq!(dst, h1);
     }
 
     #[test]
     fn test_ltree_3() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_2n_to_n_ret(&h0, &h0);
-        let h2 = hash::hash_2n_to_n_ret(&h1, &h0);
+        let h1 = Haraka::hash_2n_to_n_ret(&h0, &h0);
+        let h2 = Haraka::hash_2n_to_n_ret(&h1, &h0);
 
-        let dst = ltree_leaves_ret(&[h0; 3]);
+        let dst = ltree_leaves_ret(&Tweak::NONE, &[h0; 3]);
         assert_eq!(dst, h2);
     }
 
     #[test]
     fn test_ltree_4() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_2n_to_n_ret(&h0, &h0);
-        let h6 = hash::hash_2n_to_n_ret(&h1, &h1);
+        let h1 = Haraka::hash_2n_to_n_ret(&h0, &h0);
+        let h6 = Haraka::hash_2n_to_n_ret(&h1, &h1);
 
-        let dst = ltree_leaves_ret(&[h0; 4]);
+        let dst = ltree_leaves_ret(&Tweak::NONE, &[h0; 4]);
         assert_eq!(dst, h6);
     }
 
     #[test]
     fn test_ltree_5() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_2n_to_n_ret(&h0, &h0);
-        let h6 = hash::hash_2n_to_n_ret(&h1, &h1);
-        let h64 = hash::hash_2n_to_n_ret(&h6, &h0);
+        let h1 = Haraka::hash_2n_to_n_ret(&h0, &h0);
+        let h6 = Haraka::hash_2n_to_n_ret(&h1, &h1);
+        let h64 = Haraka::hash_2n_to_n_ret(&h6, &h0);
 
-        let dst = ltree_leaves_ret(&[h0; 5]);
+        let dst = ltree_leaves_ret(&Tweak::NONE, &[h0; 5]);
         assert_eq!(dst, h64);
     }
 
     #[test]
     fn test_ltree_6() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_2n_to_n_ret(&h0, &h0);
-        let h6 = hash::hash_2n_to_n_ret(&h1, &h1);
-        let h192 = hash::hash_2n_to_n_ret(&h6, &h1);
+        let h1 = Haraka::hash_2n_to_n_ret(&h0, &h0);
+        let h6 = Haraka::hash_2n_to_n_ret(&h1, &h1);
+        let h192 = Haraka::hash_2n_to_n_ret(&h6, &h1);
 
-        let dst = ltree_leaves_ret(&[h0; 6]);
+        let dst = ltree_leaves_ret(&Tweak::NONE, &[h0; 6]);
         assert_eq!(dst, h192);
     }
 
-    use super::super::config;
-    use std::hint::black_box;
-    use test::Bencher;
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
 
-    #[bench]
-    fn bench_ltree_1(b: &mut Bencher) {
-        let h = [hash::tests::HASH_ELEMENT; 1];
-        b.iter(|| ltree_leaves_ret(black_box(&h)));
-    }
+        use crate::config::{GravityParams, GravitySmall};
+        use std::hint::black_box;
+        use test::Bencher;
 
-    #[bench]
-    fn bench_ltree_5(b: &mut Bencher) {
-        let h = [hash::tests::HASH_ELEMENT; 5];
-        b.iter(|| ltree_leaves_ret(black_box(&h)));
-    }
+        #[bench]
+        fn bench_ltree_1(b: &mut Bencher) {
+            let h = [hash::tests::HASH_ELEMENT; 1];
+            b.iter(|| ltree_leaves_ret(&Tweak::NONE, black_box(&h)));
+        }
 
-    #[bench]
-    fn bench_ltree_20(b: &mut Bencher) {
-        let h = [hash::tests::HASH_ELEMENT; 20];
-        b.iter(|| ltree_leaves_ret(black_box(&h)));
-    }
+        #[bench]
+        fn bench_ltree_5(b: &mut Bencher) {
+            let h = [hash::tests::HASH_ELEMENT; 5];
+            b.iter(|| ltree_leaves_ret(&Tweak::NONE, black_box(&h)));
+        }
 
-    #[bench]
-    fn bench_ltree_wots(b: &mut Bencher) {
-        let h = [hash::tests::HASH_ELEMENT; config::WOTS_ELL];
-        b.iter(|| ltree_leaves_ret(black_box(&h)));
+        #[bench]
+        fn bench_ltree_20(b: &mut Bencher) {
+            let h = [hash::tests::HASH_ELEMENT; 20];
+            b.iter(|| ltree_leaves_ret(&Tweak::NONE, black_box(&h)));
+        }
+
+        #[bench]
+        fn bench_ltree_wots(b: &mut Bencher) {
+            let h = [hash::tests::HASH_ELEMENT; GravitySmall::WOTS_ELL];
+            b.iter(|| ltree_leaves_ret(&Tweak::NONE, black_box(&h)));
+        }
     }
 }
@@ -1,16 +1,32 @@
 use crate::hash;
-use crate::hash::Hash;
-use std::mem;
-
+use crate::hash::{Hash, HashFunction, Tweak};
+#[cfg(feature = "alloc")]
+use alloc::vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+use core::mem;
+
+// Trees may have any number of leaves. In a layer with an odd number of nodes, the last node has
+// no sibling, and is promoted to the next layer as is, like in the L-tree.
+#[cfg(feature = "alloc")]
 pub struct MerkleBuf {
     height: usize,
+    count: usize,
     buf: Vec<Hash>,
 }
 
+#[cfg(feature = "alloc")]
 impl MerkleBuf {
     pub fn new(height: usize) -> Self {
+        Self::with_leaf_count(1 << height)
+    }
+
+    pub fn with_leaf_count(count: usize) -> Self {
+        let height = tree_height(count);
         Self {
             height,
+            count,
             buf: vec![Default::default(); 1 << (height + 1)],
         }
     }
@@ -19,12 +35,16 @@ impl MerkleBuf {
         self.height
     }
 
+    pub fn leaf_count(&self) -> usize {
+        self.count
+    }
+
     pub fn split_half_mut(&mut self) -> (&mut [Hash], &mut [Hash]) {
         self.buf.as_mut_slice().split_at_mut(1 << self.height)
     }
 
     pub fn slice_leaves_mut(&mut self) -> &mut [Hash] {
-        &mut self.buf[..(1 << self.height)]
+        &mut self.buf[..self.count]
     }
 
     #[cfg(test)]
@@ -33,111 +53,212 @@ impl MerkleBuf {
     }
 }
 
+#[cfg(feature = "alloc")]
 pub struct MerkleTree {
     height: usize,
+    count: usize,
     nodes: Vec<Hash>,
 }
 
+#[cfg(feature = "alloc")]
 impl MerkleTree {
     pub fn new(height: usize) -> Self {
+        Self::with_leaf_count(1 << height)
+    }
+
+    pub fn with_leaf_count(count: usize) -> Self {
+        let height = tree_height(count);
         Self {
             height,
+            count,
             nodes: vec![Default::default(); 1 << (height + 1)],
         }
     }
 
     pub fn leaves(&mut self) -> &mut [Hash] {
         let n = 1 << self.height;
-        &mut self.nodes[n..(2 * n)]
+        &mut self.nodes[n..(n + self.count)]
     }
 
-    pub fn generate(&mut self) {
+    pub fn get_leaves(&self) -> &[Hash] {
+        let n = 1 << self.height;
+        &self.nodes[n..(n + self.count)]
+    }
+
+    pub fn generate<H: HashFunction>(&mut self, tweak: &Tweak<H>) {
+        let mut count = self.count;
         for i in 0..self.height {
             let n = 1 << (self.height - 1 - i);
-            {
-                let (dst, src) = self.nodes.split_at_mut(2 * n);
-                hash::hash_compress_pairs(&mut dst[n..(2 * n)], src, n);
-            }
+            let (dst, src) = self.nodes.split_at_mut(2 * n);
+            count = compress_layer(&tweak.with_height(i + 1), &mut dst[n..], src, count);
         }
     }
 
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
     pub fn root(&self) -> Hash {
         self.nodes[1]
     }
 
-    #[allow(clippy::needless_range_loop)]
-    pub fn gen_auth(&self, auth: &mut [Hash], mut index: usize) {
+    // Node at the given index of the layer at the given height above the leaves.
+    pub fn node(&self, height: usize, index: usize) -> Hash {
+        self.nodes[(1 << (self.height - height)) + index]
+    }
+
+    // Writes the authentication path of the leaf at `index`, and returns its length, which is
+    // the height of the tree unless some nodes of the path are promoted.
+    pub fn gen_auth(&self, auth: &mut [Hash], mut index: usize) -> usize {
         let mut n = 1 << self.height;
-        for l in 0..self.height {
-            // Copy auth path
+        let mut count = self.count;
+        let mut len = 0;
+        for _ in 0..self.height {
             let sibling = index ^ 1;
-            auth[l] = self.nodes[n + sibling];
+            if sibling < count {
+                auth[len] = self.nodes[n + sibling];
+                len += 1;
+            }
             index >>= 1;
             n >>= 1;
+            count = count.div_ceil(2);
+        }
+        len
+    }
+}
+
+// Height of a tree of `count` leaves, for `count > 0`.
+pub fn tree_height(count: usize) -> usize {
+    count.next_power_of_two().trailing_zeros() as usize
+}
+
+// Length of the authentication path of the leaf at `index`, in a tree of `count` leaves.
+#[cfg(feature = "alloc")]
+pub fn auth_len(mut count: usize, mut index: usize) -> usize {
+    let mut len = 0;
+    while count > 1 {
+        if index ^ 1 < count {
+            len += 1;
         }
+        index >>= 1;
+        count = count.div_ceil(2);
+    }
+    len
+}
+
+// Computes the layer above the `count` nodes of `src` into `dst`, and returns its number of nodes.
+#[cfg(feature = "alloc")]
+pub fn compress_layer<H: HashFunction>(
+    tweak: &Tweak<H>,
+    dst: &mut [Hash],
+    src: &[Hash],
+    count: usize,
+) -> usize {
+    hash::hash_compress_pairs(tweak, dst, src, count / 2);
+    if count % 2 == 1 {
+        dst[count / 2] = src[count - 1];
     }
+    count.div_ceil(2)
 }
 
-pub fn merkle_compress_all(root: &mut Hash, buf: &mut MerkleBuf) {
+#[cfg(feature = "alloc")]
+pub fn merkle_compress_all<H: HashFunction>(
+    tweak: &Tweak<H>,
+    root: &mut Hash,
+    buf: &mut MerkleBuf,
+) {
     let height = buf.height();
-    let mut n = 1 << height;
+    let mut count = buf.leaf_count();
     let (mut dst, mut src) = buf.split_half_mut();
 
-    for _ in 0..height {
+    for l in 0..height {
         mem::swap(&mut dst, &mut src);
-        n >>= 1;
-        hash::hash_compress_pairs(dst, src, n);
+        count = compress_layer(&tweak.with_height(l + 1), dst, src, count);
     }
 
     *root = dst[0]
 }
 
 #[cfg(test)]
-pub fn merkle_compress_all_leaves(leaves: &[Hash], height: usize) -> Hash {
-    let count = leaves.len();
-    assert_eq!(count, 1 << height);
-
-    let mut buf = MerkleBuf::new(height);
+pub fn merkle_compress_all_leaves<H: HashFunction>(tweak: &Tweak<H>, leaves: &[Hash]) -> Hash {
+    let mut buf = MerkleBuf::with_leaf_count(leaves.len());
     buf.fill_leaves(leaves);
 
     let mut root = Default::default();
-    merkle_compress_all(&mut root, &mut buf);
+    merkle_compress_all(tweak, &mut root, &mut buf);
     root
 }
 
-#[allow(clippy::needless_range_loop)]
-pub fn merkle_gen_auth(auth: &mut [Hash], buf: &mut MerkleBuf, mut index: usize) -> Hash {
+// Writes the authentication path of the leaf at `index` like `MerkleTree::gen_auth`, and returns
+// the root.
+#[cfg(feature = "alloc")]
+pub fn merkle_gen_auth<H: HashFunction>(
+    tweak: &Tweak<H>,
+    auth: &mut [Hash],
+    buf: &mut MerkleBuf,
+    mut index: usize,
+) -> Hash {
     let height = buf.height();
-    let mut n = 1 << height;
+    let mut count = buf.leaf_count();
     let (mut dst, mut src) = buf.split_half_mut();
+    let mut len = 0;
 
     for l in 0..height {
         // Copy auth path
         let sibling = index ^ 1;
-        auth[l] = dst[sibling];
+        if sibling < count {
+            auth[len] = dst[sibling];
+            len += 1;
+        }
         index >>= 1;
 
         // Compute next layer
         mem::swap(&mut dst, &mut src);
-        n >>= 1;
-        hash::hash_compress_pairs(dst, src, n);
+        count = compress_layer(&tweak.with_height(l + 1), dst, src, count);
     }
 
     dst[0]
 }
 
+// Computes the root of the subtree of the given height and index depth-first, from the leaves
+// returned by `leaf` in a tree of `count` leaves. Only O(height) nodes are alive at any time.
+#[cfg(feature = "alloc")]
+pub fn merkle_subtree_root<H: HashFunction, F>(
+    tweak: &Tweak<H>,
+    leaf: &F,
+    count: usize,
+    height: usize,
+    index: usize,
+) -> Hash
+where
+    F: Fn(usize) -> Hash,
+{
+    if height == 0 {
+        return leaf(index);
+    }
+    let left = merkle_subtree_root(tweak, leaf, count, height - 1, 2 * index);
+    if (2 * index + 1) << (height - 1) >= count {
+        // The right subtree is empty, so the left one is promoted.
+        return left;
+    }
+    let right = merkle_subtree_root(tweak, leaf, count, height - 1, 2 * index + 1);
+    hash::tweak_2n_to_n_ret(&tweak.with_height(height).with_index(index), &left, &right)
+}
+
 #[allow(clippy::needless_range_loop)]
-pub fn merkle_compress_auth(
+pub fn merkle_compress_auth<H: HashFunction>(
+    tweak: &Tweak<H>,
     node: &mut Hash,
     auth: &[Hash],
     height_diff: usize,
     mut index: usize,
 ) -> usize {
     for l in 0..height_diff {
+        let tweak = tweak.with_height(l + 1).with_index(index >> 1);
         if index & 1 == 0 {
-            *node = hash::hash_2n_to_n_ret(node, &auth[l])
+            *node = hash::tweak_2n_to_n_ret(&tweak, node, &auth[l])
         } else {
-            *node = hash::hash_2n_to_n_ret(&auth[l], node)
+            *node = hash::tweak_2n_to_n_ret(&tweak, &auth[l], node)
         }
         index >>= 1;
     }
@@ -145,9 +266,47 @@ pub fn merkle_compress_auth(
     index
 }
 
+// Computes the root of a tree of `count` leaves from the leaf `node` at `index` and its
+// authentication path, as written by `MerkleTree::gen_auth`. Returns `None` if `auth` doesn't
+// have the length of this path.
+#[cfg(feature = "alloc")]
+pub fn merkle_compress_auth_count<H: HashFunction>(
+    tweak: &Tweak<H>,
+    node: &Hash,
+    auth: &[Hash],
+    mut count: usize,
+    mut index: usize,
+) -> Option<Hash> {
+    let mut node = *node;
+    let mut auth = auth.iter();
+    let mut l = 0;
+    while count > 1 {
+        l += 1;
+        if index ^ 1 < count {
+            let tweak = tweak.with_height(l).with_index(index >> 1);
+            let sibling = auth.next()?;
+            if index & 1 == 0 {
+                node = hash::tweak_2n_to_n_ret(&tweak, &node, sibling)
+            } else {
+                node = hash::tweak_2n_to_n_ret(&tweak, sibling, &node)
+            }
+        }
+        index >>= 1;
+        count = count.div_ceil(2);
+    }
+
+    if auth.next().is_none() {
+        Some(node)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hash::Haraka;
+    use crate::ltree;
 
     fn merkle_gen_auth_leaves(
         auth: &mut [Hash],
@@ -161,7 +320,7 @@ mod tests {
         let mut buf = MerkleBuf::new(height);
         buf.fill_leaves(leaves);
 
-        merkle_gen_auth(auth, &mut buf, index)
+        merkle_gen_auth(&Tweak::NONE, auth, &mut buf, index)
     }
 
     // Notation for these tests: H(h_i, h_i) = h_{i+1}
@@ -170,71 +329,90 @@ mod tests {
         let h0 = hash::tests::HASH_ELEMENT;
 
         let src = [h0; 1];
-        let dst = merkle_compress_all_leaves(&src, 0);
+        let dst = merkle_compress_all_leaves(&Tweak::NONE, &src);
         assert_eq!(dst, h0);
     }
 
     #[test]
     fn test_merkle_compress_all_1() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_2n_to_n_ret(&h0, &h0);
+        let h1 = Haraka::hash_2n_to_n_ret(&h0, &h0);
 
         let src = [h0; 2];
-        let dst = merkle_compress_all_leaves(&src, 1);
+        let dst = merkle_compress_all_leaves(&Tweak::NONE, &src);
         assert_eq!(dst, h1);
     }
 
     #[test]
     fn test_merkle_compress_all_2() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_2n_to_n_ret(&h0, &h0);
-        let h2 = hash::hash_2n_to_n_ret(&h1, &h1);
+        let h1 = Haraka::hash_2n_to_n_ret(&h0, &h0);
+        let h2 = Haraka::hash_2n_to_n_ret(&h1, &h1);
 
         let src = [h0; 4];
-        let dst = merkle_compress_all_leaves(&src, 2);
+        let dst = merkle_compress_all_leaves(&Tweak::NONE, &src);
         assert_eq!(dst, h2);
     }
 
     #[test]
     fn test_merkle_compress_all_3() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_2n_to_n_ret(&h0, &h0);
-        let h2 = hash::hash_2n_to_n_ret(&h1, &h1);
-        let h3 = hash::hash_2n_to_n_ret(&h2, &h2);
+        let h1 = Haraka::hash_2n_to_n_ret(&h0, &h0);
+        let h2 = Haraka::hash_2n_to_n_ret(&h1, &h1);
+        let h3 = Haraka::hash_2n_to_n_ret(&h2, &h2);
 
         let src = [h0; 8];
-        let dst = merkle_compress_all_leaves(&src, 3);
+        let dst = merkle_compress_all_leaves(&Tweak::NONE, &src);
         assert_eq!(dst, h3);
     }
 
     #[test]
     fn test_merkle_compress_all_mixed() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_n_to_n_ret(&h0);
-        let h2 = hash::hash_n_to_n_ret(&h1);
-        let h3 = hash::hash_n_to_n_ret(&h2);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
 
-        let h4 = hash::hash_2n_to_n_ret(&h0, &h1);
-        let h5 = hash::hash_2n_to_n_ret(&h2, &h3);
+        let h4 = Haraka::hash_2n_to_n_ret(&h0, &h1);
+        let h5 = Haraka::hash_2n_to_n_ret(&h2, &h3);
 
-        let h6 = hash::hash_2n_to_n_ret(&h4, &h5);
+        let h6 = Haraka::hash_2n_to_n_ret(&h4, &h5);
 
         let src = [h0, h1, h2, h3];
-        let dst = merkle_compress_all_leaves(&src, 2);
+        let dst = merkle_compress_all_leaves(&Tweak::NONE, &src);
         assert_eq!(dst, h6);
     }
 
+    #[test]
+    fn test_merkle_subtree_root() {
+        let h0 = hash::tests::HASH_ELEMENT;
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
+
+        let h4 = Haraka::hash_2n_to_n_ret(&h0, &h1);
+        let h5 = Haraka::hash_2n_to_n_ret(&h2, &h3);
+
+        let h6 = Haraka::hash_2n_to_n_ret(&h4, &h5);
+
+        let src = [h0, h1, h2, h3];
+        let leaf = |i: usize| src[i];
+        assert_eq!(merkle_subtree_root(&Tweak::NONE, &leaf, 4, 0, 2), h2);
+        assert_eq!(merkle_subtree_root(&Tweak::NONE, &leaf, 4, 1, 1), h5);
+        assert_eq!(merkle_subtree_root(&Tweak::NONE, &leaf, 4, 2, 0), h6);
+    }
+
     #[test]
     fn test_merkle_gen_auth_0() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_n_to_n_ret(&h0);
-        let h2 = hash::hash_n_to_n_ret(&h1);
-        let h3 = hash::hash_n_to_n_ret(&h2);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
 
-        let h4 = hash::hash_2n_to_n_ret(&h0, &h1);
-        let h5 = hash::hash_2n_to_n_ret(&h2, &h3);
+        let h4 = Haraka::hash_2n_to_n_ret(&h0, &h1);
+        let h5 = Haraka::hash_2n_to_n_ret(&h2, &h3);
 
-        let h6 = hash::hash_2n_to_n_ret(&h4, &h5);
+        let h6 = Haraka::hash_2n_to_n_ret(&h4, &h5);
 
         let src = [h0, h1, h2, h3];
         let mut auth = [Default::default(); 2];
@@ -246,14 +424,14 @@ mod tests {
     #[test]
     fn test_merkle_gen_auth_1() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_n_to_n_ret(&h0);
-        let h2 = hash::hash_n_to_n_ret(&h1);
-        let h3 = hash::hash_n_to_n_ret(&h2);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
 
-        let h4 = hash::hash_2n_to_n_ret(&h0, &h1);
-        let h5 = hash::hash_2n_to_n_ret(&h2, &h3);
+        let h4 = Haraka::hash_2n_to_n_ret(&h0, &h1);
+        let h5 = Haraka::hash_2n_to_n_ret(&h2, &h3);
 
-        let h6 = hash::hash_2n_to_n_ret(&h4, &h5);
+        let h6 = Haraka::hash_2n_to_n_ret(&h4, &h5);
 
         let src = [h0, h1, h2, h3];
         let mut auth = [Default::default(); 2];
@@ -265,14 +443,14 @@ mod tests {
     #[test]
     fn test_merkle_gen_auth_2() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_n_to_n_ret(&h0);
-        let h2 = hash::hash_n_to_n_ret(&h1);
-        let h3 = hash::hash_n_to_n_ret(&h2);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
 
-        let h4 = hash::hash_2n_to_n_ret(&h0, &h1);
-        let h5 = hash::hash_2n_to_n_ret(&h2, &h3);
+        let h4 = Haraka::hash_2n_to_n_ret(&h0, &h1);
+        let h5 = Haraka::hash_2n_to_n_ret(&h2, &h3);
 
-        let h6 = hash::hash_2n_to_n_ret(&h4, &h5);
+        let h6 = Haraka::hash_2n_to_n_ret(&h4, &h5);
 
         let src = [h0, h1, h2, h3];
         let mut auth = [Default::default(); 2];
@@ -284,16 +462,16 @@ mod tests {
     #[test]
     fn test_merkle_gen_compress_auth() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_n_to_n_ret(&h0);
-        let h2 = hash::hash_n_to_n_ret(&h1);
-        let h3 = hash::hash_n_to_n_ret(&h2);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
         let src = [h0, h1, h2, h3];
 
         for i in 0..4 {
             let mut auth = [Default::default(); 2];
             let root = merkle_gen_auth_leaves(&mut auth, &src, 2, i);
             let mut node = src[i];
-            let index = merkle_compress_auth(&mut node, &auth, 2, i);
+            let index = merkle_compress_auth(&Tweak::NONE, &mut node, &auth, 2, i);
             assert_eq!(index, 0);
             assert_eq!(node, root);
         }
@@ -302,17 +480,17 @@ mod tests {
     #[test]
     fn test_merkle_compress_auth() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let a1 = hash::hash_n_to_n_ret(&h0);
-        let a2 = hash::hash_n_to_n_ret(&a1);
-        let a3 = hash::hash_n_to_n_ret(&a2);
+        let a1 = Haraka::hash_n_to_n_ret(&h0);
+        let a2 = Haraka::hash_n_to_n_ret(&a1);
+        let a3 = Haraka::hash_n_to_n_ret(&a2);
         let auth = [a1, a2, a3];
 
-        let h1 = hash::hash_2n_to_n_ret(&h0, &a1);
-        let h2 = hash::hash_2n_to_n_ret(&a2, &h1);
-        let h3 = hash::hash_2n_to_n_ret(&h2, &a3);
+        let h1 = Haraka::hash_2n_to_n_ret(&h0, &a1);
+        let h2 = Haraka::hash_2n_to_n_ret(&a2, &h1);
+        let h3 = Haraka::hash_2n_to_n_ret(&h2, &a3);
 
         let mut node = h0;
-        let index = merkle_compress_auth(&mut node, &auth, 3, 2);
+        let index = merkle_compress_auth(&Tweak::NONE, &mut node, &auth, 3, 2);
         assert_eq!(index, 0);
         assert_eq!(node, h3);
     }
@@ -320,16 +498,16 @@ mod tests {
     #[test]
     fn test_merkle_compress_auth_partial() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let a1 = hash::hash_n_to_n_ret(&h0);
-        let a2 = hash::hash_n_to_n_ret(&a1);
-        let a3 = hash::hash_n_to_n_ret(&a2);
+        let a1 = Haraka::hash_n_to_n_ret(&h0);
+        let a2 = Haraka::hash_n_to_n_ret(&a1);
+        let a3 = Haraka::hash_n_to_n_ret(&a2);
         let auth = [a1, a2, a3];
 
-        let h1 = hash::hash_2n_to_n_ret(&a1, &h0);
-        let h2 = hash::hash_2n_to_n_ret(&a2, &h1);
+        let h1 = Haraka::hash_2n_to_n_ret(&a1, &h0);
+        let h2 = Haraka::hash_2n_to_n_ret(&a2, &h1);
 
         let mut node = h0;
-        let index = merkle_compress_auth(&mut node, &auth, 2, 7);
+        let index = merkle_compress_auth(&Tweak::NONE, &mut node, &auth, 2, 7);
         assert_eq!(index, 1);
         assert_eq!(node, h2);
     }
@@ -337,11 +515,11 @@ mod tests {
     #[test]
     fn test_merkle_tree_root() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_n_to_n_ret(&h0);
-        let h2 = hash::hash_n_to_n_ret(&h1);
-        let h3 = hash::hash_n_to_n_ret(&h2);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
         let src = [h0, h1, h2, h3];
-        let expect = merkle_compress_all_leaves(&src, 2);
+        let expect = merkle_compress_all_leaves(&Tweak::NONE, &src);
 
         let mut mt = MerkleTree::new(2);
         {
@@ -351,7 +529,7 @@ mod tests {
             leaves[2] = h2;
             leaves[3] = h3;
         }
-        mt.generate();
+        mt.generate(&Tweak::NONE);
         let root = mt.root();
 
         assert_eq!(root, expect);
@@ -360,9 +538,9 @@ mod tests {
     #[test]
     fn test_merkle_tree_gen_auth() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_n_to_n_ret(&h0);
-        let h2 = hash::hash_n_to_n_ret(&h1);
-        let h3 = hash::hash_n_to_n_ret(&h2);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
         let src = [h0, h1, h2, h3];
 
         let mut mt = MerkleTree::new(2);
@@ -373,7 +551,7 @@ mod tests {
             leaves[2] = h2;
             leaves[3] = h3;
         }
-        mt.generate();
+        mt.generate(&Tweak::NONE);
 
         for index in 0..4 {
             let mut expect = [Default::default(); 2];
@@ -384,160 +562,222 @@ mod tests {
         }
     }
 
-    macro_rules! all_benches {
-        ( $mod:ident, $params:ty ) => {
-            crate::tests::param_benches!(
-                $mod,
-                $params,
-                bench_merkle_compress_all_subtree,
-                bench_merkle_gen_auth_subtree_first,
-                bench_merkle_gen_auth_subtree_last,
-                bench_merkle_gen_auth_subtree_middle,
-                bench_merkle_compress_auth_subtree_first,
-                bench_merkle_compress_auth_subtree_last,
-                bench_merkle_compress_auth_subtree_middle,
-                bench_merkle_compress_all_pors,
-                bench_merkle_gen_auth_pors_first,
-                bench_merkle_gen_auth_pors_last,
-                bench_merkle_gen_auth_pors_middle,
-                bench_merkle_compress_auth_pors_first,
-                bench_merkle_compress_auth_pors_last,
-                bench_merkle_compress_auth_pors_middle,
-            );
-        };
-    }
-
-    use crate::config::{GravityLarge, GravityMedium, GravityParams, GravitySmall};
-
-    all_benches!(benches_small, GravitySmall);
-    all_benches!(benches_medium, GravityMedium);
-    all_benches!(benches_large, GravityLarge);
-
-    use std::hint::black_box;
-    use test::Bencher;
-
-    fn bench_merkle_compress_all(b: &mut Bencher, height: usize) {
-        let mut buf = MerkleBuf::new(height);
-        for leaf in buf.slice_leaves_mut() {
-            *leaf = hash::tests::HASH_ELEMENT;
+    #[test]
+    fn test_merkle_leaf_count() {
+        let h0 = hash::tests::HASH_ELEMENT;
+        let src: Vec<Hash> = (0..9)
+            .scan(h0, |h, _| {
+                let x = *h;
+                *h = Haraka::hash_n_to_n_ret(h);
+                Some(x)
+            })
+            .collect();
+
+        // With promoted nodes, trees of any number of leaves are L-trees.
+        for count in 1..=9 {
+            let leaves = &src[..count];
+            let expect = ltree::ltree_leaves_ret(&Tweak::NONE, leaves);
+
+            let mut mt = MerkleTree::with_leaf_count(count);
+            mt.leaves().copy_from_slice(leaves);
+            mt.generate(&Tweak::NONE);
+            assert_eq!(mt.root(), expect);
+            assert_eq!(merkle_compress_all_leaves(&Tweak::NONE, leaves), expect);
+            let leaf = |i: usize| leaves[i];
+            let height = tree_height(count);
+            let root = merkle_subtree_root(&Tweak::NONE, &leaf, count, height, 0);
+            assert_eq!(root, expect);
+
+            for index in 0..count {
+                let mut auth = [Default::default(); 5];
+                let len = mt.gen_auth(&mut auth, index);
+                assert_eq!(len, auth_len(count, index));
+
+                let mut buf = MerkleBuf::with_leaf_count(count);
+                buf.fill_leaves(leaves);
+                let mut buf_auth = [Default::default(); 5];
+                let root = merkle_gen_auth(&Tweak::NONE, &mut buf_auth, &mut buf, index);
+                assert_eq!(root, expect);
+                assert_eq!(buf_auth, auth);
+
+                let node = leaves[index];
+                let root =
+                    merkle_compress_auth_count(&Tweak::NONE, &node, &auth[..len], count, index);
+                assert_eq!(root, Some(expect));
+                let longer = &auth[..(len + 1)];
+                assert!(
+                    merkle_compress_auth_count(&Tweak::NONE, &node, longer, count, index).is_none()
+                );
+                if len > 0 {
+                    let shorter = &auth[..(len - 1)];
+                    assert!(
+                        merkle_compress_auth_count(&Tweak::NONE, &node, shorter, count, index)
+                            .is_none()
+                    );
+                }
+            }
         }
-
-        b.iter(|| {
-            let mut root = Default::default();
-            merkle_compress_all(&mut root, black_box(&mut buf));
-            root
-        });
     }
 
-    fn bench_merkle_gen_auth(b: &mut Bencher, height: usize, index: usize) {
-        let mut buf = MerkleBuf::new(height);
-        b.iter(|| {
-            // TODO: use const generic height once it's available.
-            let mut auth = vec![Default::default(); height];
-            let hash = merkle_gen_auth(&mut auth, black_box(&mut buf), index);
-            (hash, auth)
-        });
-    }
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+
+        macro_rules! all_benches {
+            ( $mod:ident, $params:ty ) => {
+                crate::tests::param_benches!(
+                    $mod,
+                    $params,
+                    bench_merkle_compress_all_subtree,
+                    bench_merkle_gen_auth_subtree_first,
+                    bench_merkle_gen_auth_subtree_last,
+                    bench_merkle_gen_auth_subtree_middle,
+                    bench_merkle_compress_auth_subtree_first,
+                    bench_merkle_compress_auth_subtree_last,
+                    bench_merkle_compress_auth_subtree_middle,
+                    bench_merkle_compress_all_pors,
+                    bench_merkle_gen_auth_pors_first,
+                    bench_merkle_gen_auth_pors_last,
+                    bench_merkle_gen_auth_pors_middle,
+                    bench_merkle_compress_auth_pors_first,
+                    bench_merkle_compress_auth_pors_last,
+                    bench_merkle_compress_auth_pors_middle,
+                );
+            };
+        }
 
-    fn bench_merkle_gen_auth_first(b: &mut Bencher, height: usize) {
-        bench_merkle_gen_auth(b, height, 0);
-    }
+        use crate::config::{GravityLarge, GravityMedium, GravityParams, GravitySmall};
 
-    fn bench_merkle_gen_auth_last(b: &mut Bencher, height: usize) {
-        bench_merkle_gen_auth(b, height, (1 << height) - 1);
-    }
+        all_benches!(benches_small, GravitySmall);
+        all_benches!(benches_medium, GravityMedium);
+        all_benches!(benches_large, GravityLarge);
 
-    fn bench_merkle_gen_auth_middle(b: &mut Bencher, height: usize) {
-        let mut index = 0;
-        for i in 0..height {
-            index <<= 1;
-            index |= i & 1;
+        use std::hint::black_box;
+        use test::Bencher;
+
+        fn bench_merkle_compress_all(b: &mut Bencher, height: usize) {
+            let mut buf = MerkleBuf::new(height);
+            for leaf in buf.slice_leaves_mut() {
+                *leaf = hash::tests::HASH_ELEMENT;
+            }
+
+            b.iter(|| {
+                let mut root = Default::default();
+                merkle_compress_all(&Tweak::NONE, &mut root, black_box(&mut buf));
+                root
+            });
         }
-        bench_merkle_gen_auth(b, height, index);
-    }
 
-    fn bench_merkle_compress_auth(b: &mut Bencher, height: usize, index: usize) {
-        // TODO: use const generic height once it's available.
-        let auth = vec![hash::tests::HASH_ELEMENT; height];
-        b.iter(|| {
-            let mut node = black_box(hash::tests::HASH_ELEMENT);
-            merkle_compress_auth(&mut node, black_box(&auth), height, index);
-            node
-        });
-    }
+        fn bench_merkle_gen_auth(b: &mut Bencher, height: usize, index: usize) {
+            let mut buf = MerkleBuf::new(height);
+            b.iter(|| {
+                // TODO: use const generic height once it's available.
+                let mut auth = vec![Default::default(); height];
+                let hash = merkle_gen_auth(&Tweak::NONE, &mut auth, black_box(&mut buf), index);
+                (hash, auth)
+            });
+        }
 
-    fn bench_merkle_compress_auth_first(b: &mut Bencher, height: usize) {
-        bench_merkle_compress_auth(b, height, 0);
-    }
+        fn bench_merkle_gen_auth_first(b: &mut Bencher, height: usize) {
+            bench_merkle_gen_auth(b, height, 0);
+        }
 
-    fn bench_merkle_compress_auth_last(b: &mut Bencher, height: usize) {
-        bench_merkle_compress_auth(b, height, (1 << height) - 1);
-    }
+        fn bench_merkle_gen_auth_last(b: &mut Bencher, height: usize) {
+            bench_merkle_gen_auth(b, height, (1 << height) - 1);
+        }
 
-    fn bench_merkle_compress_auth_middle(b: &mut Bencher, height: usize) {
-        let mut index = 0;
-        for i in 0..height {
-            index <<= 1;
-            index |= i & 1;
+        fn bench_merkle_gen_auth_middle(b: &mut Bencher, height: usize) {
+            let mut index = 0;
+            for i in 0..height {
+                index <<= 1;
+                index |= i & 1;
+            }
+            bench_merkle_gen_auth(b, height, index);
         }
-        bench_merkle_compress_auth(b, height, index);
-    }
 
-    // SPHINCS subtree
-    fn bench_merkle_compress_all_subtree<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_compress_all(b, P::MERKLE_H);
-    }
+        fn bench_merkle_compress_auth(b: &mut Bencher, height: usize, index: usize) {
+            // TODO: use const generic height once it's available.
+            let auth = vec![hash::tests::HASH_ELEMENT; height];
+            b.iter(|| {
+                let mut node = black_box(hash::tests::HASH_ELEMENT);
+                merkle_compress_auth(&Tweak::NONE, &mut node, black_box(&auth), height, index);
+                node
+            });
+        }
 
-    fn bench_merkle_gen_auth_subtree_first<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_gen_auth_first(b, P::MERKLE_H);
-    }
+        fn bench_merkle_compress_auth_first(b: &mut Bencher, height: usize) {
+            bench_merkle_compress_auth(b, height, 0);
+        }
 
-    fn bench_merkle_gen_auth_subtree_last<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_gen_auth_last(b, P::MERKLE_H);
-    }
+        fn bench_merkle_compress_auth_last(b: &mut Bencher, height: usize) {
+            bench_merkle_compress_auth(b, height, (1 << height) - 1);
+        }
 
-    fn bench_merkle_gen_auth_subtree_middle<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_gen_auth_middle(b, P::MERKLE_H);
-    }
+        fn bench_merkle_compress_auth_middle(b: &mut Bencher, height: usize) {
+            let mut index = 0;
+            for i in 0..height {
+                index <<= 1;
+                index |= i & 1;
+            }
+            bench_merkle_compress_auth(b, height, index);
+        }
 
-    fn bench_merkle_compress_auth_subtree_first<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_compress_auth_first(b, P::MERKLE_H);
-    }
+        // SPHINCS subtree
+        fn bench_merkle_compress_all_subtree<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_compress_all(b, P::MERKLE_H);
+        }
 
-    fn bench_merkle_compress_auth_subtree_last<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_compress_auth_last(b, P::MERKLE_H);
-    }
+        fn bench_merkle_gen_auth_subtree_first<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_gen_auth_first(b, P::MERKLE_H);
+        }
 
-    fn bench_merkle_compress_auth_subtree_middle<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_compress_auth_middle(b, P::MERKLE_H);
-    }
+        fn bench_merkle_gen_auth_subtree_last<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_gen_auth_last(b, P::MERKLE_H);
+        }
 
-    // PORS tree
-    fn bench_merkle_compress_all_pors<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_compress_all(b, P::PORS_TAU);
-    }
+        fn bench_merkle_gen_auth_subtree_middle<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_gen_auth_middle(b, P::MERKLE_H);
+        }
 
-    fn bench_merkle_gen_auth_pors_first<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_gen_auth_first(b, P::PORS_TAU);
-    }
+        fn bench_merkle_compress_auth_subtree_first<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_compress_auth_first(b, P::MERKLE_H);
+        }
 
-    fn bench_merkle_gen_auth_pors_last<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_gen_auth_last(b, P::PORS_TAU);
-    }
+        fn bench_merkle_compress_auth_subtree_last<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_compress_auth_last(b, P::MERKLE_H);
+        }
 
-    fn bench_merkle_gen_auth_pors_middle<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_gen_auth_middle(b, P::PORS_TAU);
-    }
+        fn bench_merkle_compress_auth_subtree_middle<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_compress_auth_middle(b, P::MERKLE_H);
+        }
 
-    fn bench_merkle_compress_auth_pors_first<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_compress_auth_first(b, P::PORS_TAU);
-    }
+        // PORS tree
+        fn bench_merkle_compress_all_pors<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_compress_all(b, P::PORS_TAU);
+        }
 
-    fn bench_merkle_compress_auth_pors_last<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_compress_auth_last(b, P::PORS_TAU);
-    }
+        fn bench_merkle_gen_auth_pors_first<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_gen_auth_first(b, P::PORS_TAU);
+        }
+
+        fn bench_merkle_gen_auth_pors_last<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_gen_auth_last(b, P::PORS_TAU);
+        }
 
-    fn bench_merkle_compress_auth_pors_middle<P: GravityParams>(b: &mut Bencher) {
-        bench_merkle_compress_auth_middle(b, P::PORS_TAU);
+        fn bench_merkle_gen_auth_pors_middle<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_gen_auth_middle(b, P::PORS_TAU);
+        }
+
+        fn bench_merkle_compress_auth_pors_first<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_compress_auth_first(b, P::PORS_TAU);
+        }
+
+        fn bench_merkle_compress_auth_pors_last<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_compress_auth_last(b, P::PORS_TAU);
+        }
+
+        fn bench_merkle_compress_auth_pors_middle<P: GravityParams>(b: &mut Bencher) {
+            bench_merkle_compress_auth_middle(b, P::PORS_TAU);
+        }
     }
 }
@@ -0,0 +1,577 @@
+use crate::hash::{Hash, HashFunction, Tweak};
+use crate::merkle;
+use crate::octopus;
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::{BigEndian, ByteOrder};
+use core::marker::PhantomData;
+
+/// Merkle tree over the hash function `H`, whose leaves are given as hashes. Each leaf is hashed
+/// into its node with `H::long_hash` and a `0x00` prefix byte, and the inner nodes are computed
+/// with `H::hash_2n_to_n`, without the tweaks of the signature scheme.
+///
+/// The tree may have any number of leaves. In a layer with an odd number of nodes, the last node
+/// has no sibling and is promoted to the next layer as is, so the authentication paths of some
+/// leaves are shorter than the height of the tree.
+pub struct MerkleTree<H: HashFunction> {
+    leaves: Vec<Hash>,
+    tree: merkle::MerkleTree,
+    _phantom: PhantomData<H>,
+}
+
+impl<H: HashFunction> MerkleTree<H> {
+    /// Builds the tree of `leaves`. Returns `None` if there are none.
+    ///
+    /// As in RFC 6962, the leaves are hashed with a prefix byte into nodes of another domain than
+    /// the inner nodes, so that an inner node cannot be passed off as a leaf: a leaf node is the
+    /// hash of `0x00 || leaf`, whereas an inner node is the hash of two nodes.
+    pub fn from_leaves(leaves: &[Hash]) -> Option<Self> {
+        if leaves.is_empty() {
+            return None;
+        }
+
+        let mut tree = merkle::MerkleTree::with_leaf_count(leaves.len());
+        for (node, leaf) in tree.leaves().iter_mut().zip(leaves) {
+            *node = leaf_node::<H>(leaf);
+        }
+        tree.generate(&Tweak::<H>::plain());
+        Some(Self {
+            leaves: leaves.to_vec(),
+            tree,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn root(&self) -> Hash {
+        self.tree.root()
+    }
+
+    pub fn leaves(&self) -> &[Hash] {
+        self.leaves.as_slice()
+    }
+
+    /// Proves that the leaf at `index` is in the tree, with its authentication path. Returns
+    /// `None` if `index` is out of range.
+    pub fn prove(&self, index: usize) -> Option<InclusionProof<H>> {
+        let leaf_count = self.leaves().len();
+        if index >= leaf_count {
+            return None;
+        }
+
+        let mut auth = vec![Default::default(); self.tree.height()];
+        let len = self.tree.gen_auth(&mut auth, index);
+        auth.truncate(len);
+        Some(InclusionProof {
+            leaf_count,
+            index,
+            auth,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Proves that the leaves at `indices` are in the tree, with a single octopus: the union of
+    /// their authentication paths, without the nodes that the verifier computes from the leaves.
+    /// The indices may be in any order and repeated; those of the proof are sorted and unique.
+    /// Returns `None` if `indices` is empty or out of range.
+    pub fn prove_multi(&self, indices: &[usize]) -> Option<MultiProof<H>> {
+        let leaf_count = self.leaves().len();
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        if !octopus::valid_indices(&indices, leaf_count) {
+            return None;
+        }
+
+        let mut layer = indices.clone();
+        let mut count = layer.len();
+        let mut n = leaf_count;
+        let mut nodes = Vec::new();
+        for l in 0..self.tree.height() {
+            count = octopus::octopus_layer(&mut layer, count, n, |sibling| {
+                nodes.push(self.tree.node(l, sibling))
+            });
+            n = n.div_ceil(2);
+        }
+        Some(MultiProof {
+            leaf_count,
+            indices,
+            nodes,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+/// Proof that a leaf is in a [`MerkleTree`]: the index of the leaf, and its authentication path.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InclusionProof<H: HashFunction> {
+    leaf_count: usize,
+    index: usize,
+    auth: Vec<Hash>,
+    _phantom: PhantomData<H>,
+}
+
+impl<H: HashFunction> InclusionProof<H> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Verifies that `leaf` is at the index of this proof in the tree of `leaf_count` leaves whose
+    /// root is `root`.
+    ///
+    /// The root doesn't commit to the number of leaves, and as the last node of an odd layer is
+    /// promoted, a leaf has valid proofs at several indices of trees of different sizes. The
+    /// `root` and `leaf_count` must therefore both be trusted, as the signed tree head of RFC 6962,
+    /// and the proof is rejected if it is for another number of leaves.
+    pub fn verify(&self, root: &Hash, leaf_count: usize, leaf: &Hash) -> bool {
+        if leaf_count != self.leaf_count {
+            return false;
+        }
+
+        let h = merkle::merkle_compress_auth_count(
+            &Tweak::<H>::plain(),
+            &leaf_node::<H>(leaf),
+            &self.auth,
+            self.leaf_count,
+            self.index,
+        );
+        h == Some(*root)
+    }
+
+    pub fn serialize(&self, output: &mut Vec<u8>) {
+        write_u64(output, self.leaf_count as u64);
+        write_u64(output, self.index as u64);
+        for x in self.auth.iter() {
+            x.serialize(H::OUTPUT_BYTES, output);
+        }
+    }
+
+    pub fn deserialize<'a, I>(it: &mut I) -> Option<Self>
+    where
+        I: Iterator<Item = &'a u8>,
+    {
+        let leaf_count = read_usize(it)?;
+        let index = read_usize(it)?;
+        if index >= leaf_count {
+            return None;
+        }
+
+        let auth = (0..merkle::auth_len(leaf_count, index))
+            .map(|_| Hash::deserialize(H::OUTPUT_BYTES, it))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            leaf_count,
+            index,
+            auth,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+/// Proof that several leaves are in a [`MerkleTree`]: their indices, and the octopus of nodes
+/// needed to compute the root from them.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MultiProof<H: HashFunction> {
+    leaf_count: usize,
+    indices: Vec<usize>,
+    nodes: Vec<Hash>,
+    _phantom: PhantomData<H>,
+}
+
+impl<H: HashFunction> MultiProof<H> {
+    pub fn indices(&self) -> &[usize] {
+        self.indices.as_slice()
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Verifies that `leaves` are at the indices of this proof, in the same order, in the tree of
+    /// `leaf_count` leaves whose root is `root`. As for [`InclusionProof::verify`], `root` and
+    /// `leaf_count` must both be trusted.
+    pub fn verify(&self, root: &Hash, leaf_count: usize, leaves: &[Hash]) -> bool {
+        if leaf_count != self.leaf_count || leaves.len() != self.indices.len() {
+            return false;
+        }
+
+        let mut nodes: Vec<Hash> = leaves.iter().map(leaf_node::<H>).collect();
+        let mut indices = self.indices.clone();
+        let h = octopus::merkle_compress_octopus(
+            &Tweak::<H>::plain(),
+            &mut nodes,
+            self.nodes.iter().copied(),
+            self.leaf_count,
+            &mut indices,
+        );
+        h == Some(*root)
+    }
+
+    /// Same as [`MultiProof::verify`], but with the leaves given with their indices, in any order.
+    /// A leaf may be repeated with the same index. Returns `false` if the indices are not exactly
+    /// those of this proof, or if an index is given with different leaves.
+    pub fn verify_indexed(&self, root: &Hash, leaf_count: usize, leaves: &[(usize, Hash)]) -> bool {
+        let mut leaves = leaves.to_vec();
+        leaves.sort_unstable_by_key(|(index, _)| *index);
+        leaves.dedup();
+        if !leaves
+            .iter()
+            .map(|(index, _)| index)
+            .eq(self.indices.iter())
+        {
+            return false;
+        }
+
+        let leaves: Vec<Hash> = leaves.into_iter().map(|(_, leaf)| leaf).collect();
+        self.verify(root, leaf_count, &leaves)
+    }
+
+    /// Serializes the proof. The number of nodes is implied by the indices, so it is not
+    /// serialized.
+    pub fn serialize(&self, output: &mut Vec<u8>) {
+        write_u64(output, self.leaf_count as u64);
+        write_u64(output, self.indices.len() as u64);
+        for index in self.indices.iter() {
+            write_u64(output, *index as u64);
+        }
+        for x in self.nodes.iter() {
+            x.serialize(H::OUTPUT_BYTES, output);
+        }
+    }
+
+    pub fn deserialize<'a, I>(it: &mut I) -> Option<Self>
+    where
+        I: Iterator<Item = &'a u8>,
+    {
+        let leaf_count = read_usize(it)?;
+        let count = read_usize(it)?;
+        if count > leaf_count {
+            return None;
+        }
+        let indices: Vec<usize> = (0..count).map(|_| read_usize(it)).collect::<Option<_>>()?;
+        if !octopus::valid_indices(&indices, leaf_count) {
+            return None;
+        }
+
+        let nodes = (0..octopus::octopus_len(&indices, leaf_count))
+            .map(|_| Hash::deserialize(H::OUTPUT_BYTES, it))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            leaf_count,
+            indices,
+            nodes,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+const LEAF_PREFIX: u8 = 0x00;
+
+fn leaf_node<H: HashFunction>(leaf: &Hash) -> Hash {
+    let mut bytes = vec![LEAF_PREFIX];
+    leaf.serialize(H::OUTPUT_BYTES, &mut bytes);
+    H::long_hash(&bytes)
+}
+
+fn write_u64(output: &mut Vec<u8>, x: u64) {
+    let mut bytes = [0; 8];
+    BigEndian::write_u64(&mut bytes, x);
+    output.extend(bytes);
+}
+
+fn read_usize<'a, I>(it: &mut I) -> Option<usize>
+where
+    I: Iterator<Item = &'a u8>,
+{
+    let mut bytes = [0; 8];
+    for x in bytes.iter_mut() {
+        *x = *it.next()?;
+    }
+    usize::try_from(BigEndian::read_u64(&bytes)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::{Haraka, Sha2};
+
+    fn leaves<H: HashFunction>(count: usize) -> Vec<Hash> {
+        (0..count as u32)
+            .map(|i| H::long_hash(&i.to_be_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merkle_tree_root() {
+        let l = leaves::<Haraka>(4);
+        let n: Vec<Hash> = l.iter().map(leaf_node::<Haraka>).collect();
+        let h01 = Haraka::hash_2n_to_n_ret(&n[0], &n[1]);
+        let h23 = Haraka::hash_2n_to_n_ret(&n[2], &n[3]);
+        let root = Haraka::hash_2n_to_n_ret(&h01, &h23);
+
+        let mut bytes = vec![0];
+        l[0].serialize(32, &mut bytes);
+        assert_eq!(n[0], Haraka::long_hash(&bytes));
+
+        let tree = MerkleTree::<Haraka>::from_leaves(&l).unwrap();
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.leaves(), l.as_slice());
+
+        let tree = MerkleTree::<Haraka>::from_leaves(&l[..1]).unwrap();
+        assert_eq!(tree.root(), n[0]);
+
+        // The third leaf is promoted.
+        let tree = MerkleTree::<Haraka>::from_leaves(&l[..3]).unwrap();
+        assert_eq!(tree.root(), Haraka::hash_2n_to_n_ret(&h01, &n[2]));
+        assert_eq!(tree.prove(2).unwrap().auth, [h01]);
+        assert_eq!(tree.prove(0).unwrap().auth, [n[1], n[2]]);
+
+        assert!(MerkleTree::<Haraka>::from_leaves(&[]).is_none());
+    }
+
+    #[test]
+    fn test_prove_verify_haraka() {
+        test_prove_verify::<Haraka>();
+    }
+
+    #[test]
+    fn test_prove_verify_sha2() {
+        test_prove_verify::<Sha2>();
+    }
+
+    fn test_prove_verify<H: HashFunction + core::fmt::Debug + PartialEq>() {
+        for count in [1, 2, 5, 8] {
+            let l = leaves::<H>(count);
+            let tree = MerkleTree::<H>::from_leaves(&l).unwrap();
+            let root = tree.root();
+            assert!(tree.prove(count).is_none());
+
+            for (i, leaf) in l.iter().enumerate() {
+                let proof = tree.prove(i).unwrap();
+                assert_eq!(proof.index(), i);
+                assert_eq!(proof.leaf_count(), count);
+                assert!(proof.verify(&root, count, leaf));
+                assert!(!proof.verify(&root, count, &H::long_hash(b"Goodbye")));
+                if count > 1 {
+                    assert!(!proof.verify(&root, count, &l[(i + 1) % count]));
+                    assert!(!proof.verify(leaf, count, leaf));
+                }
+
+                let mut bytes = Vec::new();
+                proof.serialize(&mut bytes);
+                let len = merkle::auth_len(count, i);
+                assert_eq!(proof.auth.len(), len);
+                assert_eq!(bytes.len(), 16 + len * H::OUTPUT_BYTES);
+                let deserialized = InclusionProof::<H>::deserialize(&mut bytes.iter()).unwrap();
+                assert_eq!(deserialized, proof);
+            }
+        }
+    }
+
+    #[test]
+    fn test_prove_multi() {
+        let l = leaves::<Haraka>(8);
+        let tree = MerkleTree::<Haraka>::from_leaves(&l).unwrap();
+
+        // Same octopus as in `octopus::tests::test_merkle_gen_octopus`.
+        let n: Vec<Hash> = l.iter().map(leaf_node::<Haraka>).collect();
+        let proof = tree.prove_multi(&[0, 2, 3, 6]).unwrap();
+        let h45 = Haraka::hash_2n_to_n_ret(&n[4], &n[5]);
+        assert_eq!(proof.nodes, [n[1], n[7], h45]);
+
+        // All the subsets of leaves, in trees of 5, 6, 7 and 8 leaves.
+        for count in 5..=8 {
+            let tree = MerkleTree::<Haraka>::from_leaves(&l[..count]).unwrap();
+            let root = tree.root();
+
+            for subset in 1..(1u32 << count) {
+                let indices: Vec<usize> = (0..count).filter(|i| subset & (1 << i) != 0).collect();
+                let mut subset_leaves: Vec<Hash> = indices.iter().map(|i| l[*i]).collect();
+
+                let proof = tree.prove_multi(&indices).unwrap();
+                assert_eq!(proof.indices(), indices.as_slice());
+                assert_eq!(proof.leaf_count(), count);
+                assert_eq!(proof.nodes.len(), octopus::octopus_len(&indices, count));
+                if let [index] = indices[..] {
+                    assert_eq!(proof.nodes, tree.prove(index).unwrap().auth);
+                }
+                assert!(proof.verify(&root, count, &subset_leaves));
+                assert!(!proof.verify(&root, count, &subset_leaves[1..]));
+
+                let mut bytes = Vec::new();
+                proof.serialize(&mut bytes);
+                assert_eq!(bytes.len(), 16 + 8 * indices.len() + 32 * proof.nodes.len());
+                let deserialized = MultiProof::<Haraka>::deserialize(&mut bytes.iter()).unwrap();
+                assert_eq!(deserialized, proof);
+
+                subset_leaves[0] = Haraka::long_hash(b"Goodbye");
+                assert!(!proof.verify(&root, count, &subset_leaves));
+            }
+        }
+    }
+
+    #[test]
+    fn test_inner_node_as_leaf() {
+        let l = leaves::<Haraka>(5);
+        let n: Vec<Hash> = l.iter().map(leaf_node::<Haraka>).collect();
+        let tree = MerkleTree::<Haraka>::from_leaves(&l).unwrap();
+        let root = tree.root();
+        let h01 = Haraka::hash_2n_to_n_ret(&n[0], &n[1]);
+        let h23 = Haraka::hash_2n_to_n_ret(&n[2], &n[3]);
+
+        // The inner nodes would be the leaves of a tree of 2 leaves.
+        let h0123 = Haraka::hash_2n_to_n_ret(&h01, &h23);
+        let proof = InclusionProof::<Haraka> {
+            leaf_count: 2,
+            index: 0,
+            auth: vec![n[4]],
+            _phantom: PhantomData,
+        };
+        assert!(!proof.verify(&root, 2, &h0123));
+        let proof = MultiProof::<Haraka> {
+            leaf_count: 3,
+            indices: vec![0, 1],
+            nodes: vec![n[4]],
+            _phantom: PhantomData,
+        };
+        assert!(!proof.verify(&root, 3, &[h01, h23]));
+    }
+
+    #[test]
+    fn test_relabeled_leaf_count() {
+        let l = leaves::<Haraka>(5);
+        let tree = MerkleTree::<Haraka>::from_leaves(&l).unwrap();
+        let root = tree.root();
+
+        // The last leaf of 5 is promoted twice, so it has the same authentication path as the last
+        // leaf of 3 or of 2 leaves.
+        let proof = tree.prove(4).unwrap();
+        assert!(proof.verify(&root, 5, &l[4]));
+        for (leaf_count, index) in [(3, 2), (2, 1)] {
+            let relabeled = InclusionProof::<Haraka> {
+                leaf_count,
+                index,
+                auth: proof.auth.clone(),
+                _phantom: PhantomData,
+            };
+            assert!(!relabeled.verify(&root, 5, &l[4]));
+            assert!(relabeled.verify(&root, leaf_count, &l[4]));
+        }
+        assert!(!proof.verify(&root, 3, &l[4]));
+
+        let proof = tree.prove_multi(&[4]).unwrap();
+        assert!(proof.verify(&root, 5, &[l[4]]));
+        let relabeled = MultiProof::<Haraka> {
+            leaf_count: 3,
+            indices: vec![2],
+            nodes: proof.nodes.clone(),
+            _phantom: PhantomData,
+        };
+        assert!(!relabeled.verify(&root, 5, &[l[4]]));
+        assert!(!relabeled.verify_indexed(&root, 5, &[(2, l[4])]));
+        assert!(!proof.verify(&root, 3, &[l[4]]));
+    }
+
+    #[test]
+    fn test_prove_multi_invalid() {
+        let l = leaves::<Haraka>(8);
+        let tree = MerkleTree::<Haraka>::from_leaves(&l).unwrap();
+        assert!(tree.prove_multi(&[]).is_none());
+        assert!(tree.prove_multi(&[8]).is_none());
+        assert!(tree.prove_multi(&[1, 8, 2]).is_none());
+    }
+
+    #[test]
+    fn test_prove_multi_unsorted() {
+        let l = leaves::<Haraka>(7);
+        let tree = MerkleTree::<Haraka>::from_leaves(&l).unwrap();
+        let root = tree.root();
+
+        let proof = tree.prove_multi(&[6, 1, 4, 1, 6]).unwrap();
+        assert_eq!(proof, tree.prove_multi(&[1, 4, 6]).unwrap());
+        assert_eq!(proof.indices(), [1, 4, 6]);
+
+        assert!(proof.verify_indexed(&root, 7, &[(6, l[6]), (1, l[1]), (4, l[4]), (6, l[6])]));
+        // Missing, extra or conflicting leaves.
+        assert!(!proof.verify_indexed(&root, 7, &[(6, l[6]), (1, l[1])]));
+        assert!(!proof.verify_indexed(&root, 7, &[(6, l[6]), (1, l[1]), (4, l[4]), (5, l[5])]));
+        assert!(!proof.verify_indexed(&root, 7, &[(6, l[6]), (1, l[1]), (4, l[4]), (6, l[5])]));
+        // Leaves at the wrong indices.
+        assert!(!proof.verify_indexed(&root, 7, &[(6, l[4]), (1, l[1]), (4, l[6])]));
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        let l = leaves::<Haraka>(8);
+        let tree = MerkleTree::<Haraka>::from_leaves(&l).unwrap();
+
+        let mut bytes = Vec::new();
+        tree.prove(5).unwrap().serialize(&mut bytes);
+        let deserialize = |bytes: &[u8]| InclusionProof::<Haraka>::deserialize(&mut bytes.iter());
+        assert!(deserialize(&bytes).is_some());
+        assert!(deserialize(&bytes[..bytes.len() - 1]).is_none());
+        // No leaves.
+        let mut invalid = bytes.clone();
+        invalid[7] = 0;
+        assert!(deserialize(&invalid).is_none());
+        // Index out of range.
+        let mut invalid = bytes.clone();
+        invalid[15] = 8;
+        assert!(deserialize(&invalid).is_none());
+
+        let mut bytes = Vec::new();
+        tree.prove_multi(&[1, 4]).unwrap().serialize(&mut bytes);
+        let deserialize = |bytes: &[u8]| MultiProof::<Haraka>::deserialize(&mut bytes.iter());
+        assert!(deserialize(&bytes).is_some());
+        assert!(deserialize(&bytes[..bytes.len() - 1]).is_none());
+        // Unsorted indices.
+        let mut invalid = bytes.clone();
+        invalid[23] = 5;
+        assert!(deserialize(&invalid).is_none());
+        // Index out of range.
+        let mut invalid = bytes.clone();
+        invalid[31] = 8;
+        assert!(deserialize(&invalid).is_none());
+        // More indices than leaves, or none.
+        let mut invalid = bytes.clone();
+        invalid[15] = 9;
+        assert!(deserialize(&invalid).is_none());
+        let mut invalid = bytes.clone();
+        invalid[15] = 0;
+        assert!(deserialize(&invalid).is_none());
+    }
+
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+        use test::{Bencher, black_box};
+
+        #[bench]
+        fn bench_merkle_tree_1024(b: &mut Bencher) {
+            let l = leaves::<Haraka>(1024);
+            b.iter(|| MerkleTree::<Haraka>::from_leaves(black_box(&l)));
+        }
+
+        #[bench]
+        fn bench_prove_multi_1024_32(b: &mut Bencher) {
+            let l = leaves::<Haraka>(1024);
+            let tree = MerkleTree::<Haraka>::from_leaves(&l).unwrap();
+            let indices: Vec<usize> = (0..32).map(|i| i * 31).collect();
+            b.iter(|| tree.prove_multi(black_box(&indices)));
+        }
+
+        #[bench]
+        fn bench_verify_multi_1024_32(b: &mut Bencher) {
+            let l = leaves::<Haraka>(1024);
+            let tree = MerkleTree::<Haraka>::from_leaves(&l).unwrap();
+            let indices: Vec<usize> = (0..32).map(|i| i * 31).collect();
+            let subset_leaves: Vec<Hash> = indices.iter().map(|i| l[*i]).collect();
+            let proof = tree.prove_multi(&indices).unwrap();
+            let root = tree.root();
+            b.iter(|| proof.verify(&root, 1024, black_box(&subset_leaves)));
+        }
+    }
+}
@@ -1,19 +1,52 @@
+use crate::array::Array;
 use crate::config::*;
 use crate::hash;
-use crate::hash::Hash;
+use crate::hash::{Hash, HashFunction, Tweak};
 use crate::merkle;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
 use arrayref::array_mut_ref;
 use byteorder::{ByteOrder, LittleEndian};
-use std::marker::PhantomData;
-use std::mem;
+use core::marker::PhantomData;
+#[cfg(feature = "alloc")]
+use core::mem;
 
+// The octopus is stored in a fixed array, whose unused slots are kept empty.
 #[derive(Debug, PartialEq, Eq)]
 pub struct Octopus<P: GravityParams> {
-    pub oct: Vec<Hash>,
+    oct: P::OctopusArray<Hash>,
+    len: usize,
     _phantom: PhantomData<P>,
 }
 
 impl<P: GravityParams> Octopus<P> {
+    fn empty() -> Self {
+        Self {
+            oct: Array::from_fn(|_| Default::default()),
+            len: 0,
+            _phantom: PhantomData,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_slice(oct: &[Hash]) -> Self {
+        let mut octopus = Self::empty();
+        octopus.oct.as_mut()[..oct.len()].copy_from_slice(oct);
+        octopus.len = oct.len();
+        octopus
+    }
+
+    pub fn nodes(&self) -> &[Hash] {
+        &self.oct.as_ref()[..self.len]
+    }
+
+    #[cfg(feature = "alloc")]
+    fn push(&mut self, node: Hash) {
+        self.oct[self.len] = node;
+        self.len += 1;
+    }
+
     #[cfg(test)]
     pub fn min_size_hashes() -> usize {
         // See https://eprint.iacr.org/2017/933, Theorem 1.
@@ -28,19 +61,15 @@ impl<P: GravityParams> Octopus<P> {
         P::PORS_K * (P::PORS_TAU - P::PORS_K.ilog2() as usize)
     }
 
+    #[cfg(feature = "alloc")]
     pub fn serialize(&self, output: &mut Vec<u8>) {
-        for x in self.oct.iter() {
-            x.serialize(output);
-        }
-        // TODO: improve this!
-        let empty = Hash { h: [0; HASH_SIZE] };
-        let count = self.oct.len();
-        for _ in count..(P::PORS_K * P::PORS_TAU) {
-            empty.serialize(output);
+        // Unused slots are empty, so the whole array is serialized.
+        for x in self.oct.as_ref().iter() {
+            x.serialize(P::HASH_BYTES, output);
         }
 
         let mut block = [0u8; 16];
-        LittleEndian::write_u32(array_mut_ref![&mut block, 0, 4], count as u32);
+        LittleEndian::write_u32(array_mut_ref![&mut block, 0, 4], self.len as u32);
         output.extend(block.iter());
     }
 
@@ -48,9 +77,9 @@ impl<P: GravityParams> Octopus<P> {
     where
         I: Iterator<Item = &'a u8>,
     {
-        let mut oct = Vec::new();
-        for _ in 0..(P::PORS_K * P::PORS_TAU) {
-            oct.push(Hash::deserialize(it)?);
+        let mut octopus = Self::empty();
+        for x in octopus.oct.as_mut().iter_mut() {
+            *x = Hash::deserialize(P::HASH_BYTES, it)?;
         }
 
         let mut block = [0u8; 4];
@@ -65,103 +94,211 @@ impl<P: GravityParams> Octopus<P> {
             }
         }
 
-        if count > P::PORS_K * P::PORS_TAU {
+        if count > P::PORS_OCTOPUS_CAPACITY {
             return None;
         }
         let empty = Hash { h: [0; HASH_SIZE] };
 
-        if oct[count..].iter().any(|x| *x != empty) {
+        if octopus.oct.as_ref()[count..].iter().any(|x| *x != empty) {
             return None;
         }
-        oct.resize(count, empty);
+        octopus.len = count;
 
-        Some(Self {
-            oct,
-            _phantom: PhantomData,
-        })
+        Some(octopus)
     }
 }
 
-pub fn merkle_gen_octopus<P: GravityParams>(
+// Computes the root of the tree in `buf`, and calls `push` on the nodes of the octopus of the
+// leaves at `indices`, which are overwritten. Returns `None` if the indices are empty, not strictly
+// increasing or out of range.
+#[cfg(feature = "alloc")]
+pub fn merkle_gen_octopus_with<H: HashFunction, F>(
+    tweak: &Tweak<H>,
     buf: &mut merkle::MerkleBuf,
-    mut indices: [usize; P::PORS_K],
-) -> (Hash, Octopus<P>) {
+    indices: &mut [usize],
+    mut push: F,
+) -> Option<Hash>
+where
+    F: FnMut(Hash),
+{
+    let mut n = buf.leaf_count();
+    if !valid_indices(indices, n) {
+        return None;
+    }
+
     let height = buf.height();
-    let mut n = 1 << height;
     let (mut dst, mut src) = buf.split_half_mut();
     let mut count = indices.len();
 
-    let mut oct = Vec::new();
-    for _ in 0..height {
+    for l in 0..height {
         // Copy auth octopus
-        let mut i = 0;
-        let mut j = 0;
-        while i < count {
-            let index = indices[i];
-            let sibling = index ^ 1;
+        count = octopus_layer(indices, count, n, |sibling| push(dst[sibling]));
 
-            // Check redundancy with sibling
-            if i + 1 < count && indices[i + 1] == sibling {
-                i += 1;
-            } else {
-                oct.push(dst[sibling]);
-            }
+        // Compute next layer
+        mem::swap(&mut dst, &mut src);
+        n = merkle::compress_layer(&tweak.with_height(l + 1), dst, src, n);
+    }
 
-            indices[j] = indices[i] >> 1;
+    Some(dst[0])
+}
+
+#[cfg(feature = "alloc")]
+pub fn merkle_gen_octopus<P: GravityParams>(
+    tweak: &Tweak<P::HashFunction>,
+    buf: &mut merkle::MerkleBuf,
+    mut indices: P::PorsKArray<usize>,
+) -> (Hash, Octopus<P>) {
+    debug_assert!(buf.height() <= P::PORS_TAU);
+    let mut octopus = Octopus::empty();
+    let root = merkle_gen_octopus_with(tweak, buf, indices.as_mut(), |node| octopus.push(node))
+        .expect("the PORS subset is sorted and in range");
+    (root, octopus)
+}
 
+// Same octopus as `merkle_gen_octopus_with`, but each node is computed as the root of its subtree
+// from the leaves returned by `leaf`, so that the tree is never stored. As the octopus nodes are
+// roots of disjoint subtrees, the number of hashes is about the same. Returns `None` on the same
+// invalid indices.
+#[cfg(feature = "alloc")]
+pub fn merkle_gen_octopus_streaming_with<H: HashFunction, F, G>(
+    tweak: &Tweak<H>,
+    leaf: &F,
+    leaf_count: usize,
+    indices: &mut [usize],
+    mut push: G,
+) -> Option<()>
+where
+    F: Fn(usize) -> Hash,
+    G: FnMut(Hash),
+{
+    if !valid_indices(indices, leaf_count) {
+        return None;
+    }
+
+    let mut n = leaf_count;
+    let mut count = indices.len();
+    for l in 0..merkle::tree_height(leaf_count) {
+        count = octopus_layer(indices, count, n, |sibling| {
+            push(merkle::merkle_subtree_root(
+                tweak, leaf, leaf_count, l, sibling,
+            ))
+        });
+        n = n.div_ceil(2);
+    }
+    Some(())
+}
+
+#[cfg(feature = "alloc")]
+pub fn merkle_gen_octopus_streaming<P: GravityParams, F>(
+    tweak: &Tweak<P::HashFunction>,
+    leaf: &F,
+    leaf_count: usize,
+    mut indices: P::PorsKArray<usize>,
+) -> Octopus<P>
+where
+    F: Fn(usize) -> Hash,
+{
+    debug_assert!(merkle::tree_height(leaf_count) <= P::PORS_TAU);
+    let mut octopus = Octopus::empty();
+    merkle_gen_octopus_streaming_with(tweak, leaf, leaf_count, indices.as_mut(), |node| {
+        octopus.push(node)
+    })
+    .expect("the PORS subset is sorted and in range");
+    octopus
+}
+
+// Whether `indices` are non-empty, strictly increasing and less than `leaf_count`.
+pub fn valid_indices(indices: &[usize], leaf_count: usize) -> bool {
+    match indices.last() {
+        Some(last) => *last < leaf_count && indices.windows(2).all(|x| x[0] < x[1]),
+        None => false,
+    }
+}
+
+// Number of nodes in the octopus of the leaves at the sorted `indices`, in a tree of `leaf_count`
+// leaves.
+#[cfg(feature = "alloc")]
+pub fn octopus_len(indices: &[usize], leaf_count: usize) -> usize {
+    let mut layer = indices.to_vec();
+    let mut count = layer.len();
+    let mut n = leaf_count;
+    let mut len = 0;
+    while n > 1 {
+        count = octopus_layer(&mut layer, count, n, |_| len += 1);
+        n = n.div_ceil(2);
+    }
+    len
+}
+
+// Calls `f` on the siblings of the sorted `indices` that are not themselves in `indices`, in
+// increasing order, in a layer of `n` nodes. The last node of a layer with an odd number of nodes
+// has no sibling. The indices are then replaced by their parents, whose count is returned.
+#[cfg(feature = "alloc")]
+pub fn octopus_layer<F>(indices: &mut [usize], count: usize, n: usize, mut f: F) -> usize
+where
+    F: FnMut(usize),
+{
+    let mut i = 0;
+    let mut j = 0;
+    while i < count {
+        let index = indices[i];
+        let sibling = index ^ 1;
+
+        // Check redundancy with sibling
+        if i + 1 < count && indices[i + 1] == sibling {
             i += 1;
-            j += 1;
+        } else if sibling < n {
+            f(sibling);
         }
-        count = j;
 
-        // Compute next layer
-        mem::swap(&mut dst, &mut src);
-        n >>= 1;
-        hash::hash_compress_pairs(dst, src, n);
-    }
+        indices[j] = indices[i] >> 1;
 
-    let root = dst[0];
-    let octopus = Octopus {
-        oct,
-        _phantom: PhantomData,
-    };
-    (root, octopus)
+        i += 1;
+        j += 1;
+    }
+    j
 }
 
-pub fn merkle_compress_octopus<P: GravityParams>(
+// Computes the root of a tree of `leaf_count` leaves from the `nodes` at the sorted `indices` and
+// the octopus `oct`. Both `nodes` and `indices` are overwritten. Returns `None` if there isn't one
+// node per index, if the indices are invalid as in `valid_indices`, or if the octopus doesn't have
+// exactly the nodes needed.
+pub fn merkle_compress_octopus<H: HashFunction, I>(
+    tweak: &Tweak<H>,
     nodes: &mut [Hash],
-    octopus: &Octopus<P>,
-    height: usize,
-    mut indices: [usize; P::PORS_K],
-) -> Option<Hash> {
-    let octolen = octopus.oct.len();
-    let mut len = 0;
+    mut oct: I,
+    leaf_count: usize,
+    indices: &mut [usize],
+) -> Option<Hash>
+where
+    I: Iterator<Item = Hash>,
+{
+    if nodes.len() != indices.len() || !valid_indices(indices, leaf_count) {
+        return None;
+    }
+
     let mut count = indices.len();
+    let mut n = leaf_count;
 
-    for _ in 0..height {
+    for l in 0..merkle::tree_height(leaf_count) {
         let mut i = 0;
         let mut j = 0;
         while i < count {
             let index = indices[i];
+            let tweak = tweak.with_height(l + 1).with_index(index >> 1);
 
             if index & 1 == 0 {
                 let sibling = index ^ 1;
                 if i + 1 < count && indices[i + 1] == sibling {
-                    nodes[j] = hash::hash_2n_to_n_ret(&nodes[i], &nodes[i + 1]);
+                    nodes[j] = hash::tweak_2n_to_n_ret(&tweak, &nodes[i], &nodes[i + 1]);
                     i += 1;
+                } else if sibling < n {
+                    nodes[j] = hash::tweak_2n_to_n_ret(&tweak, &nodes[i], &oct.next()?);
                 } else {
-                    if len == octolen {
-                        return None;
-                    }
-                    nodes[j] = hash::hash_2n_to_n_ret(&nodes[i], &octopus.oct[len]);
-                    len += 1;
+                    nodes[j] = nodes[i];
                 }
             } else {
-                if len == octolen {
-                    return None;
-                }
-                nodes[j] = hash::hash_2n_to_n_ret(&octopus.oct[len], &nodes[i]);
-                len += 1;
+                nodes[j] = hash::tweak_2n_to_n_ret(&tweak, &oct.next()?, &nodes[i]);
             }
 
             indices[j] = indices[i] >> 1;
@@ -170,14 +307,40 @@ pub fn merkle_compress_octopus<P: GravityParams>(
             j += 1;
         }
         count = j;
+        n = n.div_ceil(2);
+    }
+
+    if oct.next().is_none() {
+        Some(nodes[0])
+    } else {
+        None
+    }
+}
+
+// Check a serialized octopus, as written by `Octopus::serialize`, and return the bytes of its
+// nodes without copying them.
+pub fn serialized_nodes<P: GravityParams>(bytes: &[u8]) -> Option<&[u8]> {
+    let (oct, block) = bytes.split_at_checked(P::PORS_OCTOPUS_CAPACITY * P::HASH_BYTES)?;
+    if block.len() != 16 || block[4..].iter().any(|x| *x != 0) {
+        return None;
+    }
+
+    let count = LittleEndian::read_u32(&block[..4]) as usize;
+    if count > P::PORS_OCTOPUS_CAPACITY {
+        return None;
     }
 
-    if len == octolen { Some(nodes[0]) } else { None }
+    let (nodes, empty) = oct.split_at(count * P::HASH_BYTES);
+    if empty.iter().any(|x| *x != 0) {
+        return None;
+    }
+    Some(nodes)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hash::Haraka;
 
     #[derive(Debug, PartialEq)]
     struct Octopus83;
@@ -193,6 +356,9 @@ mod tests {
             const {
                 assert!(Self::PORS_K > 0);
                 assert!(Self::PORS_K <= Self::PORS_T);
+                assert!(
+                    Self::PORS_T <= 1 << Self::PORS_TAU && 2 * Self::PORS_T > 1 << Self::PORS_TAU
+                );
             };
         }
 
@@ -202,6 +368,16 @@ mod tests {
         const H: usize = 0;
         const D: usize = 0;
         const C: usize = 0;
+        const OID: &'static [u32] = &[];
+
+        type HashFunction = Haraka;
+        type Prng = crate::prng::AesCtrPrng;
+        type PorsKArray<T: Copy> = [T; Self::PORS_K];
+        type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+        type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+        type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+        type GravityDArray<T> = [T; Self::GRAVITY_D];
+        type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
     }
 
     #[derive(Debug, PartialEq)]
@@ -218,6 +394,9 @@ mod tests {
             const {
                 assert!(Self::PORS_K > 0);
                 assert!(Self::PORS_K <= Self::PORS_T);
+                assert!(
+                    Self::PORS_T <= 1 << Self::PORS_TAU && 2 * Self::PORS_T > 1 << Self::PORS_TAU
+                );
             };
         }
 
@@ -227,6 +406,16 @@ mod tests {
         const H: usize = 0;
         const D: usize = 0;
         const C: usize = 0;
+        const OID: &'static [u32] = &[];
+
+        type HashFunction = Haraka;
+        type Prng = crate::prng::AesCtrPrng;
+        type PorsKArray<T: Copy> = [T; Self::PORS_K];
+        type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+        type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+        type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+        type GravityDArray<T> = [T; Self::GRAVITY_D];
+        type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
     }
 
     macro_rules! all_tests {
@@ -239,15 +428,16 @@ mod tests {
     all_tests!(medium, GravityMedium);
     all_tests!(large, GravityLarge);
 
-    fn test_octopus_size<P: GravityParams>()
-    where
-        [(); P::GRAVITY_D]:,
-        [(); P::GRAVITY_C]:,
-        [(); P::MERKLE_H]:,
-        [(); P::PORS_K]:,
-    {
+    fn test_octopus_size<P: GravityParams>() {
         let (expected_min_hashes, expected_max_hashes) = match P::config_type() {
-            ConfigType::S => (11, 288),
+            ConfigType::S
+            | ConfigType::ST
+            | ConfigType::SSha2
+            | ConfigType::SShake
+            | ConfigType::S128
+            | ConfigType::S192
+            | ConfigType::SW4
+            | ConfigType::SW256 => (11, 288),
             ConfigType::M => (11, 352),
             ConfigType::L => (11, 336),
             ConfigType::Unknown => unimplemented!(),
@@ -256,10 +446,10 @@ mod tests {
         assert_eq!(Octopus::<P>::max_size_hashes(), expected_max_hashes);
     }
 
-    fn merkle_gen_octopus_leaves<P: GravityParams>(
+    fn merkle_gen_octopus_leaves<P: GravityParams<HashFunction = Haraka>>(
         leaves: &[Hash],
         height: usize,
-        indices: [usize; P::PORS_K],
+        indices: P::PorsKArray<usize>,
     ) -> (Hash, Octopus<P>) {
         let count = leaves.len();
         assert_eq!(count, 1 << height);
@@ -267,52 +457,46 @@ mod tests {
         let mut buf = merkle::MerkleBuf::new(height);
         buf.fill_leaves(leaves);
 
-        merkle_gen_octopus(&mut buf, indices)
+        merkle_gen_octopus(&Tweak::NONE, &mut buf, indices)
     }
 
     #[test]
     fn test_merkle_gen_octopus() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_n_to_n_ret(&h0);
-        let h2 = hash::hash_n_to_n_ret(&h1);
-        let h3 = hash::hash_n_to_n_ret(&h2);
-        let h4 = hash::hash_n_to_n_ret(&h3);
-        let h5 = hash::hash_n_to_n_ret(&h4);
-        let h6 = hash::hash_n_to_n_ret(&h5);
-        let h7 = hash::hash_n_to_n_ret(&h6);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
+        let h4 = Haraka::hash_n_to_n_ret(&h3);
+        let h5 = Haraka::hash_n_to_n_ret(&h4);
+        let h6 = Haraka::hash_n_to_n_ret(&h5);
+        let h7 = Haraka::hash_n_to_n_ret(&h6);
 
-        let h8 = hash::hash_2n_to_n_ret(&h0, &h1);
-        let h9 = hash::hash_2n_to_n_ret(&h2, &h3);
-        let h10 = hash::hash_2n_to_n_ret(&h4, &h5);
-        let h11 = hash::hash_2n_to_n_ret(&h6, &h7);
+        let h8 = Haraka::hash_2n_to_n_ret(&h0, &h1);
+        let h9 = Haraka::hash_2n_to_n_ret(&h2, &h3);
+        let h10 = Haraka::hash_2n_to_n_ret(&h4, &h5);
+        let h11 = Haraka::hash_2n_to_n_ret(&h6, &h7);
 
-        let h12 = hash::hash_2n_to_n_ret(&h8, &h9);
-        let h13 = hash::hash_2n_to_n_ret(&h10, &h11);
+        let h12 = Haraka::hash_2n_to_n_ret(&h8, &h9);
+        let h13 = Haraka::hash_2n_to_n_ret(&h10, &h11);
 
-        let h14 = hash::hash_2n_to_n_ret(&h12, &h13);
+        let h14 = Haraka::hash_2n_to_n_ret(&h12, &h13);
 
         let src = [h0, h1, h2, h3, h4, h5, h6, h7];
         let (root, octopus) = merkle_gen_octopus_leaves::<Octopus84>(&src, 3, [0, 2, 3, 6]);
-        assert_eq!(
-            octopus,
-            Octopus {
-                oct: vec![h1, h7, h10],
-                _phantom: PhantomData,
-            }
-        );
+        assert_eq!(octopus, Octopus::from_slice(&[h1, h7, h10]));
         assert_eq!(root, h14);
     }
 
     #[test]
     fn test_merkle_gen_compress_octopus() {
         let h0 = hash::tests::HASH_ELEMENT;
-        let h1 = hash::hash_n_to_n_ret(&h0);
-        let h2 = hash::hash_n_to_n_ret(&h1);
-        let h3 = hash::hash_n_to_n_ret(&h2);
-        let h4 = hash::hash_n_to_n_ret(&h3);
-        let h5 = hash::hash_n_to_n_ret(&h4);
-        let h6 = hash::hash_n_to_n_ret(&h5);
-        let h7 = hash::hash_n_to_n_ret(&h6);
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let h2 = Haraka::hash_n_to_n_ret(&h1);
+        let h3 = Haraka::hash_n_to_n_ret(&h2);
+        let h4 = Haraka::hash_n_to_n_ret(&h3);
+        let h5 = Haraka::hash_n_to_n_ret(&h4);
+        let h6 = Haraka::hash_n_to_n_ret(&h5);
+        let h7 = Haraka::hash_n_to_n_ret(&h6);
         let src = [h0, h1, h2, h3, h4, h5, h6, h7];
 
         // Test all combinations of 3 indices
@@ -323,120 +507,349 @@ mod tests {
                         merkle_gen_octopus_leaves::<Octopus83>(&src, 3, [i, j, k]);
 
                     let mut nodes = [src[i], src[j], src[k]];
-                    let compressed = merkle_compress_octopus(&mut nodes, &octopus, 3, [i, j, k]);
+                    let compressed = merkle_compress_octopus(
+                        &Tweak::NONE,
+                        &mut nodes,
+                        octopus.nodes().iter().copied(),
+                        8,
+                        &mut [i, j, k],
+                    );
                     assert_eq!(compressed, Some(root));
                 }
             }
         }
     }
 
-    macro_rules! all_benches {
-        ( $mod:ident, $params:ty ) => {
-            crate::tests::param_benches!(
-                $mod,
-                $params,
-                bench_merkle_gen_octopus_pors,
-                bench_merkle_compress_octopus_pors,
-            );
-        };
+    #[test]
+    fn test_merkle_gen_octopus_streaming() {
+        let h0 = hash::tests::HASH_ELEMENT;
+        let src: Vec<Hash> = (0..8)
+            .scan(h0, |h, _| {
+                let x = *h;
+                *h = Haraka::hash_n_to_n_ret(h);
+                Some(x)
+            })
+            .collect();
+        let leaf = |i: usize| src[i];
+
+        for i in 0..6 {
+            for j in (i + 1)..7 {
+                for k in (j + 1)..8 {
+                    let (_, octopus) = merkle_gen_octopus_leaves::<Octopus83>(&src, 3, [i, j, k]);
+                    let streamed = merkle_gen_octopus_streaming::<Octopus83, _>(
+                        &Tweak::NONE,
+                        &leaf,
+                        8,
+                        [i, j, k],
+                    );
+                    assert_eq!(streamed, octopus);
+                }
+            }
+        }
     }
 
-    all_benches!(benches_small, GravitySmall);
-    all_benches!(benches_medium, GravityMedium);
-    all_benches!(benches_large, GravityLarge);
+    #[test]
+    fn test_merkle_octopus_leaf_count() {
+        let h0 = hash::tests::HASH_ELEMENT;
+        let src: Vec<Hash> = (0..8)
+            .scan(h0, |h, _| {
+                let x = *h;
+                *h = Haraka::hash_n_to_n_ret(h);
+                Some(x)
+            })
+            .collect();
+
+        for count in 5..8 {
+            let leaves = &src[..count];
+            let leaf = |i: usize| leaves[i];
+            let expect = crate::ltree::ltree_leaves_ret(&Tweak::NONE, leaves);
+
+            for i in 0..(count - 2) {
+                for j in (i + 1)..(count - 1) {
+                    for k in (j + 1)..count {
+                        let mut buf = merkle::MerkleBuf::with_leaf_count(count);
+                        buf.fill_leaves(leaves);
+                        let (root, octopus) =
+                            merkle_gen_octopus::<Octopus83>(&Tweak::NONE, &mut buf, [i, j, k]);
+                        assert_eq!(root, expect);
+
+                        let streamed = merkle_gen_octopus_streaming::<Octopus83, _>(
+                            &Tweak::NONE,
+                            &leaf,
+                            count,
+                            [i, j, k],
+                        );
+                        assert_eq!(streamed, octopus);
+
+                        let mut nodes = [src[i], src[j], src[k]];
+                        let compressed = merkle_compress_octopus(
+                            &Tweak::NONE,
+                            &mut nodes,
+                            octopus.nodes().iter().copied(),
+                            count,
+                            &mut [i, j, k],
+                        );
+                        assert_eq!(compressed, Some(expect));
+                    }
+                }
+            }
+        }
+    }
 
-    use super::super::{address, prng};
-    use arrayref::array_ref;
-    use byteorder::{BigEndian, ByteOrder};
-    use std::hint::black_box;
-    use test::Bencher;
+    #[test]
+    fn test_merkle_octopus_invalid_indices() {
+        let h0 = hash::tests::HASH_ELEMENT;
+        let src = [h0; 6];
+        let leaf = |i: usize| src[i];
+
+        for indices in [&[][..], &[6], &[1, 1], &[3, 2], &[0, 4, 2]] {
+            let mut buf = merkle::MerkleBuf::with_leaf_count(6);
+            buf.fill_leaves(&src);
+            let root = merkle_gen_octopus_with(
+                &Tweak::<Haraka>::NONE,
+                &mut buf,
+                &mut indices.to_vec(),
+                |_| (),
+            );
+            assert!(root.is_none());
+
+            let streamed = merkle_gen_octopus_streaming_with(
+                &Tweak::<Haraka>::NONE,
+                &leaf,
+                6,
+                &mut indices.to_vec(),
+                |_| (),
+            );
+            assert!(streamed.is_none());
+
+            let mut nodes = vec![h0; indices.len()];
+            let compressed = merkle_compress_octopus(
+                &Tweak::<Haraka>::NONE,
+                &mut nodes,
+                [h0; 6].into_iter(),
+                6,
+                &mut indices.to_vec(),
+            );
+            assert!(compressed.is_none());
+        }
 
-    #[bench]
-    fn bench_merkle_gen_octopus_8_4(b: &mut Bencher) {
-        const HEIGHT: usize = 3;
-        let src = [hash::tests::HASH_ELEMENT; 1 << HEIGHT];
-        let indices = [0, 2, 3, 6];
-        b.iter(|| merkle_gen_octopus_leaves::<Octopus84>(black_box(&src), HEIGHT, indices));
+        // Valid indices, with one node too many or too few.
+        let mut octopus = Vec::new();
+        let mut buf = merkle::MerkleBuf::with_leaf_count(6);
+        buf.fill_leaves(&src);
+        let root = merkle_gen_octopus_with(&Tweak::<Haraka>::NONE, &mut buf, &mut [1, 4], |node| {
+            octopus.push(node)
+        });
+        assert!(root.is_some());
+        assert_eq!(octopus.len(), octopus_len(&[1, 4], 6));
+        for nodes in [&[h0][..], &[h0; 3]] {
+            let compressed = merkle_compress_octopus(
+                &Tweak::<Haraka>::NONE,
+                &mut nodes.to_vec(),
+                octopus.iter().copied(),
+                6,
+                &mut [1, 4],
+            );
+            assert!(compressed.is_none());
+        }
+        let compressed = merkle_compress_octopus(
+            &Tweak::<Haraka>::NONE,
+            &mut [h0, h0],
+            octopus.iter().copied(),
+            6,
+            &mut [1, 4],
+        );
+        assert_eq!(compressed, root);
     }
 
-    #[bench]
-    fn bench_merkle_compress_octopus_8_3(b: &mut Bencher) {
-        const HEIGHT: usize = 3;
-        let src = [hash::tests::HASH_ELEMENT; 1 << HEIGHT];
-        let indices = [0, 2, 3];
+    #[test]
+    fn test_serialized_nodes() {
+        let h0 = hash::tests::HASH_ELEMENT;
+        let h1 = Haraka::hash_n_to_n_ret(&h0);
+        let octopus = Octopus::<Octopus83>::from_slice(&[h0, h1]);
+        let mut bytes = Vec::new();
+        octopus.serialize(&mut bytes);
+
+        // Parses the octopus both in place and with `Octopus::deserialize`, which must agree.
+        fn parse(bytes: &[u8]) -> Option<&[u8]> {
+            let nodes = serialized_nodes::<Octopus83>(bytes);
+            let mut it = bytes.iter();
+            let deserialized = Octopus::<Octopus83>::deserialize(&mut it);
+            let deserialized = deserialized.filter(|_| it.next().is_none());
+            assert_eq!(nodes.is_some(), deserialized.is_some());
+            if let (Some(nodes), Some(deserialized)) = (nodes, &deserialized) {
+                let mut expect = Vec::new();
+                for x in deserialized.nodes() {
+                    x.serialize(Octopus83::HASH_BYTES, &mut expect);
+                }
+                assert_eq!(nodes, expect.as_slice());
+            }
+            nodes
+        }
 
-        let (_, octopus) = merkle_gen_octopus_leaves::<Octopus83>(&src, HEIGHT, indices);
+        let mut expect = Vec::new();
+        h0.serialize(Octopus83::HASH_BYTES, &mut expect);
+        h1.serialize(Octopus83::HASH_BYTES, &mut expect);
+        assert_eq!(parse(&bytes), Some(expect.as_slice()));
+
+        // Truncated or extended
+        assert!(parse(&bytes[..bytes.len() - 1]).is_none());
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(parse(&extended).is_none());
+
+        // Non-empty unused slot
+        for i in [2, Octopus83::PORS_OCTOPUS_CAPACITY - 1] {
+            let mut invalid = bytes.clone();
+            invalid[(i + 1) * Octopus83::HASH_BYTES - 1] = 1;
+            assert!(parse(&invalid).is_none());
+        }
 
-        let mut nodes = indices.map(|i| src[i]);
-        b.iter(|| {
-            merkle_compress_octopus(black_box(&mut nodes), black_box(&octopus), HEIGHT, indices)
-        })
-    }
+        // Count too large
+        let count = bytes.len() - 16;
+        for x in [Octopus83::PORS_OCTOPUS_CAPACITY as u32 + 1, u32::MAX] {
+            let mut invalid = bytes.clone();
+            LittleEndian::write_u32(&mut invalid[count..(count + 4)], x);
+            assert!(parse(&invalid).is_none());
+        }
 
-    fn bench_merkle_gen_octopus_pors<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::PORS_K]:,
-    {
-        let src = vec![hash::tests::HASH_ELEMENT; P::PORS_T];
-        let mut buf = merkle::MerkleBuf::new(P::PORS_TAU);
-        hash::hash_parallel(buf.slice_leaves_mut(), &src, P::PORS_T);
+        // Non-zero padding
+        for i in 4..16 {
+            let mut invalid = bytes.clone();
+            invalid[count + i] = 1;
+            assert!(parse(&invalid).is_none());
+        }
 
-        let subset = fake_pors_subset::<P>();
-        b.iter(|| merkle_gen_octopus::<P>(black_box(&mut buf), subset));
+        // Fewer or more nodes than serialized
+        for x in [0, 1, 3, Octopus83::PORS_OCTOPUS_CAPACITY as u32] {
+            let mut invalid = bytes.clone();
+            LittleEndian::write_u32(&mut invalid[count..(count + 4)], x);
+            let nodes = parse(&invalid);
+            if x < 2 {
+                assert!(nodes.is_none());
+            } else {
+                assert_eq!(nodes.unwrap().len(), x as usize * Octopus83::HASH_BYTES);
+            }
+        }
     }
 
-    fn bench_merkle_compress_octopus_pors<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::PORS_K]:,
-    {
-        let src = vec![hash::tests::HASH_ELEMENT; P::PORS_T];
-        let mut buf = merkle::MerkleBuf::new(P::PORS_TAU);
-        hash::hash_parallel(buf.slice_leaves_mut(), &src, P::PORS_T);
-
-        let subset = fake_pors_subset::<P>();
-        let (_, octopus) = merkle_gen_octopus::<P>(&mut buf, subset);
-
-        let mut nodes = subset.map(|i| src[i]);
-        b.iter(|| {
-            merkle_compress_octopus(
-                black_box(&mut nodes),
-                black_box(&octopus),
-                P::PORS_TAU,
-                subset,
-            )
-        });
-    }
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+
+        macro_rules! all_benches {
+            ( $mod:ident, $params:ty ) => {
+                crate::tests::param_benches!(
+                    $mod,
+                    $params,
+                    bench_merkle_gen_octopus_pors,
+                    bench_merkle_compress_octopus_pors,
+                );
+            };
+        }
 
-    fn fake_pors_subset<P: GravityParams>() -> [usize; P::PORS_K] {
-        let seed = hash::tests::HASH_ELEMENT;
-        let prng = prng::Prng::new(&seed);
-        let address = address::Address::new(0, 0);
+        all_benches!(benches_small, GravitySmall);
+        all_benches!(benches_medium, GravityMedium);
+        all_benches!(benches_large, GravityLarge);
+
+        use crate::address;
+        use crate::prng::SecretPrng;
+        use arrayref::array_ref;
+        use byteorder::{BigEndian, ByteOrder};
+        use std::hint::black_box;
+        use test::Bencher;
+
+        #[bench]
+        fn bench_merkle_gen_octopus_8_4(b: &mut Bencher) {
+            const HEIGHT: usize = 3;
+            let src = [hash::tests::HASH_ELEMENT; 1 << HEIGHT];
+            let indices = [0, 2, 3, 6];
+            b.iter(|| merkle_gen_octopus_leaves::<Octopus84>(black_box(&src), HEIGHT, indices));
+        }
 
-        let mut subset: [usize; P::PORS_K] = [0; P::PORS_K];
-        let mut count = 0;
-        let mut counter = 1;
-        let mut block = Default::default();
+        #[bench]
+        fn bench_merkle_compress_octopus_8_3(b: &mut Bencher) {
+            const HEIGHT: usize = 3;
+            let src = [hash::tests::HASH_ELEMENT; 1 << HEIGHT];
+            let indices = [0, 2, 3];
+
+            let (_, octopus) = merkle_gen_octopus_leaves::<Octopus83>(&src, HEIGHT, indices);
+
+            let mut nodes = indices.map(|i| src[i]);
+            b.iter(|| {
+                merkle_compress_octopus(
+                    &Tweak::NONE,
+                    black_box(&mut nodes),
+                    black_box(&octopus).nodes().iter().copied(),
+                    1 << HEIGHT,
+                    &mut indices.clone(),
+                )
+            })
+        }
 
-        'outer: while count < P::PORS_K {
-            prng.genblock(&mut block, &address, counter);
-            'inner: for i in 0..8 {
-                let x = BigEndian::read_u32(array_ref![block.h, 4 * i, 4]) as usize;
-                let x = x % P::PORS_T;
+        fn bench_merkle_gen_octopus_pors<P: GravityParams<HashFunction = Haraka>>(b: &mut Bencher) {
+            let src = vec![hash::tests::HASH_ELEMENT; P::PORS_T];
+            let mut buf = merkle::MerkleBuf::with_leaf_count(P::PORS_T);
+            hash::hash_parallel(&Tweak::NONE, buf.slice_leaves_mut(), &src, P::PORS_T);
 
-                if subset[..count].contains(&x) {
-                    continue 'inner;
-                }
+            let subset = fake_pors_subset::<P>();
+            b.iter(|| merkle_gen_octopus::<P>(&Tweak::NONE, black_box(&mut buf), subset));
+        }
+
+        fn bench_merkle_compress_octopus_pors<P: GravityParams<HashFunction = Haraka>>(
+            b: &mut Bencher,
+        ) {
+            let src = vec![hash::tests::HASH_ELEMENT; P::PORS_T];
+            let mut buf = merkle::MerkleBuf::with_leaf_count(P::PORS_T);
+            hash::hash_parallel(&Tweak::NONE, buf.slice_leaves_mut(), &src, P::PORS_T);
+
+            let subset = fake_pors_subset::<P>();
+            let (_, octopus) = merkle_gen_octopus::<P>(&Tweak::NONE, &mut buf, subset);
+
+            let mut nodes: P::PorsKArray<Hash> = Array::from_fn(|i| src[subset[i]]);
+            b.iter(|| {
+                let mut indices = subset;
+                merkle_compress_octopus(
+                    &Tweak::NONE,
+                    black_box(nodes.as_mut()),
+                    black_box(&octopus).nodes().iter().copied(),
+                    P::PORS_T,
+                    indices.as_mut(),
+                )
+            });
+        }
 
-                subset[count] = x;
-                count += 1;
-                if count == P::PORS_K {
-                    break 'outer;
+        fn fake_pors_subset<P: GravityParams>() -> P::PorsKArray<usize> {
+            let seed = hash::tests::HASH_ELEMENT;
+            let prng = P::Prng::new(&seed);
+            let address = address::Address::new(0, 0);
+
+            let mut subset: P::PorsKArray<usize> = Array::from_fn(|_| 0);
+            let mut count = 0;
+            let mut counter = 1;
+            let mut block = Default::default();
+
+            'outer: while count < P::PORS_K {
+                prng.genblock(&mut block, &address, counter);
+                'inner: for i in 0..8 {
+                    let x = BigEndian::read_u32(array_ref![block.h, 4 * i, 4]) as usize;
+                    let x = x % P::PORS_T;
+
+                    if subset.as_ref()[..count].contains(&x) {
+                        continue 'inner;
+                    }
+
+                    subset[count] = x;
+                    count += 1;
+                    if count == P::PORS_K {
+                        break 'outer;
+                    }
                 }
+                counter += 1;
             }
-            counter += 1;
-        }
 
-        subset.sort();
-        subset
+            subset.as_mut().sort();
+            subset
+        }
     }
 }
@@ -1,15 +1,26 @@
 use crate::address;
+use crate::array::Array;
 use crate::config::*;
 use crate::hash;
-use crate::hash::Hash;
+#[cfg(feature = "alloc")]
+use crate::hash::HashFunction;
+use crate::hash::{Hash, Tweak, TweakKind};
+#[cfg(feature = "alloc")]
 use crate::merkle;
 use crate::octopus;
-use crate::prng;
+use crate::prng::SecretPrng;
+#[cfg(feature = "alloc")]
+use alloc::vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
 use arrayref::array_ref;
 use byteorder::{BigEndian, ByteOrder};
-use std::array;
-use std::marker::PhantomData;
+#[cfg(feature = "alloc")]
+use core::marker::PhantomData;
+#[cfg(test)]
+use subtle::{Choice, ConstantTimeEq};
 
+#[cfg(feature = "alloc")]
 pub struct SecKey<P: GravityParams> {
     values: Vec<Hash>,
     _phantom: PhantomData<P>,
@@ -21,19 +32,18 @@ pub struct PubKey<P: GravityParams> {
     _phantom: PhantomData<P>,
 }
 
-pub struct Signature<P: GravityParams>
-where
-    [(); P::PORS_K]:,
-{
+pub struct Signature<P: GravityParams> {
     pepper: Hash,
-    values: [Hash; P::PORS_K],
+    values: P::PorsKArray<Hash>,
     octopus: octopus::Octopus<P>,
 }
 
+#[cfg(feature = "alloc")]
 impl<P: GravityParams> SecKey<P> {
-    pub fn new(prng: &prng::Prng, address: &address::Address) -> Self {
+    pub fn new(prng: &P::Prng, address: &address::Address) -> Self {
         let mut values = vec![Default::default(); P::PORS_T];
         prng.genblocks(values.as_mut_slice(), address);
+        values.iter_mut().for_each(P::HashFunction::truncate);
         Self {
             values,
             _phantom: PhantomData,
@@ -41,25 +51,33 @@ impl<P: GravityParams> SecKey<P> {
     }
 
     #[cfg(test)]
-    pub fn genpk(&self) -> PubKey<P> {
+    pub fn genpk(&self, seed: Option<&Hash>, address: &address::Address) -> PubKey<P> {
         let mut buf = vec![Default::default(); P::PORS_T];
-        hash::hash_parallel_all(buf.as_mut_slice(), self.values.as_slice());
+        let leaf_tweak = Tweak::<P::HashFunction>::new(seed, address, TweakKind::PorsLeaf);
+        hash::hash_parallel_all(&leaf_tweak, buf.as_mut_slice(), self.values.as_slice());
+        let node_tweak = Tweak::<P::HashFunction>::new(seed, address, TweakKind::PorsNode);
         PubKey {
-            h: merkle::merkle_compress_all_leaves(buf.as_slice(), P::PORS_TAU),
+            h: merkle::merkle_compress_all_leaves(&node_tweak, buf.as_slice()),
             _phantom: PhantomData,
         }
     }
 
     #[allow(clippy::needless_range_loop)]
-    pub fn sign_subset(&self, pepper: Hash, subset: [usize; P::PORS_K]) -> (Hash, Signature<P>)
-    where
-        [(); P::PORS_K]:,
-    {
-        let values = array::from_fn(|i| self.values[subset[i]]);
-
-        let mut buf = merkle::MerkleBuf::new(P::PORS_TAU);
-        hash::hash_parallel(buf.slice_leaves_mut(), self.values.as_slice(), P::PORS_T);
-        let (root, octopus) = octopus::merkle_gen_octopus(&mut buf, subset);
+    pub fn sign_subset(
+        &self,
+        seed: Option<&Hash>,
+        address: &address::Address,
+        pepper: Hash,
+        subset: P::PorsKArray<usize>,
+    ) -> (Hash, Signature<P>) {
+        let values = Array::from_fn(|i| self.values[subset[i]]);
+
+        let mut buf = merkle::MerkleBuf::with_leaf_count(P::PORS_T);
+        let leaf_tweak = Tweak::<P::HashFunction>::new(seed, address, TweakKind::PorsLeaf);
+        let values_all = self.values.as_slice();
+        hash::hash_parallel(&leaf_tweak, buf.slice_leaves_mut(), values_all, P::PORS_T);
+        let node_tweak = Tweak::<P::HashFunction>::new(seed, address, TweakKind::PorsNode);
+        let (root, octopus) = octopus::merkle_gen_octopus(&node_tweak, &mut buf, subset);
 
         let sign = Signature {
             pepper,
@@ -72,28 +90,18 @@ impl<P: GravityParams> SecKey<P> {
 
 #[cfg(test)]
 impl<P: GravityParams> PubKey<P> {
-    pub fn verify(&self, sign: &Signature<P>, msg: &Hash) -> bool
-    where
-        [(); P::PORS_K]:,
-    {
-        if let Some((_, h)) = sign.extract(msg) {
-            self.h == h
-        } else {
-            false
+    pub fn verify(&self, seed: Option<&Hash>, sign: &Signature<P>, msg: &Hash) -> Choice {
+        match sign.extract(seed, msg) {
+            Some((_, h)) => self.h.ct_eq(&h),
+            None => Choice::from(0),
         }
     }
 }
 
-impl<P: GravityParams> Signature<P>
-where
-    [(); P::PORS_K]:,
-{
-    pub fn extract(&self, msg: &Hash) -> Option<(address::Address, Hash)> {
-        let (address, subset) = obtain_address_subset(&self.pepper, msg);
-        let mut nodes = [Default::default(); P::PORS_K];
-        hash::hash_parallel_all(&mut nodes, &self.values);
-        let root = octopus::merkle_compress_octopus(&mut nodes, &self.octopus, P::PORS_TAU, subset);
-        root.map(|h| (address, h))
+impl<P: GravityParams> Signature<P> {
+    pub fn extract(&self, seed: Option<&Hash>, msg: &Hash) -> Option<(address::Address, Hash)> {
+        let octopus = self.octopus.nodes().iter().copied();
+        extract_octopus::<P, _>(seed, &self.pepper, &self.values, octopus, msg)
     }
 
     #[cfg(test)]
@@ -106,10 +114,11 @@ where
         1 + P::PORS_K + octopus::Octopus::<P>::max_size_hashes()
     }
 
+    #[cfg(feature = "alloc")]
     pub fn serialize(&self, output: &mut Vec<u8>) {
-        self.pepper.serialize(output);
-        for x in self.values.iter() {
-            x.serialize(output);
+        self.pepper.serialize(P::HASH_BYTES, output);
+        for x in self.values.as_ref().iter() {
+            x.serialize(P::HASH_BYTES, output);
         }
         self.octopus.serialize(output);
     }
@@ -118,8 +127,8 @@ where
     where
         I: Iterator<Item = &'a u8>,
     {
-        let pepper = Hash::deserialize(it)?;
-        let values = array::try_from_fn(|_| Hash::deserialize(it))?;
+        let pepper = Hash::deserialize(P::HASH_BYTES, it)?;
+        let values = Array::try_from_fn(|_| Hash::deserialize(P::HASH_BYTES, it))?;
         let octopus = octopus::Octopus::deserialize(it)?;
 
         Some(Signature {
@@ -130,27 +139,120 @@ where
     }
 }
 
-pub fn sign<P: GravityParams>(
-    prng: &prng::Prng,
-    salt: &Hash,
+// Same as `Signature::extract`, but directly on the `P::PORS_SIGNATURE_BYTES` bytes of a
+// serialized signature. The octopus is read in place rather than copied.
+pub fn extract_serialized<P: GravityParams>(
+    seed: Option<&Hash>,
+    bytes: &[u8],
     msg: &Hash,
-) -> (address::Address, Hash, Signature<P>)
+) -> Option<(address::Address, Hash)> {
+    let mut it = bytes.iter();
+    let pepper = Hash::deserialize(P::HASH_BYTES, &mut it)?;
+    let values: P::PorsKArray<Hash> =
+        Array::try_from_fn(|_| Hash::deserialize(P::HASH_BYTES, &mut it))?;
+    let oct = octopus::serialized_nodes::<P>(it.as_slice())?;
+    let octopus = oct.chunks_exact(P::HASH_BYTES).map(Hash::from_slice);
+    extract_octopus::<P, _>(seed, &pepper, &values, octopus, msg)
+}
+
+fn extract_octopus<P: GravityParams, I>(
+    seed: Option<&Hash>,
+    pepper: &Hash,
+    values: &P::PorsKArray<Hash>,
+    octopus: I,
+    msg: &Hash,
+) -> Option<(address::Address, Hash)>
 where
-    [(); P::PORS_K]:,
+    I: Iterator<Item = Hash>,
 {
-    let pepper = hash::hash_2n_to_n_ret(salt, msg);
-    let (address, subset) = obtain_address_subset(&pepper, msg);
+    let (address, mut subset) = obtain_address_subset::<P>(seed, pepper, msg);
+    // The subset is sorted and unique by construction, but the octopus only authenticates such
+    // indices, so this is checked rather than assumed.
+    if !octopus::valid_indices(subset.as_ref(), P::PORS_T) {
+        return None;
+    }
+
+    let leaf_tweak = Tweak::<P::HashFunction>::new(seed, &address, TweakKind::PorsLeaf);
+    let mut nodes: P::PorsKArray<Hash> =
+        Array::from_fn(|i| hash::tweak_n_to_n_ret(&leaf_tweak.with_index(subset[i]), &values[i]));
+    let node_tweak = Tweak::<P::HashFunction>::new(seed, &address, TweakKind::PorsNode);
+    let root = octopus::merkle_compress_octopus(
+        &node_tweak,
+        nodes.as_mut(),
+        octopus,
+        P::PORS_T,
+        subset.as_mut(),
+    );
+    root.map(|h| (address, h))
+}
+
+#[cfg(feature = "alloc")]
+pub fn sign<P: GravityParams>(
+    prng: &P::Prng,
+    seed: Option<&Hash>,
+    salt: &Hash,
+    msg: &Hash,
+) -> (address::Address, Hash, Signature<P>) {
+    let pepper = P::HashFunction::hash_2n_to_n_ret(salt, msg);
+    let (address, subset) = obtain_address_subset::<P>(seed, &pepper, msg);
 
     let sk = SecKey::new(prng, &address);
-    let (root, sign) = sk.sign_subset(pepper, subset);
+    let (root, sign) = sk.sign_subset(seed, &address, pepper, subset);
+    (address, root, sign)
+}
+
+/// Same as [`sign`], but in O(PORS_TAU * PORS_K) memory rather than O(PORS_T). The secret values
+/// are derived one at a time with [`SecretPrng::genblock`], and each node of the octopus is
+/// computed as the root of its subtree, so that the tree is never stored. The signature is the
+/// same, for about the same number of hashes.
+#[cfg(feature = "alloc")]
+pub fn sign_streaming<P: GravityParams>(
+    prng: &P::Prng,
+    seed: Option<&Hash>,
+    salt: &Hash,
+    msg: &Hash,
+) -> (address::Address, Hash, Signature<P>) {
+    let pepper = P::HashFunction::hash_2n_to_n_ret(salt, msg);
+    let (address, mut subset) = obtain_address_subset::<P>(seed, &pepper, msg);
+
+    let value = |i: usize| {
+        let mut x = Default::default();
+        prng.genblock(&mut x, &address, i as u32);
+        P::HashFunction::truncate(&mut x);
+        x
+    };
+    let leaf_tweak = Tweak::<P::HashFunction>::new(seed, &address, TweakKind::PorsLeaf);
+    let leaf = |i: usize| hash::tweak_n_to_n_ret(&leaf_tweak.with_index(i), &value(i));
+
+    let node_tweak = Tweak::<P::HashFunction>::new(seed, &address, TweakKind::PorsNode);
+    let octopus =
+        octopus::merkle_gen_octopus_streaming::<P, _>(&node_tweak, &leaf, P::PORS_T, subset);
+
+    let values: P::PorsKArray<Hash> = Array::from_fn(|i| value(subset[i]));
+    let mut nodes: P::PorsKArray<Hash> = Array::from_fn(|i| leaf(subset[i]));
+    let root = octopus::merkle_compress_octopus(
+        &node_tweak,
+        nodes.as_mut(),
+        octopus.nodes().iter().copied(),
+        P::PORS_T,
+        subset.as_mut(),
+    )
+    .expect("the octopus is complete");
+
+    let sign = Signature {
+        pepper,
+        values,
+        octopus,
+    };
     (address, root, sign)
 }
 
 #[allow(clippy::needless_range_loop)]
 fn obtain_address_subset<P: GravityParams>(
+    seed: Option<&Hash>,
     pepper: &Hash,
     msg: &Hash,
-) -> (address::Address, [usize; P::PORS_K]) {
+) -> (address::Address, P::PorsKArray<usize>) {
     // TODO: Make this const when supported.
     assert!(P::PORS_K > 0, "PORS is only implemented for PORS_K > 0");
     assert!(
@@ -158,24 +260,30 @@ fn obtain_address_subset<P: GravityParams>(
         "PORS is only implemented for PORS_K <= PORS_T"
     );
 
-    let seed = hash::hash_2n_to_n_ret(pepper, msg);
-    let prng = prng::Prng::new(&seed);
     let address = address::Address::new(0, 0);
+    let tweak = Tweak::<P::HashFunction>::new(seed, &address, TweakKind::Message);
+    let prng = P::Prng::new(&hash::tweak_2n_to_n_ret(&tweak, pepper, msg));
 
     let mut block = Default::default();
     prng.genblock(&mut block, &address, 0);
     let instance: u64 = BigEndian::read_u64(array_ref![block.h, 24, 8]);
     let instance = instance & P::GRAVITY_MASK;
 
-    let mut subset: [usize; P::PORS_K] = [0; P::PORS_K];
+    let mut subset: P::PorsKArray<usize> = Array::from_fn(|_| 0);
     let mut count = 0;
     let mut counter = 1;
+    // Values from the largest multiple of PORS_T on are rejected, so that the indices are
+    // uniform when PORS_T is not a power of two.
+    let limit = (1u64 << 32) / P::PORS_T as u64 * P::PORS_T as u64;
 
     'outer: while count < P::PORS_K {
         prng.genblock(&mut block, &address, counter);
         'inner: for i in 0..8 {
-            let x = BigEndian::read_u32(array_ref![block.h, 4 * i, 4]) as usize;
-            let x = x % P::PORS_T;
+            let x = BigEndian::read_u32(array_ref![block.h, 4 * i, 4]) as u64;
+            if x >= limit {
+                continue 'inner;
+            }
+            let x = (x % P::PORS_T as u64) as usize;
 
             for i in 0..count {
                 if subset[i] == x {
@@ -192,30 +300,95 @@ fn obtain_address_subset<P: GravityParams>(
         counter += 1;
     }
 
-    subset.sort();
+    subset.as_mut().sort_unstable();
     (address::Address::new(P::GRAVITY_D as u32, instance), subset)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use byteorder::LittleEndian;
 
     macro_rules! all_tests {
         ( $mod:ident, $params:ty ) => {
-            crate::tests::param_tests!($mod, $params, test_signature_size, test_sign_verify,);
+            crate::tests::param_tests!(
+                $mod,
+                $params,
+                test_signature_size,
+                test_sign_verify,
+                test_sign_streaming,
+            );
         };
     }
 
     all_tests!(small, GravitySmall);
     all_tests!(medium, GravityMedium);
     all_tests!(large, GravityLarge);
+    all_tests!(small_tweaked, GravitySmallTweaked);
+    all_tests!(small_sha2, GravitySmallSha2);
+    all_tests!(small_shake, GravitySmallShake);
+    all_tests!(small_128, GravitySmall128);
+    all_tests!(small_192, GravitySmall192);
+
+    // The streaming signer derives each value with `genblock` rather than `genblocks`.
+    crate::tests::param_tests!(small_chacha20, SmallChaCha20, test_sign_streaming,);
+    crate::tests::param_tests!(small_shake_prng, SmallShakePrng, test_sign_streaming,);
+    crate::tests::param_tests!(small_octopus, GravitySmall, test_extract_invalid_octopus,);
+    crate::tests::param_tests!(
+        small_odd_t,
+        SmallOddT,
+        test_sign_verify,
+        test_sign_streaming,
+        test_extract_invalid_octopus,
+    );
 
-    fn test_signature_size<P: GravityParams>()
-    where
-        [(); P::PORS_K]:,
-    {
+    // GravitySmall with a PORS tree of 3 * 2^14 leaves rather than 2^16.
+    #[derive(Debug, PartialEq)]
+    struct SmallOddT;
+
+    impl GravityParams for SmallOddT {
+        fn config_type() -> ConfigType {
+            ConfigType::Unknown
+        }
+
+        fn check_params() {
+            // TODO: Move this implementation to the trait when supported.
+            const {
+                assert!(Self::PORS_K > 0);
+                assert!(Self::PORS_K <= Self::PORS_T);
+                assert!(Self::PORS_T <= 1 << Self::PORS_TAU);
+                assert!(2 * Self::PORS_T > 1 << Self::PORS_TAU);
+            };
+        }
+
+        const TAU: usize = GravitySmall::TAU;
+        const T: usize = 3 << 14;
+        const K: usize = GravitySmall::K;
+        const H: usize = GravitySmall::H;
+        const D: usize = GravitySmall::D;
+        const C: usize = GravitySmall::C;
+        const OID: &'static [u32] = &[];
+
+        type HashFunction = crate::hash::Haraka;
+        type Prng = crate::prng::AesCtrPrng;
+        type PorsKArray<T: Copy> = [T; Self::PORS_K];
+        type OctopusArray<T: Copy> = [T; Self::PORS_OCTOPUS_CAPACITY];
+        type MerkleHArray<T: Copy> = [T; Self::MERKLE_H];
+        type GravityCArray<T: Copy> = [T; Self::GRAVITY_C];
+        type GravityDArray<T> = [T; Self::GRAVITY_D];
+        type WotsArray<T: Copy> = [T; Self::WOTS_ELL];
+    }
+
+    fn test_signature_size<P: GravityParams>() {
         let (expected_min_hashes, expected_max_hashes) = match P::config_type() {
-            ConfigType::S => (36, 313),
+            ConfigType::S
+            | ConfigType::ST
+            | ConfigType::SSha2
+            | ConfigType::SShake
+            | ConfigType::S128
+            | ConfigType::S192
+            | ConfigType::SW4
+            | ConfigType::SW256 => (36, 313),
             ConfigType::M => (44, 385),
             ConfigType::L => (40, 365),
             ConfigType::Unknown => unimplemented!(),
@@ -224,142 +397,240 @@ mod tests {
         assert_eq!(Signature::<P>::max_size_hashes(), expected_max_hashes);
     }
 
-    fn test_sign_verify<P: GravityParams>()
-    where
-        [(); P::PORS_K]:,
-    {
+    fn test_sign_verify<P: GravityParams>() {
         let seed = hash::tests::HASH_ELEMENT;
         let salt = hash::tests::HASH_ELEMENT;
         let msg = hash::tests::HASH_ELEMENT;
 
-        let prng = prng::Prng::new(&seed);
-        let pepper = hash::hash_2n_to_n_ret(&salt, &msg);
-        let (address, subset) = obtain_address_subset(&pepper, &msg);
+        let pub_seed = P::TWEAKED.then_some(&seed);
+
+        let prng = P::Prng::new(&seed);
+        let pepper = P::HashFunction::hash_2n_to_n_ret(&salt, &msg);
+        let (address, subset) = obtain_address_subset::<P>(pub_seed, &pepper, &msg);
 
         let sk = SecKey::<P>::new(&prng, &address);
-        let pk = sk.genpk();
-        let (_, sign) = sk.sign_subset(pepper, subset);
+        let pk = sk.genpk(pub_seed, &address);
+        let (_, sign) = sk.sign_subset(pub_seed, &address, pepper, subset);
 
-        assert!(pk.verify(&sign, &msg));
-    }
+        assert!(bool::from(pk.verify(pub_seed, &sign, &msg)));
 
-    macro_rules! all_benches {
-        ( $mod:ident, $params:ty ) => {
-            crate::tests::param_benches!(
-                $mod,
-                $params,
-                bench_obtain_address_subset,
-                bench_keypair,
-                bench_gensk,
-                bench_genpk,
-                bench_sign,
-                bench_verify,
-            );
-        };
+        let mut bytes = Vec::new();
+        sign.serialize(&mut bytes);
+        assert_eq!(bytes.len(), P::PORS_SIGNATURE_BYTES);
+        let (address_serialized, h) = extract_serialized::<P>(pub_seed, &bytes, &msg).unwrap();
+        assert!(address_serialized == address);
+        assert_eq!(h, pk.h);
     }
 
-    all_benches!(benches_small, GravitySmall);
-    all_benches!(benches_medium, GravityMedium);
-    all_benches!(benches_large, GravityLarge);
-
-    use std::hint::black_box;
-    use test::Bencher;
-
-    fn bench_obtain_address_subset<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::PORS_K]:,
-    {
+    fn test_sign_streaming<P: GravityParams>() {
+        let seed = hash::tests::HASH_ELEMENT;
         let salt = hash::tests::HASH_ELEMENT;
-        let msg = hash::tests::HASH_ELEMENT;
-
-        let pepper = hash::hash_2n_to_n_ret(&salt, &msg);
-        b.iter(|| obtain_address_subset::<P>(black_box(&pepper), black_box(&msg)));
+        let pub_seed = P::TWEAKED.then_some(&seed);
+        let prng = P::Prng::new(&seed);
+
+        for i in 0..4u8 {
+            let msg = P::HashFunction::long_hash(&[i]);
+            let (address, root, sign) = sign::<P>(&prng, pub_seed, &salt, &msg);
+            let (address_streaming, root_streaming, sign_streaming) =
+                sign_streaming::<P>(&prng, pub_seed, &salt, &msg);
+            assert!(address_streaming == address);
+            assert_eq!(root_streaming, root);
+
+            let mut bytes = Vec::new();
+            sign.serialize(&mut bytes);
+            let mut bytes_streaming = Vec::new();
+            sign_streaming.serialize(&mut bytes_streaming);
+            assert_eq!(bytes_streaming, bytes);
+        }
     }
 
-    fn bench_keypair<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::PORS_K]:,
-    {
+    fn test_extract_invalid_octopus<P: GravityParams>() {
         let seed = hash::tests::HASH_ELEMENT;
         let salt = hash::tests::HASH_ELEMENT;
         let msg = hash::tests::HASH_ELEMENT;
+        let pub_seed = P::TWEAKED.then_some(&seed);
+        let prng = P::Prng::new(&seed);
+
+        let (_, root, sign) = sign::<P>(&prng, pub_seed, &salt, &msg);
+        let mut bytes = Vec::new();
+        sign.serialize(&mut bytes);
+
+        // Extracts the root both from the deserialized signature and in place, which must agree.
+        let extract = |bytes: &[u8]| {
+            let extracted = Signature::<P>::deserialize(&mut bytes.iter())
+                .and_then(|sign| sign.extract(pub_seed, &msg))
+                .map(|(_, h)| h);
+            let serialized = extract_serialized::<P>(pub_seed, bytes, &msg).map(|(_, h)| h);
+            assert_eq!(extracted, serialized);
+            serialized
+        };
+        assert_eq!(extract(&bytes), Some(root));
 
-        let prng = prng::Prng::new(&seed);
-        b.iter(|| {
-            let pepper = hash::hash_2n_to_n_ret(black_box(&salt), black_box(&msg));
-            let (address, _) = obtain_address_subset::<P>(&pepper, &msg);
+        let oct_offset = (1 + P::PORS_K) * P::HASH_BYTES;
+        let count_offset = oct_offset + P::PORS_OCTOPUS_CAPACITY * P::HASH_BYTES;
+        let slot = |i: usize| oct_offset + i * P::HASH_BYTES;
+        let set_count = |bytes: &mut [u8], count: u32| {
+            LittleEndian::write_u32(&mut bytes[count_offset..(count_offset + 4)], count)
+        };
+        let len = sign.octopus.nodes().len();
+        assert!(len > 0 && len < P::PORS_OCTOPUS_CAPACITY);
+
+        // Too few nodes, without the last one.
+        let mut invalid = bytes.clone();
+        set_count(&mut invalid, len as u32 - 1);
+        invalid[slot(len - 1)..slot(len)].fill(0);
+        assert_eq!(extract(&invalid), None);
+
+        // Too many nodes, with an empty or a non-empty extra node.
+        let mut invalid = bytes.clone();
+        set_count(&mut invalid, len as u32 + 1);
+        assert_eq!(extract(&invalid), None);
+        invalid[slot(len)] = 1;
+        assert_eq!(extract(&invalid), None);
+
+        // Nodes in the padding, after the counted nodes.
+        for i in [slot(len), slot(len + 1) - 1, count_offset - 1] {
+            let mut invalid = bytes.clone();
+            invalid[i] = 1;
+            assert_eq!(extract(&invalid), None);
+        }
 
-            let sk = SecKey::<P>::new(black_box(&prng), &address);
-            sk.genpk()
-        });
+        // Counts exceeding the capacity.
+        let capacity = P::PORS_OCTOPUS_CAPACITY as u32;
+        for count in [capacity + 1, 1 << 16, u32::MAX] {
+            let mut invalid = bytes.clone();
+            set_count(&mut invalid, count);
+            assert_eq!(extract(&invalid), None);
+        }
+
+        // Non-zero reserved bytes in the count block.
+        for i in 4..16 {
+            let mut invalid = bytes.clone();
+            invalid[count_offset + i] = 1;
+            assert_eq!(extract(&invalid), None);
+        }
+
+        // A modified node gives another root.
+        let mut modified = bytes.clone();
+        modified[slot(0)] ^= 1;
+        let h = extract(&modified);
+        assert!(h.is_some() && h != Some(root));
     }
 
-    fn bench_gensk<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::PORS_K]:,
-    {
-        let seed = hash::tests::HASH_ELEMENT;
-        let salt = hash::tests::HASH_ELEMENT;
-        let msg = hash::tests::HASH_ELEMENT;
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+
+        macro_rules! all_benches {
+            ( $mod:ident, $params:ty ) => {
+                crate::tests::param_benches!(
+                    $mod,
+                    $params,
+                    bench_obtain_address_subset,
+                    bench_keypair,
+                    bench_gensk,
+                    bench_genpk,
+                    bench_sign,
+                    bench_sign_streaming,
+                    bench_verify,
+                );
+            };
+        }
 
-        let prng = prng::Prng::new(&seed);
-        b.iter(|| {
-            let pepper = hash::hash_2n_to_n_ret(black_box(&salt), black_box(&msg));
-            let (address, _) = obtain_address_subset::<P>(&pepper, &msg);
+        all_benches!(benches_small, GravitySmall);
+        all_benches!(benches_medium, GravityMedium);
+        all_benches!(benches_large, GravityLarge);
 
-            SecKey::<P>::new(black_box(&prng), &address)
-        });
-    }
+        use std::hint::black_box;
+        use test::Bencher;
 
-    fn bench_genpk<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::PORS_K]:,
-    {
-        let seed = hash::tests::HASH_ELEMENT;
-        let salt = hash::tests::HASH_ELEMENT;
-        let msg = hash::tests::HASH_ELEMENT;
+        fn bench_obtain_address_subset<P: GravityParams>(b: &mut Bencher) {
+            let salt = hash::tests::HASH_ELEMENT;
+            let msg = hash::tests::HASH_ELEMENT;
 
-        let prng = prng::Prng::new(&seed);
-        let pepper = hash::hash_2n_to_n_ret(&salt, &msg);
-        let (address, _) = obtain_address_subset::<P>(&pepper, &msg);
+            let pepper = P::HashFunction::hash_2n_to_n_ret(&salt, &msg);
+            b.iter(|| obtain_address_subset::<P>(None, black_box(&pepper), black_box(&msg)));
+        }
 
-        let sk = SecKey::<P>::new(&prng, &address);
-        b.iter(|| sk.genpk());
-    }
+        fn bench_keypair<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let salt = hash::tests::HASH_ELEMENT;
+            let msg = hash::tests::HASH_ELEMENT;
 
-    fn bench_sign<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::PORS_K]:,
-    {
-        let seed = hash::tests::HASH_ELEMENT;
-        let salt = hash::tests::HASH_ELEMENT;
-        let msg = hash::tests::HASH_ELEMENT;
+            let prng = P::Prng::new(&seed);
+            b.iter(|| {
+                let pepper = P::HashFunction::hash_2n_to_n_ret(black_box(&salt), black_box(&msg));
+                let (address, _) = obtain_address_subset::<P>(None, &pepper, &msg);
 
-        let prng = prng::Prng::new(&seed);
-        let pepper = hash::hash_2n_to_n_ret(&salt, &msg);
-        let (address, subset) = obtain_address_subset(&pepper, &msg);
+                let sk = SecKey::<P>::new(black_box(&prng), &address);
+                sk.genpk(None, &address)
+            });
+        }
 
-        let sk = SecKey::<P>::new(&prng, &address);
-        b.iter(|| sk.sign_subset(black_box(pepper), black_box(subset)));
-    }
+        fn bench_gensk<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let salt = hash::tests::HASH_ELEMENT;
+            let msg = hash::tests::HASH_ELEMENT;
 
-    fn bench_verify<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::PORS_K]:,
-    {
-        let seed = hash::tests::HASH_ELEMENT;
-        let salt = hash::tests::HASH_ELEMENT;
-        let msg = hash::tests::HASH_ELEMENT;
+            let prng = P::Prng::new(&seed);
+            b.iter(|| {
+                let pepper = P::HashFunction::hash_2n_to_n_ret(black_box(&salt), black_box(&msg));
+                let (address, _) = obtain_address_subset::<P>(None, &pepper, &msg);
 
-        let prng = prng::Prng::new(&seed);
-        let pepper = hash::hash_2n_to_n_ret(&salt, &msg);
-        let (address, subset) = obtain_address_subset(&pepper, &msg);
+                SecKey::<P>::new(black_box(&prng), &address)
+            });
+        }
 
-        let sk = SecKey::<P>::new(&prng, &address);
-        let pk = sk.genpk();
-        let (_, sign) = sk.sign_subset(pepper, subset);
-        b.iter(|| pk.verify(black_box(&sign), black_box(&msg)));
-    }
+        fn bench_genpk<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let salt = hash::tests::HASH_ELEMENT;
+            let msg = hash::tests::HASH_ELEMENT;
+
+            let prng = P::Prng::new(&seed);
+            let pepper = P::HashFunction::hash_2n_to_n_ret(&salt, &msg);
+            let (address, _) = obtain_address_subset::<P>(None, &pepper, &msg);
 
-    // TODO: test vectors
+            let sk = SecKey::<P>::new(&prng, &address);
+            b.iter(|| sk.genpk(None, &address));
+        }
+
+        fn bench_sign<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let salt = hash::tests::HASH_ELEMENT;
+            let msg = hash::tests::HASH_ELEMENT;
+
+            let prng = P::Prng::new(&seed);
+            let pepper = P::HashFunction::hash_2n_to_n_ret(&salt, &msg);
+            let (address, subset) = obtain_address_subset::<P>(None, &pepper, &msg);
+
+            let sk = SecKey::<P>::new(&prng, &address);
+            b.iter(|| sk.sign_subset(None, &address, black_box(pepper), black_box(subset)));
+        }
+
+        fn bench_sign_streaming<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let salt = hash::tests::HASH_ELEMENT;
+            let msg = hash::tests::HASH_ELEMENT;
+
+            let prng = P::Prng::new(&seed);
+            b.iter(|| sign_streaming::<P>(&prng, None, &salt, black_box(&msg)));
+        }
+
+        fn bench_verify<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let salt = hash::tests::HASH_ELEMENT;
+            let msg = hash::tests::HASH_ELEMENT;
+
+            let prng = P::Prng::new(&seed);
+            let pepper = P::HashFunction::hash_2n_to_n_ret(&salt, &msg);
+            let (address, subset) = obtain_address_subset::<P>(None, &pepper, &msg);
+
+            let sk = SecKey::<P>::new(&prng, &address);
+            let pk = sk.genpk(None, &address);
+            let (_, sign) = sk.sign_subset(None, &address, pepper, subset);
+            b.iter(|| pk.verify(None, black_box(&sign), black_box(&msg)));
+        }
+
+        // TODO: test vectors
+    }
 }
@@ -1,6 +1,13 @@
+// With AES-NI, blocks are encrypted with the dedicated instructions. Otherwise, a bitsliced
+// implementation is used, as table-based AES would leak the key through cache timings.
+#[cfg(not(target_feature = "aes"))]
+use super::aes256_bitsliced;
+#[cfg(target_feature = "aes")]
 use super::simd128::Simd128;
+#[cfg(target_feature = "aes")]
 use arrayref::array_ref;
 
+#[cfg(target_feature = "aes")]
 #[inline(always)]
 fn assist256_1(a: &mut Simd128, mut b: Simd128) {
     b = Simd128::pshufd::<0xff>(&b);
@@ -14,6 +21,7 @@ fn assist256_1(a: &mut Simd128, mut b: Simd128) {
     Simd128::pxor(a, &b);
 }
 
+#[cfg(target_feature = "aes")]
 #[inline(always)]
 fn assist256_2(mut a: Simd128, b: &mut Simd128) {
     a = Simd128::pshufd::<0xaa>(&a);
@@ -27,6 +35,7 @@ fn assist256_2(mut a: Simd128, b: &mut Simd128) {
     Simd128::pxor(b, &a);
 }
 
+#[cfg(target_feature = "aes")]
 #[inline(always)]
 fn expand256(key: &[u8; 32], rkeys: &mut [Simd128; 15]) {
     let mut key0_xmm = Simd128::read(array_ref![key, 0, 16]);
@@ -77,6 +86,7 @@ fn expand256(key: &[u8; 32], rkeys: &mut [Simd128; 15]) {
     rkeys[14] = key0_xmm;
 }
 
+#[cfg(target_feature = "aes")]
 pub fn expand256_slice(key: &[u8; 32], rkeys: &mut [[u8; 16]; 15]) {
     let mut rkeys_xmm = [Simd128::from(0); 15];
     expand256(key, &mut rkeys_xmm);
@@ -85,6 +95,12 @@ pub fn expand256_slice(key: &[u8; 32], rkeys: &mut [[u8; 16]; 15]) {
     }
 }
 
+#[cfg(not(target_feature = "aes"))]
+pub fn expand256_slice(key: &[u8; 32], rkeys: &mut [[u8; 16]; 15]) {
+    aes256_bitsliced::expand256(key, rkeys);
+}
+
+#[cfg(target_feature = "aes")]
 #[allow(clippy::needless_range_loop)]
 fn aes256_rkeys_xmm(dst: &mut [u8; 16], src: &[u8; 16], rkeys: &[Simd128; 15]) {
     let mut state_xmm = Simd128::read(src);
@@ -98,22 +114,72 @@ fn aes256_rkeys_xmm(dst: &mut [u8; 16], src: &[u8; 16], rkeys: &[Simd128; 15]) {
     state_xmm.write(dst);
 }
 
-pub fn aes256_rkeys_slice(dst: &mut [u8; 16], src: &[u8; 16], rkeys: &[[u8; 16]; 15]) {
+#[cfg(target_feature = "aes")]
+fn read_rkeys(rkeys: &[[u8; 16]; 15]) -> [Simd128; 15] {
     let mut rkeys_xmm = [Simd128::from(0); 15];
     for i in 0..15 {
         rkeys_xmm[i] = Simd128::read(&rkeys[i]);
     }
+    rkeys_xmm
+}
 
-    aes256_rkeys_xmm(dst, src, &rkeys_xmm);
+#[cfg(test)]
+pub fn aes256_rkeys_slice(dst: &mut [u8; 16], src: &[u8; 16], rkeys: &[[u8; 16]; 15]) {
+    *dst = *src;
+    aes256_rkeys_blocks(core::slice::from_mut(dst), rkeys);
+}
+
+// Encrypts 8 blocks with interleaved rounds, so that the latency of each AES instruction is
+// hidden by the other blocks. The round keys stay in registers for the whole batch.
+#[cfg(target_feature = "aes")]
+#[allow(clippy::needless_range_loop)]
+fn aes256_rkeys_xmm8(blocks: &mut [[u8; 16]; 8], rkeys: &[Simd128; 15]) {
+    let mut state_xmm: [Simd128; 8] = core::array::from_fn(|j| Simd128::read(&blocks[j]));
+
+    for j in 0..8 {
+        Simd128::pxor(&mut state_xmm[j], &rkeys[0]);
+    }
+    for i in 1..14 {
+        for j in 0..8 {
+            Simd128::aesenc(&mut state_xmm[j], &rkeys[i]);
+        }
+    }
+    for j in 0..8 {
+        Simd128::aesenclast(&mut state_xmm[j], &rkeys[14]);
+    }
+
+    for j in 0..8 {
+        state_xmm[j].write(&mut blocks[j]);
+    }
+}
+
+/// Encrypts the blocks in place. Encrypting several blocks at once is faster: AES-NI pipelines
+/// eight blocks, and the bitsliced implementation processes four blocks in parallel.
+#[cfg(target_feature = "aes")]
+pub fn aes256_rkeys_blocks(blocks: &mut [[u8; 16]], rkeys: &[[u8; 16]; 15]) {
+    let rkeys_xmm = read_rkeys(rkeys);
+    let mut chunks = blocks.chunks_exact_mut(8);
+    for chunk in &mut chunks {
+        aes256_rkeys_xmm8(chunk.try_into().unwrap(), &rkeys_xmm);
+    }
+    for block in chunks.into_remainder() {
+        let src = *block;
+        aes256_rkeys_xmm(block, &src, &rkeys_xmm);
+    }
+}
+
+#[cfg(not(target_feature = "aes"))]
+pub fn aes256_rkeys_blocks(blocks: &mut [[u8; 16]], rkeys: &[[u8; 16]; 15]) {
+    aes256_bitsliced::aes256_blocks(blocks, rkeys);
 }
 
 #[cfg(test)]
 pub fn aes256_ret(src: &[u8; 16], key: &[u8; 32]) -> [u8; 16] {
-    let mut rkeys = [Simd128::from(0); 15];
-    expand256(key, &mut rkeys);
+    let mut rkeys = [[0u8; 16]; 15];
+    expand256_slice(key, &mut rkeys);
 
     let mut dst = [0u8; 16];
-    aes256_rkeys_xmm(&mut dst, src, &rkeys);
+    aes256_rkeys_slice(&mut dst, src, &rkeys);
     dst
 }
 
@@ -122,7 +188,7 @@ mod tests {
     use super::*;
     use crate::primitives::constants;
     use crate::primitives::simd128;
-    use arrayref::array_mut_ref;
+    use arrayref::{array_mut_ref, array_ref};
 
     #[test]
     fn test_aes256() {
@@ -152,6 +218,21 @@ mod tests {
         assert_eq!(&dst, expect);
     }
 
+    #[test]
+    fn test_aes256_blocks() {
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let mut rkeys = [[0u8; 16]; 15];
+        expand256_slice(&key, &mut rkeys);
+
+        // A full batch of 8 blocks, followed by a partial one.
+        let src: [[u8; 16]; 11] = core::array::from_fn(|i| [i as u8; 16]);
+        let mut blocks = src;
+        aes256_rkeys_blocks(&mut blocks, &rkeys);
+        for (block, src) in blocks.iter().zip(src.iter()) {
+            assert_eq!(*block, aes256_ret(src, &key));
+        }
+    }
+
     fn subbytes(state: &mut [u8; 16]) {
         for x in state.iter_mut() {
             *x = constants::AES_SBOX[*x as usize];
@@ -360,48 +441,71 @@ mod tests {
         assert_eq!(rkeys, rkeys_bis);
     }
 
-    use std::hint::black_box;
-    use test::Bencher;
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+
+        use std::hint::black_box;
+        use test::Bencher;
+
+        #[cfg(target_feature = "aes")]
+        #[bench]
+        fn bench_expand256(b: &mut Bencher) {
+            let key = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                        \x10\x11\x12\x13\x14\x15\x16\x17\
+                        \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+            b.iter(|| {
+                let mut rkeys = [Simd128::from(0); 15];
+                expand256(black_box(key), &mut rkeys);
+                rkeys
+            });
+        }
 
-    #[bench]
-    fn bench_expand256(b: &mut Bencher) {
-        let key = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                    \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                    \x10\x11\x12\x13\x14\x15\x16\x17\
-                    \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
-        b.iter(|| {
+        #[cfg(target_feature = "aes")]
+        #[bench]
+        fn bench_aes256_rkeys_xmm(b: &mut Bencher) {
+            let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f";
+            let key = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                        \x10\x11\x12\x13\x14\x15\x16\x17\
+                        \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
             let mut rkeys = [Simd128::from(0); 15];
-            expand256(black_box(key), &mut rkeys);
-            rkeys
-        });
-    }
+            expand256(key, &mut rkeys);
 
-    #[bench]
-    fn bench_aes256_rkeys_xmm(b: &mut Bencher) {
-        let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                    \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f";
-        let key = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                    \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                    \x10\x11\x12\x13\x14\x15\x16\x17\
-                    \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
-        let mut rkeys = [Simd128::from(0); 15];
-        expand256(key, &mut rkeys);
+            b.iter(|| {
+                let mut dst = [0u8; 16];
+                aes256_rkeys_xmm(&mut dst, black_box(src), black_box(&rkeys));
+                dst
+            });
+        }
 
-        b.iter(|| {
-            let mut dst = [0u8; 16];
-            aes256_rkeys_xmm(&mut dst, black_box(src), black_box(&rkeys));
-            dst
-        });
-    }
+        #[bench]
+        fn bench_aes256_rkeys_blocks_8(b: &mut Bencher) {
+            let key = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                        \x10\x11\x12\x13\x14\x15\x16\x17\
+                        \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+            let mut rkeys = [[0u8; 16]; 15];
+            expand256_slice(key, &mut rkeys);
+
+            b.iter(|| {
+                let mut blocks: [[u8; 16]; 8] = core::array::from_fn(|i| [i as u8; 16]);
+                aes256_rkeys_blocks(&mut blocks, black_box(&rkeys));
+                blocks
+            });
+        }
 
-    #[bench]
-    fn bench_aes256(b: &mut Bencher) {
-        let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                    \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f";
-        let key = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                    \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                    \x10\x11\x12\x13\x14\x15\x16\x17\
-                    \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
-        b.iter(|| aes256_ret(black_box(src), black_box(key)));
+        #[bench]
+        fn bench_aes256(b: &mut Bencher) {
+            let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f";
+            let key = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                        \x10\x11\x12\x13\x14\x15\x16\x17\
+                        \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+            b.iter(|| aes256_ret(black_box(src), black_box(key)));
+        }
     }
 }
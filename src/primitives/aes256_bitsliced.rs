@@ -0,0 +1,398 @@
+// Constant-time software AES-256, used when AES-NI is not available. Four blocks are encrypted at
+// once in bitsliced form, following the "ct64" implementation of BearSSL: the state is stored in
+// eight 64-bit words, word `i` holding bit `i` of all the bytes of the four blocks. The S-box is
+// then a boolean circuit, and no memory access depends on the key or the data.
+use super::constants::AES_RCON;
+use arrayref::{array_mut_ref, array_ref};
+use byteorder::{ByteOrder, LittleEndian};
+
+type State = [u64; 8];
+
+// Boolean circuit of the S-box by Boyar and Peralta, see "A depth-16 circuit for the AES S-box".
+#[allow(clippy::many_single_char_names)]
+fn sbox(q: &mut State) {
+    let x0 = q[7];
+    let x1 = q[6];
+    let x2 = q[5];
+    let x3 = q[4];
+    let x4 = q[3];
+    let x5 = q[2];
+    let x6 = q[1];
+    let x7 = q[0];
+
+    // Top linear transformation.
+    let y14 = x3 ^ x5;
+    let y13 = x0 ^ x6;
+    let y9 = x0 ^ x3;
+    let y8 = x0 ^ x5;
+    let t0 = x1 ^ x2;
+    let y1 = t0 ^ x7;
+    let y4 = y1 ^ x3;
+    let y12 = y13 ^ y14;
+    let y2 = y1 ^ x0;
+    let y5 = y1 ^ x6;
+    let y3 = y5 ^ y8;
+    let t1 = x4 ^ y12;
+    let y15 = t1 ^ x5;
+    let y20 = t1 ^ x1;
+    let y6 = y15 ^ x7;
+    let y10 = y15 ^ t0;
+    let y11 = y20 ^ y9;
+    let y7 = x7 ^ y11;
+    let y17 = y10 ^ y11;
+    let y19 = y10 ^ y8;
+    let y16 = t0 ^ y11;
+    let y21 = y13 ^ y16;
+    let y18 = x0 ^ y16;
+
+    // Non-linear section.
+    let t2 = y12 & y15;
+    let t3 = y3 & y6;
+    let t4 = t3 ^ t2;
+    let t5 = y4 & x7;
+    let t6 = t5 ^ t2;
+    let t7 = y13 & y16;
+    let t8 = y5 & y1;
+    let t9 = t8 ^ t7;
+    let t10 = y2 & y7;
+    let t11 = t10 ^ t7;
+    let t12 = y9 & y11;
+    let t13 = y14 & y17;
+    let t14 = t13 ^ t12;
+    let t15 = y8 & y10;
+    let t16 = t15 ^ t12;
+    let t17 = t4 ^ t14;
+    let t18 = t6 ^ t16;
+    let t19 = t9 ^ t14;
+    let t20 = t11 ^ t16;
+    let t21 = t17 ^ y20;
+    let t22 = t18 ^ y19;
+    let t23 = t19 ^ y21;
+    let t24 = t20 ^ y18;
+
+    let t25 = t21 ^ t22;
+    let t26 = t21 & t23;
+    let t27 = t24 ^ t26;
+    let t28 = t25 & t27;
+    let t29 = t28 ^ t22;
+    let t30 = t23 ^ t24;
+    let t31 = t22 ^ t26;
+    let t32 = t31 & t30;
+    let t33 = t32 ^ t24;
+    let t34 = t23 ^ t33;
+    let t35 = t27 ^ t33;
+    let t36 = t24 & t35;
+    let t37 = t36 ^ t34;
+    let t38 = t27 ^ t36;
+    let t39 = t29 & t38;
+    let t40 = t25 ^ t39;
+
+    let t41 = t40 ^ t37;
+    let t42 = t29 ^ t33;
+    let t43 = t29 ^ t40;
+    let t44 = t33 ^ t37;
+    let t45 = t42 ^ t41;
+    let z0 = t44 & y15;
+    let z1 = t37 & y6;
+    let z2 = t33 & x7;
+    let z3 = t43 & y16;
+    let z4 = t40 & y1;
+    let z5 = t29 & y7;
+    let z6 = t42 & y11;
+    let z7 = t45 & y17;
+    let z8 = t41 & y10;
+    let z9 = t44 & y12;
+    let z10 = t37 & y3;
+    let z11 = t33 & y4;
+    let z12 = t43 & y13;
+    let z13 = t40 & y5;
+    let z14 = t29 & y2;
+    let z15 = t42 & y9;
+    let z16 = t45 & y14;
+    let z17 = t41 & y8;
+
+    // Bottom linear transformation.
+    let t46 = z15 ^ z16;
+    let t47 = z10 ^ z11;
+    let t48 = z5 ^ z13;
+    let t49 = z9 ^ z10;
+    let t50 = z2 ^ z12;
+    let t51 = z2 ^ z5;
+    let t52 = z7 ^ z8;
+    let t53 = z0 ^ z3;
+    let t54 = z6 ^ z7;
+    let t55 = z16 ^ z17;
+    let t56 = z12 ^ t48;
+    let t57 = t50 ^ t53;
+    let t58 = z4 ^ t46;
+    let t59 = z3 ^ t54;
+    let t60 = t46 ^ t57;
+    let t61 = z14 ^ t57;
+    let t62 = t52 ^ t58;
+    let t63 = t49 ^ t58;
+    let t64 = z4 ^ t59;
+    let t65 = t61 ^ t62;
+    let t66 = z1 ^ t63;
+    let s0 = t59 ^ t63;
+    let s6 = t56 ^ !t62;
+    let s7 = t48 ^ !t60;
+    let t67 = t64 ^ t65;
+    let s3 = t53 ^ t66;
+    let s4 = t51 ^ t66;
+    let s5 = t47 ^ t65;
+    let s1 = t64 ^ !s3;
+    let s2 = t55 ^ !t67;
+
+    q[7] = s0;
+    q[6] = s1;
+    q[5] = s2;
+    q[4] = s3;
+    q[3] = s4;
+    q[2] = s5;
+    q[1] = s6;
+    q[0] = s7;
+}
+
+// Transposes the bits of the state, to convert between the bitsliced and the interleaved forms.
+// This is an involution.
+fn ortho(q: &mut State) {
+    #[inline(always)]
+    fn swap(q: &mut State, i: usize, j: usize, low: u64, shift: u32) {
+        let (a, b) = (q[i], q[j]);
+        q[i] = (a & low) | ((b & low) << shift);
+        q[j] = ((a & !low) >> shift) | (b & !low);
+    }
+
+    for i in (0..8).step_by(2) {
+        swap(q, i, i + 1, 0x5555555555555555, 1);
+    }
+    for i in [0, 1, 4, 5] {
+        swap(q, i, i + 2, 0x3333333333333333, 2);
+    }
+    for i in 0..4 {
+        swap(q, i, i + 4, 0x0f0f0f0f0f0f0f0f, 4);
+    }
+}
+
+// Spreads the 16 bytes of a block over two words, so that the block occupies one quarter of the
+// bits of the state once transposed.
+fn interleave_in(block: &[u8; 16]) -> (u64, u64) {
+    let mut x = [0u64; 4];
+    for (i, x) in x.iter_mut().enumerate() {
+        *x = LittleEndian::read_u32(array_ref![block, 4 * i, 4]) as u64;
+        *x |= *x << 16;
+        *x &= 0x0000ffff0000ffff;
+        *x |= *x << 8;
+        *x &= 0x00ff00ff00ff00ff;
+    }
+    (x[0] | (x[2] << 8), x[1] | (x[3] << 8))
+}
+
+fn interleave_out(block: &mut [u8; 16], q0: u64, q1: u64) {
+    let mut x = [
+        q0 & 0x00ff00ff00ff00ff,
+        q1 & 0x00ff00ff00ff00ff,
+        (q0 >> 8) & 0x00ff00ff00ff00ff,
+        (q1 >> 8) & 0x00ff00ff00ff00ff,
+    ];
+    for (i, x) in x.iter_mut().enumerate() {
+        *x |= *x >> 8;
+        *x &= 0x0000ffff0000ffff;
+        LittleEndian::write_u32(
+            array_mut_ref![block, 4 * i, 4],
+            *x as u32 | (*x >> 16) as u32,
+        );
+    }
+}
+
+fn load(blocks: &[[u8; 16]]) -> State {
+    let mut q = [0; 8];
+    for (i, block) in blocks.iter().enumerate() {
+        (q[i], q[i + 4]) = interleave_in(block);
+    }
+    ortho(&mut q);
+    q
+}
+
+fn store(blocks: &mut [[u8; 16]], mut q: State) {
+    ortho(&mut q);
+    for (i, block) in blocks.iter_mut().enumerate() {
+        interleave_out(block, q[i], q[i + 4]);
+    }
+}
+
+fn shift_rows(q: &mut State) {
+    for x in q.iter_mut() {
+        *x = (*x & 0x000000000000ffff)
+            | ((*x & 0x00000000fff00000) >> 4)
+            | ((*x & 0x00000000000f0000) << 12)
+            | ((*x & 0x0000ff0000000000) >> 8)
+            | ((*x & 0x000000ff00000000) << 8)
+            | ((*x & 0xf000000000000000) >> 12)
+            | ((*x & 0x0fff000000000000) << 4);
+    }
+}
+
+fn mix_columns(q: &mut State) {
+    let r: State = core::array::from_fn(|i| q[i].rotate_right(16));
+    let s: State = core::array::from_fn(|i| (q[i] ^ r[i]).rotate_right(32));
+    let q7 = q[7] ^ r[7];
+    *q = [
+        q7 ^ r[0] ^ s[0],
+        q[0] ^ r[0] ^ q7 ^ r[1] ^ s[1],
+        q[1] ^ r[1] ^ r[2] ^ s[2],
+        q[2] ^ r[2] ^ q7 ^ r[3] ^ s[3],
+        q[3] ^ r[3] ^ q7 ^ r[4] ^ s[4],
+        q[4] ^ r[4] ^ r[5] ^ s[5],
+        q[5] ^ r[5] ^ r[6] ^ s[6],
+        q[6] ^ r[6] ^ r[7] ^ s[7],
+    ];
+}
+
+fn add_round_key(q: &mut State, rkey: &State) {
+    for (x, k) in q.iter_mut().zip(rkey) {
+        *x ^= k;
+    }
+}
+
+fn encrypt(q: &mut State, rkeys: &[State; 15]) {
+    add_round_key(q, &rkeys[0]);
+    for rkey in rkeys[1..14].iter() {
+        sbox(q);
+        shift_rows(q);
+        mix_columns(q);
+        add_round_key(q, rkey);
+    }
+    sbox(q);
+    shift_rows(q);
+    add_round_key(q, &rkeys[14]);
+}
+
+fn sub_word(x: u32) -> u32 {
+    let mut q = [0; 8];
+    q[0] = x as u64;
+    ortho(&mut q);
+    sbox(&mut q);
+    ortho(&mut q);
+    q[0] as u32
+}
+
+pub fn expand256(key: &[u8; 32], rkeys: &mut [[u8; 16]; 15]) {
+    let mut words = [0u32; 60];
+    LittleEndian::read_u32_into(key, &mut words[..8]);
+    for i in 8..60 {
+        let mut word = words[i - 1];
+        if i % 8 == 0 {
+            word = sub_word(word.rotate_right(8)) ^ AES_RCON[i / 8 - 1] as u32;
+        } else if i % 8 == 4 {
+            word = sub_word(word);
+        }
+        words[i] = words[i - 8] ^ word;
+    }
+    for (rkey, words) in rkeys.iter_mut().zip(words.chunks_exact(4)) {
+        LittleEndian::write_u32_into(words, rkey);
+    }
+}
+
+/// Encrypts the blocks in place, four at a time.
+pub fn aes256_blocks(blocks: &mut [[u8; 16]], rkeys: &[[u8; 16]; 15]) {
+    // Each round key is repeated for the four blocks.
+    let rkeys: [State; 15] = core::array::from_fn(|i| load(&[rkeys[i]; 4]));
+    for chunk in blocks.chunks_mut(4) {
+        let mut q = load(chunk);
+        encrypt(&mut q, &rkeys);
+        store(chunk, q);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::constants::AES_SBOX;
+
+    #[test]
+    fn test_sbox() {
+        for x in 0..=255u8 {
+            let bytes = [x, x ^ 0x55, !x, x.rotate_left(3)];
+            let word = sub_word(u32::from_le_bytes(bytes));
+            assert_eq!(
+                word,
+                u32::from_le_bytes(bytes.map(|x| AES_SBOX[x as usize]))
+            );
+        }
+    }
+
+    #[test]
+    fn test_ortho() {
+        let mut q: State =
+            core::array::from_fn(|i| 0x0123456789abcdef_u64.rotate_left(8 * i as u32));
+        let expect = q;
+        ortho(&mut q);
+        assert_ne!(q, expect);
+        ortho(&mut q);
+        assert_eq!(q, expect);
+    }
+
+    // FIPS 197, appendix A.3.
+    #[test]
+    fn test_expand256_fips197() {
+        let key = *b"\x60\x3d\xeb\x10\x15\xca\x71\xbe\
+                     \x2b\x73\xae\xf0\x85\x7d\x77\x81\
+                     \x1f\x35\x2c\x07\x3b\x61\x08\xd7\
+                     \x2d\x98\x10\xa3\x09\x14\xdf\xf4";
+        let mut rkeys = [[0; 16]; 15];
+        expand256(&key, &mut rkeys);
+        assert_eq!(
+            rkeys[2],
+            *b"\x9b\xa3\x54\x11\x8e\x69\x25\xaf\xa5\x1a\x8b\x5f\x20\x67\xfc\xde"
+        );
+        assert_eq!(
+            rkeys[14],
+            *b"\xfe\x48\x90\xd1\xe6\x18\x8d\x0b\x04\x6d\xf3\x44\x70\x6c\x63\x1e"
+        );
+    }
+
+    // NIST SP 800-38A, F.1.5 ECB-AES256.Encrypt, with the four blocks encrypted at once.
+    #[test]
+    fn test_aes256_nist() {
+        let key = *b"\x60\x3d\xeb\x10\x15\xca\x71\xbe\
+                     \x2b\x73\xae\xf0\x85\x7d\x77\x81\
+                     \x1f\x35\x2c\x07\x3b\x61\x08\xd7\
+                     \x2d\x98\x10\xa3\x09\x14\xdf\xf4";
+        let mut blocks = [
+            *b"\x6b\xc1\xbe\xe2\x2e\x40\x9f\x96\xe9\x3d\x7e\x11\x73\x93\x17\x2a",
+            *b"\xae\x2d\x8a\x57\x1e\x03\xac\x9c\x9e\xb7\x6f\xac\x45\xaf\x8e\x51",
+            *b"\x30\xc8\x1c\x46\xa3\x5c\xe4\x11\xe5\xfb\xc1\x19\x1a\x0a\x52\xef",
+            *b"\xf6\x9f\x24\x45\xdf\x4f\x9b\x17\xad\x2b\x41\x7b\xe6\x6c\x37\x10",
+        ];
+        let expect = [
+            *b"\xf3\xee\xd1\xbd\xb5\xd2\xa0\x3c\x06\x4b\x5a\x7e\x3d\xb1\x81\xf8",
+            *b"\x59\x1c\xcb\x10\xd4\x10\xed\x26\xdc\x5b\xa7\x4a\x31\x36\x28\x70",
+            *b"\xb6\xed\x21\xb9\x9c\xa6\xf4\xf9\xf1\x53\xe7\xb1\xbe\xaf\xed\x1d",
+            *b"\x23\x30\x4b\x7a\x39\xf9\xf3\xff\x06\x7d\x8d\x8f\x9e\x24\xec\xc7",
+        ];
+        let mut rkeys = [[0; 16]; 15];
+        expand256(&key, &mut rkeys);
+        aes256_blocks(&mut blocks, &rkeys);
+        assert_eq!(blocks, expect);
+
+        // A partial chunk gives the same result.
+        let mut blocks = [*b"\x6b\xc1\xbe\xe2\x2e\x40\x9f\x96\xe9\x3d\x7e\x11\x73\x93\x17\x2a"];
+        aes256_blocks(&mut blocks, &rkeys);
+        assert_eq!(blocks[0], expect[0]);
+    }
+
+    // FIPS 197, appendix C.3.
+    #[test]
+    fn test_aes256_fips197() {
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let mut blocks = [*b"\x00\x11\x22\x33\x44\x55\x66\x77\x88\x99\xaa\xbb\xcc\xdd\xee\xff"];
+        let mut rkeys = [[0; 16]; 15];
+        expand256(&key, &mut rkeys);
+        aes256_blocks(&mut blocks, &rkeys);
+        assert_eq!(
+            blocks[0],
+            *b"\x8e\xa2\xb7\xca\x51\x67\x45\xbf\xea\xfc\x49\x90\x4b\x49\x60\x89"
+        );
+    }
+}
@@ -51,7 +51,7 @@ pub(crate) static HARAKA_CONSTANTS: &[Simd128; 48] = &[
     Simd128::from(0x02f7f57fdb2dc1ddbd03239fe3e67e4a),
 ];
 
-#[cfg(test)]
+#[cfg(any(test, not(target_feature = "aes")))]
 pub static AES_RCON: [u8; 7] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40];
 
 #[cfg(test)]
@@ -24,7 +24,11 @@ fn aes_mix2(s0: &mut Simd128, s1: &mut Simd128, rci: usize) {
     mix2(s0, s1);
 }
 
+/// Haraka-256 v2 with `N_ROUNDS` rounds, which compresses 32 bytes into 32 bytes. The
+/// specification uses 5 rounds, and the round constants are defined for up to 6 rounds.
 pub fn haraka256<const N_ROUNDS: usize>(dst: &mut [u8; 32], src: &[u8; 32]) {
+    const { assert!(N_ROUNDS <= 6, "Haraka is defined for up to 6 rounds") };
+
     let mut s0 = Simd128::read(array_ref![src, 0, 16]);
     let mut s1 = Simd128::read(array_ref![src, 16, 16]);
 
@@ -147,30 +151,35 @@ mod tests {
         assert_eq!(&dst, expect);
     }
 
-    use std::hint::black_box;
-    use test::Bencher;
-
-    fn haraka256_through<const N_ROUNDS: usize>(src: &[u8; 32]) -> [u8; 32] {
-        let mut dst = [0; 32];
-        haraka256::<N_ROUNDS>(&mut dst, src);
-        dst
-    }
-
-    #[bench]
-    fn bench_haraka256_5round(b: &mut Bencher) {
-        let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                    \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                    \x10\x11\x12\x13\x14\x15\x16\x17\
-                    \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
-        b.iter(|| haraka256_through::<5>(black_box(src)));
-    }
-
-    #[bench]
-    fn bench_haraka256_6round(b: &mut Bencher) {
-        let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                    \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                    \x10\x11\x12\x13\x14\x15\x16\x17\
-                    \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
-        b.iter(|| haraka256_through::<6>(black_box(src)));
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+
+        use std::hint::black_box;
+        use test::Bencher;
+
+        fn haraka256_through<const N_ROUNDS: usize>(src: &[u8; 32]) -> [u8; 32] {
+            let mut dst = [0; 32];
+            haraka256::<N_ROUNDS>(&mut dst, src);
+            dst
+        }
+
+        #[bench]
+        fn bench_haraka256_5round(b: &mut Bencher) {
+            let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                        \x10\x11\x12\x13\x14\x15\x16\x17\
+                        \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+            b.iter(|| haraka256_through::<5>(black_box(src)));
+        }
+
+        #[bench]
+        fn bench_haraka256_6round(b: &mut Bencher) {
+            let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                        \x10\x11\x12\x13\x14\x15\x16\x17\
+                        \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+            b.iter(|| haraka256_through::<6>(black_box(src)));
+        }
     }
 }
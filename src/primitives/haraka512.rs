@@ -43,7 +43,11 @@ fn truncstore(dst: &mut [u8; 32], s0: &Simd128, s1: &Simd128, s2: &Simd128, s3:
     Simd128::unpacklo_epi64(s2, s3).write(array_mut_ref![dst, 16, 16]);
 }
 
+/// Haraka-512 v2 with `N_ROUNDS` rounds, which compresses 64 bytes `src0 || src1` into 32 bytes.
+/// The specification uses 5 rounds, and the round constants are defined for up to 6 rounds.
 pub fn haraka512<const N_ROUNDS: usize>(dst: &mut [u8; 32], src0: &[u8; 32], src1: &[u8; 32]) {
+    const { assert!(N_ROUNDS <= 6, "Haraka is defined for up to 6 rounds") };
+
     let mut s0 = Simd128::read(array_ref![src0, 0, 16]);
     let mut s1 = Simd128::read(array_ref![src0, 16, 16]);
     let mut s2 = Simd128::read(array_ref![src1, 0, 16]);
@@ -65,6 +69,27 @@ pub fn haraka512<const N_ROUNDS: usize>(dst: &mut [u8; 32], src0: &[u8; 32], src
     truncstore(dst, &s0, &s1, &s2, &s3);
 }
 
+/// The Haraka-512 permutation with `N_ROUNDS` rounds, which is [`haraka512`] without the
+/// feed-forward and the truncation of the output.
+#[cfg_attr(not(target_feature = "aes"), allow(dead_code))]
+pub fn haraka512_perm<const N_ROUNDS: usize>(state: &mut [u8; 64]) {
+    const { assert!(N_ROUNDS <= 6, "Haraka is defined for up to 6 rounds") };
+
+    let mut s0 = Simd128::read(array_ref![state, 0, 16]);
+    let mut s1 = Simd128::read(array_ref![state, 16, 16]);
+    let mut s2 = Simd128::read(array_ref![state, 32, 16]);
+    let mut s3 = Simd128::read(array_ref![state, 48, 16]);
+
+    for i in 0..N_ROUNDS {
+        aes_mix4(&mut s0, &mut s1, &mut s2, &mut s3, 8 * i);
+    }
+
+    s0.write(array_mut_ref![state, 0, 16]);
+    s1.write(array_mut_ref![state, 16, 16]);
+    s2.write(array_mut_ref![state, 32, 16]);
+    s3.write(array_mut_ref![state, 48, 16]);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -243,70 +268,92 @@ mod tests {
         assert_eq!(&dst, expect);
     }
 
-    use std::hint::black_box;
-    use test::Bencher;
+    #[test]
+    fn test_haraka512_perm() {
+        let src: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let mut state = src;
+        haraka512_perm::<5>(&mut state);
+        for (x, y) in state.iter_mut().zip(src.iter()) {
+            *x ^= y;
+        }
 
-    fn haraka512_through<const N_ROUNDS: usize>(src0: &[u8; 32], src1: &[u8; 32]) -> [u8; 32] {
         let mut dst = [0; 32];
-        haraka512::<N_ROUNDS>(&mut dst, src0, src1);
-        dst
+        haraka512_bis::<5>(&mut dst, &src);
+        assert_eq!(dst[..8], state[8..16]);
+        assert_eq!(dst[8..16], state[24..32]);
+        assert_eq!(dst[16..24], state[32..40]);
+        assert_eq!(dst[24..], state[48..56]);
     }
 
-    fn haraka512_through_bis<const N_ROUNDS: usize>(src: &[u8; 64]) -> [u8; 32] {
-        let mut dst = [0; 32];
-        haraka512_bis::<N_ROUNDS>(&mut dst, src);
-        dst
-    }
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
 
-    #[bench]
-    fn bench_haraka512_5round(b: &mut Bencher) {
-        let src1 = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                     \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                     \x10\x11\x12\x13\x14\x15\x16\x17\
-                     \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
-        let src2 = b"\x20\x21\x22\x23\x24\x25\x26\x27\
-                     \x28\x29\x2a\x2b\x2c\x2d\x2e\x2f\
-                     \x30\x31\x32\x33\x34\x35\x36\x37\
-                     \x38\x39\x3a\x3b\x3c\x3d\x3e\x3f";
-        b.iter(|| haraka512_through::<5>(black_box(src1), black_box(src2)));
-    }
+        use std::hint::black_box;
+        use test::Bencher;
 
-    #[bench]
-    fn bench_haraka512_5round_bis(b: &mut Bencher) {
-        let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                    \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                    \x10\x11\x12\x13\x14\x15\x16\x17\
-                    \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f\
-                    \x20\x21\x22\x23\x24\x25\x26\x27\
-                    \x28\x29\x2a\x2b\x2c\x2d\x2e\x2f\
-                    \x30\x31\x32\x33\x34\x35\x36\x37\
-                    \x38\x39\x3a\x3b\x3c\x3d\x3e\x3f";
-        b.iter(|| haraka512_through_bis::<5>(black_box(src)));
-    }
+        fn haraka512_through<const N_ROUNDS: usize>(src0: &[u8; 32], src1: &[u8; 32]) -> [u8; 32] {
+            let mut dst = [0; 32];
+            haraka512::<N_ROUNDS>(&mut dst, src0, src1);
+            dst
+        }
 
-    #[bench]
-    fn bench_haraka512_6round(b: &mut Bencher) {
-        let src1 = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                     \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                     \x10\x11\x12\x13\x14\x15\x16\x17\
-                     \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
-        let src2 = b"\x20\x21\x22\x23\x24\x25\x26\x27\
-                     \x28\x29\x2a\x2b\x2c\x2d\x2e\x2f\
-                     \x30\x31\x32\x33\x34\x35\x36\x37\
-                     \x38\x39\x3a\x3b\x3c\x3d\x3e\x3f";
-        b.iter(|| haraka512_through::<6>(black_box(src1), black_box(src2)));
-    }
+        fn haraka512_through_bis<const N_ROUNDS: usize>(src: &[u8; 64]) -> [u8; 32] {
+            let mut dst = [0; 32];
+            haraka512_bis::<N_ROUNDS>(&mut dst, src);
+            dst
+        }
 
-    #[bench]
-    fn bench_haraka512_6round_bis(b: &mut Bencher) {
-        let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
-                    \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
-                    \x10\x11\x12\x13\x14\x15\x16\x17\
-                    \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f\
-                    \x20\x21\x22\x23\x24\x25\x26\x27\
-                    \x28\x29\x2a\x2b\x2c\x2d\x2e\x2f\
-                    \x30\x31\x32\x33\x34\x35\x36\x37\
-                    \x38\x39\x3a\x3b\x3c\x3d\x3e\x3f";
-        b.iter(|| haraka512_through_bis::<6>(black_box(src)));
+        #[bench]
+        fn bench_haraka512_5round(b: &mut Bencher) {
+            let src1 = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                         \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                         \x10\x11\x12\x13\x14\x15\x16\x17\
+                         \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+            let src2 = b"\x20\x21\x22\x23\x24\x25\x26\x27\
+                         \x28\x29\x2a\x2b\x2c\x2d\x2e\x2f\
+                         \x30\x31\x32\x33\x34\x35\x36\x37\
+                         \x38\x39\x3a\x3b\x3c\x3d\x3e\x3f";
+            b.iter(|| haraka512_through::<5>(black_box(src1), black_box(src2)));
+        }
+
+        #[bench]
+        fn bench_haraka512_5round_bis(b: &mut Bencher) {
+            let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                        \x10\x11\x12\x13\x14\x15\x16\x17\
+                        \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f\
+                        \x20\x21\x22\x23\x24\x25\x26\x27\
+                        \x28\x29\x2a\x2b\x2c\x2d\x2e\x2f\
+                        \x30\x31\x32\x33\x34\x35\x36\x37\
+                        \x38\x39\x3a\x3b\x3c\x3d\x3e\x3f";
+            b.iter(|| haraka512_through_bis::<5>(black_box(src)));
+        }
+
+        #[bench]
+        fn bench_haraka512_6round(b: &mut Bencher) {
+            let src1 = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                         \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                         \x10\x11\x12\x13\x14\x15\x16\x17\
+                         \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+            let src2 = b"\x20\x21\x22\x23\x24\x25\x26\x27\
+                         \x28\x29\x2a\x2b\x2c\x2d\x2e\x2f\
+                         \x30\x31\x32\x33\x34\x35\x36\x37\
+                         \x38\x39\x3a\x3b\x3c\x3d\x3e\x3f";
+            b.iter(|| haraka512_through::<6>(black_box(src1), black_box(src2)));
+        }
+
+        #[bench]
+        fn bench_haraka512_6round_bis(b: &mut Bencher) {
+            let src = b"\x00\x01\x02\x03\x04\x05\x06\x07\
+                        \x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\
+                        \x10\x11\x12\x13\x14\x15\x16\x17\
+                        \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f\
+                        \x20\x21\x22\x23\x24\x25\x26\x27\
+                        \x28\x29\x2a\x2b\x2c\x2d\x2e\x2f\
+                        \x30\x31\x32\x33\x34\x35\x36\x37\
+                        \x38\x39\x3a\x3b\x3c\x3d\x3e\x3f";
+            b.iter(|| haraka512_through_bis::<6>(black_box(src)));
+        }
     }
 }
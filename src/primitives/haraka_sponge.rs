@@ -0,0 +1,143 @@
+use super::haraka512::haraka512_perm;
+
+/// Number of bytes absorbed and squeezed per call to the permutation.
+pub const HARAKA_SPONGE_RATE: usize = 32;
+
+/// Haraka-Sponge with `N_ROUNDS` rounds: a sponge over the Haraka-512 permutation with a rate and a
+/// capacity of 32 bytes, and the padding of SHAKE. It hashes `src` of any length into `dst` of any
+/// length.
+///
+/// This is the arbitrary-length hash of SPHINCS+-Haraka with 5 rounds, except that SPHINCS+ also
+/// derives the round constants from its public seed.
+pub fn haraka_sponge<const N_ROUNDS: usize>(dst: &mut [u8], src: &[u8]) {
+    let mut state = [0u8; 64];
+
+    let mut blocks = src.chunks_exact(HARAKA_SPONGE_RATE);
+    for block in &mut blocks {
+        xor_into(&mut state, block);
+        haraka512_perm::<N_ROUNDS>(&mut state);
+    }
+    let last = blocks.remainder();
+    xor_into(&mut state, last);
+    state[last.len()] ^= 0x1f;
+    state[HARAKA_SPONGE_RATE - 1] ^= 0x80;
+
+    for block in dst.chunks_mut(HARAKA_SPONGE_RATE) {
+        haraka512_perm::<N_ROUNDS>(&mut state);
+        block.copy_from_slice(&state[..block.len()]);
+    }
+}
+
+fn xor_into(state: &mut [u8; 64], src: &[u8]) {
+    for (x, y) in state.iter_mut().zip(src) {
+        *x ^= y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // There are no official test vectors for the sponge alone, so these tests check it against the
+    // permutation, whose rounds are checked against the official vectors of Haraka-512.
+    #[test]
+    fn test_haraka_sponge_padding() {
+        let src: [u8; 64] = core::array::from_fn(|i| i as u8);
+        for len in [0, 1, 31, 32, 33, 63, 64] {
+            // Pad the message to a multiple of the rate, then absorb it block by block.
+            let mut padded = [0u8; 96];
+            padded[..len].copy_from_slice(&src[..len]);
+            padded[len] = 0x1f;
+            let padded_len = (len / HARAKA_SPONGE_RATE + 1) * HARAKA_SPONGE_RATE;
+            padded[padded_len - 1] |= 0x80;
+
+            let mut state = [0u8; 64];
+            for block in padded[..padded_len].chunks_exact(HARAKA_SPONGE_RATE) {
+                xor_into(&mut state, block);
+                haraka512_perm::<5>(&mut state);
+            }
+
+            let mut dst = [0u8; 32];
+            haraka_sponge::<5>(&mut dst, &src[..len]);
+            assert_eq!(dst, state[..32], "len = {len}");
+        }
+    }
+
+    #[test]
+    fn test_haraka_sponge_empty() {
+        let mut state = [0u8; 64];
+        state[0] = 0x1f;
+        state[31] = 0x80;
+        haraka512_perm::<5>(&mut state);
+        let first = state;
+        haraka512_perm::<5>(&mut state);
+
+        let mut dst = [0u8; 64];
+        haraka_sponge::<5>(&mut dst, b"");
+        assert_eq!(dst[..32], first[..32]);
+        assert_eq!(dst[32..], state[..32]);
+    }
+
+    #[test]
+    fn test_haraka_sponge_squeeze() {
+        let src: [u8; 40] = core::array::from_fn(|i| i as u8);
+        let mut long = [0u8; 100];
+        haraka_sponge::<5>(&mut long, &src);
+        for len in [0, 1, 16, 32, 33, 64, 99] {
+            let mut dst = [0u8; 100];
+            haraka_sponge::<5>(&mut dst[..len], &src);
+            assert_eq!(dst[..len], long[..len]);
+        }
+
+        let mut dst = [0u8; 100];
+        haraka_sponge::<6>(&mut dst, &src);
+        assert_ne!(dst, long);
+    }
+
+    #[test]
+    fn test_haraka_sponge_distinct() {
+        // Messages that differ only by their padding give different hashes.
+        let src = [0x1fu8; 65];
+        let mut hashes = [[0u8; 32]; 66];
+        for (len, hash) in hashes.iter_mut().enumerate() {
+            haraka_sponge::<5>(hash, &src[..len]);
+        }
+        for i in 0..hashes.len() {
+            for j in 0..i {
+                assert_ne!(hashes[i], hashes[j]);
+            }
+        }
+    }
+
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+
+        use std::hint::black_box;
+        use test::Bencher;
+
+        fn haraka_sponge_through<const N_ROUNDS: usize>(src: &[u8]) -> [u8; 32] {
+            let mut dst = [0; 32];
+            haraka_sponge::<N_ROUNDS>(&mut dst, src);
+            dst
+        }
+
+        #[bench]
+        fn bench_haraka_sponge_5round_64(b: &mut Bencher) {
+            let src = [0u8; 64];
+            b.iter(|| haraka_sponge_through::<5>(black_box(&src)));
+        }
+
+        #[bench]
+        fn bench_haraka_sponge_6round_64(b: &mut Bencher) {
+            let src = [0u8; 64];
+            b.iter(|| haraka_sponge_through::<6>(black_box(&src)));
+        }
+
+        #[bench]
+        fn bench_haraka_sponge_5round_1024(b: &mut Bencher) {
+            let src = [0u8; 1024];
+            b.iter(|| haraka_sponge_through::<5>(black_box(&src)));
+        }
+    }
+}
@@ -1,5 +1,16 @@
-pub mod aes256;
+//! The Haraka v2 short-input hash functions, with 5 rounds as in the specification or 6 rounds as
+//! in Gravity-SPHINCS, and the Haraka-Sponge of SPHINCS+-Haraka.
+//!
+//! They run AES-NI instructions, so this module is only public when compiling with the `aes`
+//! target feature, e.g. with `-C target-cpu=native` on a CPU that has it.
+
+pub(crate) mod aes256;
+#[cfg(any(test, not(target_feature = "aes")))]
+mod aes256_bitsliced;
 mod constants;
 pub mod haraka256;
 pub mod haraka512;
+// Only used through the public module, which requires AES-NI.
+#[cfg_attr(not(target_feature = "aes"), allow(dead_code))]
+pub mod haraka_sponge;
 mod simd128;
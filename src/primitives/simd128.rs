@@ -1,16 +1,16 @@
 #[cfg(target_arch = "x86")]
-use std::arch::x86::{
+use core::arch::x86::{
     __m128i, _mm_aesenc_si128, _mm_aesenclast_si128, _mm_aeskeygenassist_si128, _mm_loadu_si128,
     _mm_shuffle_epi32, _mm_slli_si128, _mm_storeu_si128, _mm_unpackhi_epi32, _mm_unpackhi_epi64,
     _mm_unpacklo_epi32, _mm_unpacklo_epi64, _mm_xor_si128,
 };
 #[cfg(target_arch = "x86_64")]
-use std::arch::x86_64::{
+use core::arch::x86_64::{
     __m128i, _mm_aesenc_si128, _mm_aesenclast_si128, _mm_aeskeygenassist_si128, _mm_loadu_si128,
     _mm_shuffle_epi32, _mm_slli_si128, _mm_storeu_si128, _mm_unpackhi_epi32, _mm_unpackhi_epi64,
     _mm_unpacklo_epi32, _mm_unpacklo_epi64, _mm_xor_si128,
 };
-use std::mem::transmute;
+use core::mem::transmute;
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 #[derive(Clone, Copy)]
@@ -44,6 +44,8 @@ impl Simd128 {
         }
     }
 
+    // Only used by AES-256, which is implemented in software without AES-NI.
+    #[cfg_attr(not(target_feature = "aes"), allow(dead_code))]
     #[inline(always)]
     pub(crate) fn aesenclast(block: &mut Self, key: &Self) {
         unsafe {
@@ -51,6 +53,7 @@ impl Simd128 {
         }
     }
 
+    #[cfg_attr(not(target_feature = "aes"), allow(dead_code))]
     #[inline(always)]
     pub(crate) fn aeskeygenassist<const ROUND_CONSTANT: i32>(block: &Self) -> Self {
         unsafe { Self(_mm_aeskeygenassist_si128::<ROUND_CONSTANT>(block.0)) }
@@ -63,11 +66,13 @@ impl Simd128 {
         }
     }
 
+    #[cfg_attr(not(target_feature = "aes"), allow(dead_code))]
     #[inline(always)]
     pub(crate) fn pslldq<const SHIFT: i32>(block: &mut Self) {
         unsafe { block.0 = _mm_slli_si128::<SHIFT>(block.0) }
     }
 
+    #[cfg_attr(not(target_feature = "aes"), allow(dead_code))]
     #[inline(always)]
     pub(crate) fn pshufd<const CONTROL: i32>(block: &Self) -> Self {
         unsafe { Self(_mm_shuffle_epi32::<CONTROL>(block.0)) }
@@ -2,40 +2,127 @@ use crate::address;
 use crate::hash::Hash;
 use crate::primitives::aes256;
 use arrayref::array_mut_ref;
+use byteorder::{BigEndian, ByteOrder};
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use sha3::Shake256;
+use sha3::digest::{ExtendableOutput, Update, XofReader};
 
+/// Pseudo-random generator deriving the secret values of the scheme from the secret seed. Each
+/// output block is indexed by an address and a counter.
+pub trait SecretPrng {
+    fn new(seed: &Hash) -> Self;
+    fn genblock(&self, dst: &mut Hash, address: &address::Address, counter: u32);
+
+    // Fill `dst` with the blocks of counters 0, 1, 2, etc.
+    fn genblocks(&self, dst: &mut [Hash], address: &address::Address) {
+        for (i, x) in dst.iter_mut().enumerate() {
+            self.genblock(x, address, i as u32);
+        }
+    }
+}
+
+/// AES-256 in counter mode, keyed by the seed. Each block is the encryption of two counter
+/// blocks `Address::to_block`.
 #[derive(Default)]
-pub struct Prng {
+pub struct AesCtrPrng {
     rkeys: [[u8; 16]; 15],
 }
 
-impl Prng {
-    pub fn new(seed: &Hash) -> Self {
-        let mut prng: Prng = Default::default();
+impl SecretPrng for AesCtrPrng {
+    fn new(seed: &Hash) -> Self {
+        let mut prng: AesCtrPrng = Default::default();
         aes256::expand256_slice(&seed.h, &mut prng.rkeys);
         prng
     }
 
-    pub fn genblock(&self, dst: &mut Hash, address: &address::Address, counter: u32) {
-        let h = &mut dst.h;
-        aes256::aes256_rkeys_slice(
-            array_mut_ref![h, 0, 16],
-            &address.to_block(2 * counter),
-            &self.rkeys,
-        );
-        aes256::aes256_rkeys_slice(
-            array_mut_ref![h, 16, 16],
-            &address.to_block(2 * counter + 1),
-            &self.rkeys,
-        );
+    fn genblock(&self, dst: &mut Hash, address: &address::Address, counter: u32) {
+        self.genblocks_from(core::slice::from_mut(dst), address, counter);
     }
 
-    pub fn genblocks(&self, dst: &mut [Hash], address: &address::Address) {
-        for (i, x) in dst.iter_mut().enumerate() {
-            self.genblock(x, address, i as u32);
+    fn genblocks(&self, dst: &mut [Hash], address: &address::Address) {
+        for (i, chunk) in dst.chunks_mut(AES_BATCH / 2).enumerate() {
+            self.genblocks_from(chunk, address, (i * AES_BATCH / 2) as u32);
+        }
+    }
+}
+
+// Number of AES blocks encrypted at once, which AES-NI pipelines. The bitsliced software
+// implementation processes them four at a time.
+const AES_BATCH: usize = 8;
+
+impl AesCtrPrng {
+    // Fills `dst` with at most `AES_BATCH / 2` blocks, starting from the given counter.
+    fn genblocks_from(&self, dst: &mut [Hash], address: &address::Address, counter: u32) {
+        let mut blocks = [[0; 16]; AES_BATCH];
+        let blocks = &mut blocks[..2 * dst.len()];
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = address.to_block(2 * counter + i as u32);
+        }
+        aes256::aes256_rkeys_blocks(blocks, &self.rkeys);
+        for (x, blocks) in dst.iter_mut().zip(blocks.chunks_exact(2)) {
+            *array_mut_ref![x.h, 0, 16] = blocks[0];
+            *array_mut_ref![x.h, 16, 16] = blocks[1];
+        }
+    }
+}
+
+/// ChaCha20 keyed by the seed, with the address as the nonce. Each block is 32 bytes of the
+/// keystream, so that the blocks of an address form a contiguous keystream.
+pub struct ChaCha20Prng {
+    key: [u8; 32],
+}
+
+impl ChaCha20Prng {
+    fn cipher(&self, address: &address::Address) -> ChaCha20 {
+        let mut nonce = [0; 12];
+        BigEndian::write_u64(array_mut_ref![nonce, 0, 8], address.get_instance());
+        BigEndian::write_u32(array_mut_ref![nonce, 8, 4], address.get_layer());
+        ChaCha20::new(&self.key.into(), &nonce.into())
+    }
+}
+
+impl SecretPrng for ChaCha20Prng {
+    fn new(seed: &Hash) -> Self {
+        Self { key: seed.h }
+    }
+
+    fn genblock(&self, dst: &mut Hash, address: &address::Address, counter: u32) {
+        let mut cipher = self.cipher(address);
+        cipher.seek(32 * counter as u64);
+        dst.h = [0; 32];
+        cipher.apply_keystream(&mut dst.h);
+    }
+
+    fn genblocks(&self, dst: &mut [Hash], address: &address::Address) {
+        let mut cipher = self.cipher(address);
+        for x in dst.iter_mut() {
+            x.h = [0; 32];
+            cipher.apply_keystream(&mut x.h);
         }
     }
 }
 
+/// SHAKE256 of the seed followed by the counter block `Address::to_block`.
+pub struct ShakePrng {
+    // State after absorbing the seed.
+    hasher: Shake256,
+}
+
+impl SecretPrng for ShakePrng {
+    fn new(seed: &Hash) -> Self {
+        let mut hasher = Shake256::default();
+        hasher.update(&seed.h);
+        Self { hasher }
+    }
+
+    fn genblock(&self, dst: &mut Hash, address: &address::Address, counter: u32) {
+        let mut hasher = self.hasher.clone();
+        hasher.update(&address.to_block(counter));
+        hasher.finalize_xof().read(&mut dst.h);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::hash;
@@ -44,7 +131,7 @@ mod tests {
 
     #[test]
     fn test_genblock_zero() {
-        let prng = Prng::new(&hash::tests::HASH_ELEMENT);
+        let prng = AesCtrPrng::new(&hash::tests::HASH_ELEMENT);
         let mut dst = Default::default();
         prng.genblock(&mut dst, &address::Address::new(0, 0), 0);
 
@@ -67,7 +154,7 @@ mod tests {
     #[test]
     #[allow(clippy::needless_range_loop)]
     fn test_genblocks() {
-        let prng = Prng::new(&hash::tests::HASH_ELEMENT);
+        let prng = AesCtrPrng::new(&hash::tests::HASH_ELEMENT);
         let mut dst = [Default::default(); 3];
         prng.genblocks(&mut dst, &address::Address::new(0, 0));
 
@@ -91,11 +178,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_genblocks_batches() {
+        let prng = AesCtrPrng::new(&hash::tests::HASH_ELEMENT);
+        let address = address::Address::new(0x01020304, 0x05060708090a0b0c);
+        for count in [1, 4, 5, 11] {
+            let mut dst = vec![Default::default(); count];
+            prng.genblocks(&mut dst, &address);
+            for (i, x) in dst.iter().enumerate() {
+                let mut block = Default::default();
+                prng.genblock(&mut block, &address, i as u32);
+                assert_eq!(block, *x);
+            }
+        }
+    }
+
     #[test]
     fn test_kat() {
         use hex;
 
-        let prng = Prng::new(&hash::tests::HASH_ELEMENT);
+        let prng = AesCtrPrng::new(&hash::tests::HASH_ELEMENT);
         let mut dst = [Default::default(); 3];
         prng.genblocks(
             &mut dst,
@@ -114,66 +216,148 @@ mod tests {
         assert_eq!(dst[2].h, *array_ref![expect, 64, 32]);
     }
 
-    macro_rules! all_benches {
-        ( $mod:ident, $params:ty ) => {
-            crate::tests::param_benches!($mod, $params, bench_genblocks_pors,);
+    #[test]
+    fn test_chacha20() {
+        use hex;
+
+        // Test vector of the ChaCha20 block function from RFC 8439, section 2.3.2.
+        let key = Hash {
+            h: core::array::from_fn(|i| i as u8),
         };
+        let prng = ChaCha20Prng::new(&key);
+        let address = address::Address::new(0, 0x000000090000004a);
+        let mut dst = [Default::default(); 4];
+        prng.genblocks(&mut dst, &address);
+
+        let expect = hex::decode(
+            "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e\
+             d2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e",
+        )
+        .unwrap();
+        assert_eq!(dst[2].h, *array_ref![expect, 0, 32]);
+        assert_eq!(dst[3].h, *array_ref![expect, 32, 32]);
+
+        for (i, x) in dst.iter().enumerate() {
+            let mut block = Default::default();
+            prng.genblock(&mut block, &address, i as u32);
+            assert_eq!(block, *x);
+        }
     }
 
-    use crate::config::{self, GravityLarge, GravityMedium, GravityParams, GravitySmall};
-
-    all_benches!(benches_small, GravitySmall);
-    all_benches!(benches_medium, GravityMedium);
-    all_benches!(benches_large, GravityLarge);
-
-    use test::Bencher;
-
-    #[bench]
-    fn bench_genblock(b: &mut Bencher) {
-        let prng = Prng::new(&hash::tests::HASH_ELEMENT);
-        b.iter(|| {
-            let mut dst = Default::default();
-            prng.genblock(&mut dst, &address::Address::new(0, 0), 0);
-            dst
-        });
-    }
-
-    #[bench]
-    fn bench_genblocks_5(b: &mut Bencher) {
-        let prng = Prng::new(&hash::tests::HASH_ELEMENT);
-        b.iter(|| {
-            let mut dst = [Default::default(); 5];
-            prng.genblocks(&mut dst, &address::Address::new(0, 0));
-            dst
-        });
-    }
-
-    #[bench]
-    fn bench_genblocks_20(b: &mut Bencher) {
-        let prng = Prng::new(&hash::tests::HASH_ELEMENT);
-        b.iter(|| {
-            let mut dst = [Default::default(); 20];
-            prng.genblocks(&mut dst, &address::Address::new(0, 0));
-            dst
-        });
-    }
-
-    fn bench_genblocks_pors<P: GravityParams>(b: &mut Bencher) {
-        let prng = Prng::new(&hash::tests::HASH_ELEMENT);
-        b.iter(|| {
-            let mut dst = vec![Default::default(); P::PORS_T];
-            prng.genblocks(&mut dst, &address::Address::new(0, 0));
-            dst
-        });
-    }
-
-    #[bench]
-    fn bench_genblocks_wots(b: &mut Bencher) {
-        let prng = Prng::new(&hash::tests::HASH_ELEMENT);
-        b.iter(|| {
-            let mut dst = [Default::default(); config::WOTS_ELL];
-            prng.genblocks(&mut dst, &address::Address::new(0, 0));
-            dst
-        });
+    #[test]
+    fn test_shake() {
+        let prng = ShakePrng::new(&hash::tests::HASH_ELEMENT);
+        let address = address::Address::new(0x01020304, 0x05060708090a0b0c);
+        let mut dst = [Default::default(); 3];
+        prng.genblocks(&mut dst, &address);
+
+        for (i, x) in dst.iter().enumerate() {
+            let mut hasher = Shake256::default();
+            hasher.update(&hash::tests::HASH_ELEMENT.h);
+            hasher.update(&address.to_block(i as u32));
+            let mut expect = [0; 32];
+            hasher.finalize_xof().read(&mut expect);
+            assert_eq!(x.h, expect);
+        }
+        assert_ne!(dst[0], dst[1]);
+    }
+
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+
+        macro_rules! all_benches {
+            ( $mod:ident, $params:ty ) => {
+                crate::tests::param_benches!(
+                    $mod,
+                    $params,
+                    bench_genblocks_pors,
+                    bench_genblock_loop_pors,
+                );
+            };
+        }
+
+        use crate::config::{GravityLarge, GravityMedium, GravityParams, GravitySmall};
+
+        all_benches!(benches_small, GravitySmall);
+        all_benches!(benches_medium, GravityMedium);
+        all_benches!(benches_large, GravityLarge);
+
+        use test::Bencher;
+
+        #[bench]
+        fn bench_genblock(b: &mut Bencher) {
+            let prng = AesCtrPrng::new(&hash::tests::HASH_ELEMENT);
+            b.iter(|| {
+                let mut dst = Default::default();
+                prng.genblock(&mut dst, &address::Address::new(0, 0), 0);
+                dst
+            });
+        }
+
+        #[bench]
+        fn bench_genblocks_5(b: &mut Bencher) {
+            let prng = AesCtrPrng::new(&hash::tests::HASH_ELEMENT);
+            b.iter(|| {
+                let mut dst = [Default::default(); 5];
+                prng.genblocks(&mut dst, &address::Address::new(0, 0));
+                dst
+            });
+        }
+
+        #[bench]
+        fn bench_genblocks_20(b: &mut Bencher) {
+            let prng = AesCtrPrng::new(&hash::tests::HASH_ELEMENT);
+            b.iter(|| {
+                let mut dst = [Default::default(); 20];
+                prng.genblocks(&mut dst, &address::Address::new(0, 0));
+                dst
+            });
+        }
+
+        fn bench_genblocks_pors<P: GravityParams>(b: &mut Bencher) {
+            let prng = AesCtrPrng::new(&hash::tests::HASH_ELEMENT);
+            b.iter(|| {
+                let mut dst = vec![Default::default(); P::PORS_T];
+                prng.genblocks(&mut dst, &address::Address::new(0, 0));
+                dst
+            });
+        }
+
+        // Baseline for `bench_genblocks_pors`, deriving the blocks one at a time.
+        fn bench_genblock_loop_pors<P: GravityParams>(b: &mut Bencher) {
+            let prng = AesCtrPrng::new(&hash::tests::HASH_ELEMENT);
+            b.iter(|| {
+                let mut dst = vec![Default::default(); P::PORS_T];
+                for (i, x) in dst.iter_mut().enumerate() {
+                    prng.genblock(x, &address::Address::new(0, 0), i as u32);
+                }
+                dst
+            });
+        }
+
+        #[bench]
+        fn bench_genblocks_wots(b: &mut Bencher) {
+            bench_genblocks_wots_with::<AesCtrPrng>(b);
+        }
+
+        #[bench]
+        fn bench_genblocks_wots_chacha20(b: &mut Bencher) {
+            bench_genblocks_wots_with::<ChaCha20Prng>(b);
+        }
+
+        #[bench]
+        fn bench_genblocks_wots_shake(b: &mut Bencher) {
+            bench_genblocks_wots_with::<ShakePrng>(b);
+        }
+
+        fn bench_genblocks_wots_with<R: SecretPrng>(b: &mut Bencher) {
+            let prng = R::new(&hash::tests::HASH_ELEMENT);
+            b.iter(|| {
+                let mut dst = [Default::default(); GravitySmall::WOTS_ELL];
+                prng.genblocks(&mut dst, &address::Address::new(0, 0));
+                dst
+            });
+        }
     }
 }
@@ -0,0 +1,260 @@
+use crate::config::{GravityParams, HASH_SIZE};
+use crate::gravity::{PubKey, Signature};
+use crate::hash::Hash;
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+// All types are serialized with the same layout as their `serialize` method, as a hex string
+// in human-readable formats and as a byte string otherwise.
+
+fn serialize_bytes<S: Serializer>(serializer: S, bytes: &[u8]) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&hex::encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+// Inputs longer than `max` bytes are rejected before they are decoded, so that a length
+// declared by the input never makes a large allocation.
+fn deserialize_bytes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    max: usize,
+) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(BytesVisitor { max })
+    } else {
+        deserializer.deserialize_byte_buf(BytesVisitor { max })
+    }
+}
+
+struct BytesVisitor {
+    max: usize,
+}
+
+impl BytesVisitor {
+    fn check_length<E: de::Error>(&self, len: usize) -> Result<(), E> {
+        if len > self.max {
+            Err(E::invalid_length(
+                len,
+                &format!("at most {} bytes", self.max).as_str(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a hex string or a byte string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        self.check_length(v.len() / 2)?;
+        hex::decode(v).map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        self.check_length(v.len())?;
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        self.check_length(v.len())?;
+        Ok(v)
+    }
+
+    // Some binary formats encode byte strings as sequences of integers.
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(self.max));
+        while let Some(x) = seq.next_element()? {
+            self.check_length(bytes.len() + 1)?;
+            bytes.push(x);
+        }
+        Ok(bytes)
+    }
+}
+
+fn deserialize_hash<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Hash, D::Error> {
+    let bytes = deserialize_bytes(deserializer, HASH_SIZE)?;
+    let h = bytes.as_slice().try_into().map_err(|_| {
+        de::Error::invalid_length(bytes.len(), &format!("{} bytes", HASH_SIZE).as_str())
+    })?;
+    Ok(Hash { h })
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(serializer, &self.h)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_hash(deserializer)
+    }
+}
+
+impl<P: GravityParams> Serialize for PubKey<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(serializer, self.get_bytes())
+    }
+}
+
+impl<'de, P: GravityParams> Deserialize<'de> for PubKey<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserialize_bytes(deserializer, P::HASH_BYTES)?;
+        PubKey::new(&bytes).ok_or_else(|| {
+            de::Error::invalid_length(bytes.len(), &format!("{} bytes", P::HASH_BYTES).as_str())
+        })
+    }
+}
+
+impl<P: GravityParams> Serialize for Signature<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut bytes = Vec::new();
+        Signature::serialize(self, &mut bytes);
+        serialize_bytes(serializer, &bytes)
+    }
+}
+
+impl<'de, P: GravityParams> Deserialize<'de> for Signature<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserialize_bytes(deserializer, P::SIGNATURE_BYTES)?;
+        let mut it = bytes.iter();
+        let sign = Signature::deserialize(&mut it)
+            .ok_or_else(|| de::Error::custom("invalid Gravity signature"))?;
+        if it.next().is_some() {
+            return Err(de::Error::custom("trailing bytes after Gravity signature"));
+        }
+        Ok(sign)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GravitySmall, GravitySmall128};
+    use crate::gravity::SecKey;
+    use crate::hash;
+
+    fn to_cbor<T: Serialize>(value: &T) -> Vec<u8> {
+        let mut output = Vec::new();
+        ciborium::into_writer(value, &mut output).unwrap();
+        output
+    }
+
+    fn from_cbor<T: for<'de> Deserialize<'de>>(input: &[u8]) -> Option<T> {
+        ciborium::from_reader(input).ok()
+    }
+
+    #[test]
+    fn test_hash_json() {
+        let h = hash::tests::HASH_ELEMENT;
+        let json = serde_json::to_string(&h).unwrap();
+        assert_eq!(
+            json,
+            "\"000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f\""
+        );
+        assert_eq!(serde_json::from_str::<Hash>(&json).unwrap(), h);
+    }
+
+    #[test]
+    fn test_hash_cbor() {
+        let h = hash::tests::HASH_ELEMENT;
+        let cbor = to_cbor(&h);
+        // Byte string (major type 2) of length 32.
+        assert_eq!(cbor[..2], [0x58, 0x20]);
+        assert_eq!(cbor[2..], h.h);
+        assert_eq!(from_cbor::<Hash>(&cbor).unwrap(), h);
+    }
+
+    #[test]
+    fn test_hash_malformed() {
+        assert!(serde_json::from_str::<Hash>("\"0001\"").is_err());
+        assert!(serde_json::from_str::<Hash>(&format!("\"{}\"", "zz".repeat(32))).is_err());
+        assert!(serde_json::from_str::<Hash>(&format!("\"{}\"", "00".repeat(33))).is_err());
+        assert!(from_cbor::<Hash>(&to_cbor(&RawBytes(&[0; 31]))).is_none());
+    }
+
+    #[test]
+    fn test_pubkey() {
+        let pk = PubKey::<GravitySmall>::new(&hash::tests::HASH_ELEMENT.h).unwrap();
+
+        let json = serde_json::to_string(&pk).unwrap();
+        let decoded: PubKey<GravitySmall> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.get_bytes(), pk.get_bytes());
+
+        let decoded: PubKey<GravitySmall> = from_cbor(&to_cbor(&pk)).unwrap();
+        assert_eq!(decoded.get_bytes(), pk.get_bytes());
+
+        // Public keys of 16-byte hashes are serialized on 16 bytes.
+        let pk = PubKey::<GravitySmall128>::new(&[1; 16]).unwrap();
+        let decoded: PubKey<GravitySmall128> = from_cbor(&to_cbor(&pk)).unwrap();
+        assert_eq!(decoded.get_bytes(), pk.get_bytes());
+        assert!(from_cbor::<PubKey<GravitySmall128>>(&to_cbor(&RawBytes(&[1; 32]))).is_none());
+    }
+
+    #[test]
+    fn test_signature() {
+        let sk = SecKey::<GravitySmall>::new(&[0; 64]);
+        let pk = sk.genpk();
+        let msg = b"Hello world";
+        let sign = sk.sign_bytes(msg);
+        let mut expect = Vec::new();
+        sign.serialize(&mut expect);
+
+        let json = serde_json::to_string(&sign).unwrap();
+        assert_eq!(json, format!("\"{}\"", hex::encode(&expect)));
+        let decoded: Signature<GravitySmall> = serde_json::from_str(&json).unwrap();
+        assert!(pk.verify_bytes(&decoded, msg));
+
+        let cbor = to_cbor(&sign);
+        let decoded: Signature<GravitySmall> = from_cbor(&cbor).unwrap();
+        assert!(pk.verify_bytes(&decoded, msg));
+
+        // Truncated and extended signatures are rejected.
+        let truncated = &expect[..expect.len() - 1];
+        assert!(from_cbor::<Signature<GravitySmall>>(&to_cbor(&RawBytes(truncated))).is_none());
+        let mut extended = expect.clone();
+        extended.push(0);
+        assert!(from_cbor::<Signature<GravitySmall>>(&to_cbor(&RawBytes(&extended))).is_none());
+        assert!(
+            serde_json::from_str::<Signature<GravitySmall>>(&format!(
+                "\"{}\"",
+                hex::encode(&extended)
+            ))
+            .is_err()
+        );
+    }
+
+    // An array that declares 2^44 elements must be rejected without reserving memory for them.
+    #[test]
+    fn test_huge_length() {
+        let header = [0x9B, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert!(from_cbor::<Signature<GravitySmall>>(&header).is_none());
+        assert!(from_cbor::<PubKey<GravitySmall>>(&header).is_none());
+        assert!(from_cbor::<Hash>(&header).is_none());
+
+        // Arrays of integers are rejected after the maximal length.
+        let mut array = vec![0x9B, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00];
+        array.extend([0; 33]);
+        assert!(from_cbor::<Hash>(&array).is_none());
+        assert!(from_cbor::<Hash>(&to_cbor(&[0u8; 32])).is_some());
+    }
+
+    struct RawBytes<'a>(&'a [u8]);
+
+    impl Serialize for RawBytes<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+}
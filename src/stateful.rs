@@ -0,0 +1,609 @@
+use crate::address;
+use crate::array::Array;
+use crate::config::GravityParams;
+use crate::gravity;
+use crate::hash;
+use crate::hash::{Hash, HashFunction, Tweak, TweakKind};
+#[cfg(feature = "alloc")]
+use crate::prng::SecretPrng;
+use crate::subtree;
+use crate::wots;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use byteorder::{BigEndian, ByteOrder};
+use core::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::{fs, io, path::PathBuf};
+use subtle::ConstantTimeEq;
+
+/// Secret key of the stateful mode. The hypertree is the same as in the stateless mode, but its
+/// leaves are used in order, and each of them signs the message with a single WOTS key instead
+/// of a PORS key. Signatures are about three times smaller, but a leaf must never sign twice,
+/// which is tracked by a [`State`].
+#[cfg(feature = "alloc")]
+pub struct StatefulSecKey<P: GravityParams> {
+    sk: gravity::SecKey<P>,
+}
+
+pub struct StatefulPubKey<P: GravityParams> {
+    pk: gravity::PubKey<P>,
+}
+
+pub struct StatefulSignature<P: GravityParams> {
+    index: u64,
+    pub_seed: Option<Hash>,
+    pepper: Hash,
+    wots_sign: wots::Signature<P>,
+    subtrees: P::GravityDArray<subtree::Signature<P>>,
+    auth_c: P::GravityCArray<Hash>,
+}
+
+/// Next leaf index of a stateful key. It only moves forward, and must be persisted after each
+/// [`State::reserve`] and before the reserved indices are used, so that a crash can skip
+/// indices but never reuse them.
+#[derive(Debug, PartialEq, Eq)]
+pub struct State<P: GravityParams> {
+    next: u64,
+    _phantom: PhantomData<P>,
+}
+
+/// Leaf indices handed out by [`State::reserve`], each of which signs at most once.
+#[derive(Debug)]
+pub struct Reservation {
+    next: u64,
+    end: u64,
+}
+
+#[cfg(feature = "alloc")]
+impl<P: GravityParams> StatefulSecKey<P> {
+    pub fn new(random: &[u8; 64]) -> Self {
+        // The seed is derived at an address unused by the stateless keys, so that a stateful
+        // key never shares its hypertree with the stateless key of the same random bytes.
+        let prng = P::Prng::new(&Hash::from_slice(&random[..32]));
+        let mut seed = Default::default();
+        prng.genblock(&mut seed, &address::Address::new(u32::MAX, 1), 0);
+        let mut random = *random;
+        random[..32].copy_from_slice(&seed.h);
+        Self {
+            sk: gravity::SecKey::new(&random),
+        }
+    }
+
+    pub fn genpk(&self) -> StatefulPubKey<P> {
+        StatefulPubKey {
+            pk: self.sk.genpk(),
+        }
+    }
+
+    /// Signs with the next index of the reservation, or returns `None` if it is exhausted.
+    pub fn sign_hash(
+        &self,
+        reservation: &mut Reservation,
+        msg: &Hash,
+    ) -> Option<StatefulSignature<P>> {
+        let index = reservation.take()?;
+        if index > P::GRAVITY_MASK {
+            return None;
+        }
+
+        let prng = self.sk.prng();
+        let seed = self.sk.pub_seed();
+        let address = address::Address::new(P::GRAVITY_D as u32, index);
+        let pepper = P::HashFunction::hash_2n_to_n_ret(self.sk.salt(), msg);
+        let digest = digest::<P>(seed, &address, &pepper, msg);
+
+        let wots_sign = wots::SecKey::<P>::new(&prng, &address).sign(seed, &address, &digest);
+        let h = wots_sign.extract(seed, &address, &digest);
+        let (subtrees, auth_c) = self.sk.sign_hypertree(&prng, address, h);
+
+        Some(StatefulSignature {
+            index,
+            pub_seed: seed.copied(),
+            pepper,
+            wots_sign,
+            subtrees,
+            auth_c,
+        })
+    }
+
+    pub fn sign_bytes(
+        &self,
+        reservation: &mut Reservation,
+        msg: &[u8],
+    ) -> Option<StatefulSignature<P>> {
+        let h = P::HashFunction::long_hash(msg);
+        self.sign_hash(reservation, &h)
+    }
+}
+
+// The WOTS key signs a digest of the message bound to its leaf, as it could otherwise be attacked
+// together with the WOTS keys of the other leaves.
+fn digest<P: GravityParams>(
+    seed: Option<&Hash>,
+    address: &address::Address,
+    pepper: &Hash,
+    msg: &Hash,
+) -> Hash {
+    let tweak = Tweak::<P::HashFunction>::new(seed, address, TweakKind::Message);
+    hash::tweak_2n_to_n_ret(&tweak, pepper, msg)
+}
+
+impl<P: GravityParams> StatefulPubKey<P> {
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            pk: gravity::PubKey::new(bytes)?,
+        })
+    }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        self.pk.get_bytes()
+    }
+
+    fn verify_hash(&self, sign: &StatefulSignature<P>, msg: &Hash) -> bool {
+        match sign.extract_hash(msg) {
+            Some(h) => self.pk.get_bytes().ct_eq(&h.h[..P::HASH_BYTES]).into(),
+            None => false,
+        }
+    }
+
+    pub fn verify_bytes(&self, sign: &StatefulSignature<P>, msg: &[u8]) -> bool {
+        let h = P::HashFunction::long_hash(msg);
+        self.verify_hash(sign, &h)
+    }
+}
+
+impl<P: GravityParams> StatefulSignature<P> {
+    /// Leaf index that produced this signature.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    fn extract_hash(&self, msg: &Hash) -> Option<Hash> {
+        if self.index > P::GRAVITY_MASK {
+            return None;
+        }
+        let seed = self.pub_seed.as_ref();
+        let address = address::Address::new(P::GRAVITY_D as u32, self.index);
+        let digest = digest::<P>(seed, &address, &self.pepper, msg);
+        let h = self.wots_sign.extract(seed, &address, &digest);
+        Some(gravity::extract_hypertree::<P>(
+            seed,
+            &self.subtrees,
+            &self.auth_c,
+            address,
+            h,
+        ))
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn serialize(&self, output: &mut Vec<u8>) {
+        let mut index = [0; 8];
+        BigEndian::write_u64(&mut index, self.index);
+        output.extend(index);
+        if let Some(pub_seed) = &self.pub_seed {
+            pub_seed.serialize(P::HASH_BYTES, output);
+        }
+        self.pepper.serialize(P::HASH_BYTES, output);
+        self.wots_sign.serialize(output);
+        for t in self.subtrees.as_ref().iter() {
+            t.serialize(output);
+        }
+        for x in self.auth_c.as_ref().iter() {
+            x.serialize(P::HASH_BYTES, output);
+        }
+    }
+
+    pub fn deserialize<'a, I>(it: &mut I) -> Option<Self>
+    where
+        I: Iterator<Item = &'a u8>,
+    {
+        let mut index = [0; 8];
+        for x in index.iter_mut() {
+            *x = *it.next()?;
+        }
+        let pub_seed = if P::TWEAKED {
+            Some(Hash::deserialize(P::HASH_BYTES, it)?)
+        } else {
+            None
+        };
+        let pepper = Hash::deserialize(P::HASH_BYTES, it)?;
+        let wots_sign = wots::Signature::deserialize(it)?;
+        let subtrees = Array::try_from_fn(|_| subtree::Signature::deserialize(it))?;
+        let auth_c = Array::try_from_fn(|_| Hash::deserialize(P::HASH_BYTES, it))?;
+
+        Some(StatefulSignature {
+            index: BigEndian::read_u64(&index),
+            pub_seed,
+            pepper,
+            wots_sign,
+            subtrees,
+            auth_c,
+        })
+    }
+}
+
+impl<P: GravityParams> State<P> {
+    /// State of a new key, whose first index is 0.
+    pub fn new() -> Self {
+        Self {
+            next: 0,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// State whose next index is `next`, for callers that keep track of the index themselves.
+    /// Returns `None` if `next` is past the last leaf.
+    pub fn starting_at(next: u64) -> Option<Self> {
+        if next > Self::capacity() {
+            return None;
+        }
+        Some(Self {
+            next,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Number of leaves of the hypertree, i.e. of stateful signatures of a key.
+    pub fn capacity() -> u64 {
+        const {
+            assert!(P::GRAVITY_C + P::GRAVITY_HD < 64);
+        }
+        P::GRAVITY_MASK + 1
+    }
+
+    pub fn next_index(&self) -> u64 {
+        self.next
+    }
+
+    pub fn remaining(&self) -> u64 {
+        Self::capacity() - self.next
+    }
+
+    /// Hands out the next `count` indices, or returns `None` if fewer remain.
+    pub fn reserve(&mut self, count: u64) -> Option<Reservation> {
+        if count > self.remaining() {
+            return None;
+        }
+        let reservation = Reservation {
+            next: self.next,
+            end: self.next + count,
+        };
+        self.next += count;
+        Some(reservation)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn serialize(&self, output: &mut Vec<u8>) {
+        let mut next = [0; 8];
+        BigEndian::write_u64(&mut next, self.next);
+        output.extend(next);
+    }
+
+    pub fn deserialize<'a, I>(it: &mut I) -> Option<Self>
+    where
+        I: Iterator<Item = &'a u8>,
+    {
+        let mut next = [0; 8];
+        for x in next.iter_mut() {
+            *x = *it.next()?;
+        }
+        Self::starting_at(BigEndian::read_u64(&next))
+    }
+}
+
+impl<P: GravityParams> Default for State<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reservation {
+    pub fn remaining(&self) -> u64 {
+        self.end - self.next
+    }
+
+    #[cfg(feature = "alloc")]
+    fn take(&mut self) -> Option<u64> {
+        if self.next == self.end {
+            return None;
+        }
+        self.next += 1;
+        Some(self.next - 1)
+    }
+}
+
+/// A [`State`] stored in a file, which is updated before any reservation is handed out. The new
+/// state is written to a temporary file, synced and renamed over the state file, so that a crash
+/// leaves either the old or the new state on disk.
+///
+/// A `StateFile` holds an exclusive lock on its file while it exists, as a lock file next to it
+/// with a `.lock` suffix, so that no two of them hand out the same indices, in one process or
+/// several. The lock file is removed when the `StateFile` is dropped. After a crash, it remains
+/// and the state file cannot be opened until it is removed by hand, once no process uses the key.
+#[cfg(feature = "std")]
+pub struct StateFile<P: GravityParams> {
+    path: PathBuf,
+    state: State<P>,
+    _lock: LockFile,
+}
+
+// A file created for exclusive access to the state file, and removed on drop.
+#[cfg(feature = "std")]
+struct LockFile(PathBuf);
+
+#[cfg(feature = "std")]
+impl LockFile {
+    fn new(path: &std::path::Path) -> io::Result<Self> {
+        let mut lock = path.to_path_buf().into_os_string();
+        lock.push(".lock");
+        let lock = PathBuf::from(lock);
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock)
+        {
+            Ok(_) => Ok(Self(lock)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "state file in use",
+            )),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<P: GravityParams> StateFile<P> {
+    /// Creates the state file of a new key. This fails if the file already exists, so that the
+    /// state of a key in use is never reset.
+    pub fn create(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let lock = LockFile::new(&path)?;
+        let state = State::new();
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes);
+        {
+            use std::io::Write;
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+        }
+        Ok(Self {
+            path,
+            state,
+            _lock: lock,
+        })
+    }
+
+    /// Opens the state file of a key. This fails if another `StateFile` has it open.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let lock = LockFile::new(&path)?;
+        let bytes = fs::read(&path)?;
+        let mut it = bytes.iter();
+        let state = State::deserialize(&mut it)
+            .filter(|_| it.next().is_none())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid state file"))?;
+        Ok(Self {
+            path,
+            state,
+            _lock: lock,
+        })
+    }
+
+    pub fn state(&self) -> &State<P> {
+        &self.state
+    }
+
+    /// Hands out the next `count` indices, once the new state is on disk.
+    pub fn reserve(&mut self, count: u64) -> io::Result<Reservation> {
+        let mut state = State {
+            next: self.state.next,
+            _phantom: PhantomData,
+        };
+        let reservation = state.reserve(count).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "not enough indices left")
+        })?;
+        self.write(&state)?;
+        self.state = state;
+        Ok(reservation)
+    }
+
+    fn write(&self, state: &State<P>) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes);
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        {
+            use std::io::Write;
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        // Persist the rename itself.
+        #[cfg(unix)]
+        if let Some(dir) = self.path.parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                std::path::Path::new(".")
+            } else {
+                dir
+            };
+            fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GravitySmall, GravitySmall128, GravitySmallTweaked};
+
+    crate::tests::param_tests!(small, GravitySmall, test_sign_verify,);
+    crate::tests::param_tests!(small_tweaked, GravitySmallTweaked, test_sign_verify,);
+    crate::tests::param_tests!(small_128, GravitySmall128, test_sign_verify,);
+
+    fn test_sign_verify<P: GravityParams>() {
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let sk = StatefulSecKey::<P>::new(&random);
+        let pk = sk.genpk();
+
+        let mut state = State::<P>::starting_at(State::<P>::capacity() - 3).unwrap();
+        let mut reservation = state.reserve(3).unwrap();
+        for (i, msg) in [b"Hello world".as_slice(), b"", &[0xff; 100]]
+            .into_iter()
+            .enumerate()
+        {
+            let sign = sk.sign_bytes(&mut reservation, msg).unwrap();
+            assert_eq!(sign.index(), State::<P>::capacity() - 3 + i as u64);
+            assert!(pk.verify_bytes(&sign, msg));
+            assert!(!pk.verify_bytes(&sign, b"Goodbye"));
+
+            let mut bytes = Vec::new();
+            sign.serialize(&mut bytes);
+            assert_eq!(bytes.len(), P::STATEFUL_SIGNATURE_BYTES);
+            let sign = StatefulSignature::<P>::deserialize(&mut bytes.iter()).unwrap();
+            assert!(pk.verify_bytes(&sign, msg));
+
+            // The signature is bound to its index.
+            bytes[7] ^= 1;
+            let sign = StatefulSignature::<P>::deserialize(&mut bytes.iter()).unwrap();
+            assert!(!pk.verify_bytes(&sign, msg));
+        }
+        assert!(sk.sign_bytes(&mut reservation, b"").is_none());
+        assert!(state.reserve(1).is_none());
+    }
+
+    #[test]
+    fn test_signature_size() {
+        assert_eq!(GravitySmall::STATEFUL_SIGNATURE_BYTES, 4808);
+        assert_eq!(GravitySmallTweaked::STATEFUL_SIGNATURE_BYTES, 4840);
+        assert_eq!(GravitySmall128::STATEFUL_SIGNATURE_BYTES, 1384);
+    }
+
+    #[test]
+    fn test_keys_separated() {
+        let random: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let sk = StatefulSecKey::<GravitySmall>::new(&random);
+        let stateless_sk = gravity::SecKey::<GravitySmall>::new(&random);
+        assert_ne!(sk.genpk().get_bytes(), stateless_sk.genpk().get_bytes());
+    }
+
+    #[test]
+    fn test_reserve() {
+        type S = State<GravitySmall>;
+        let capacity = S::capacity();
+        assert_eq!(capacity, 1 << 15);
+
+        let mut state = S::new();
+        let mut reservation = state.reserve(2).unwrap();
+        assert_eq!(reservation.remaining(), 2);
+        assert_eq!(reservation.take(), Some(0));
+        assert_eq!(reservation.take(), Some(1));
+        assert_eq!(reservation.take(), None);
+        assert_eq!(state.next_index(), 2);
+
+        let mut reservation = state.reserve(3).unwrap();
+        assert_eq!(reservation.take(), Some(2));
+        assert_eq!(state.next_index(), 5);
+        assert_eq!(state.remaining(), capacity - 5);
+
+        assert!(state.reserve(capacity).is_none());
+        assert_eq!(state.next_index(), 5);
+        assert!(state.reserve(capacity - 5).is_some());
+        assert_eq!(state.remaining(), 0);
+        assert!(state.reserve(1).is_none());
+        assert!(state.reserve(0).is_some());
+
+        assert!(S::starting_at(capacity).is_some());
+        assert!(S::starting_at(capacity + 1).is_none());
+    }
+
+    #[test]
+    fn test_state_serialize() {
+        type S = State<GravitySmall>;
+        let state = S::starting_at(12345).unwrap();
+        let mut bytes = Vec::new();
+        state.serialize(&mut bytes);
+        assert_eq!(bytes, [0, 0, 0, 0, 0, 0, 0x30, 0x39]);
+        assert_eq!(S::deserialize(&mut bytes.iter()), Some(state));
+
+        assert!(S::deserialize(&mut bytes[..7].iter()).is_none());
+        let past_end = (S::capacity() + 1).to_be_bytes();
+        assert!(S::deserialize(&mut past_end.iter()).is_none());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_state_file() {
+        type F = StateFile<GravitySmall>;
+        let dir = std::env::temp_dir().join(format!("gravity-state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state");
+        let _ = fs::remove_file(&path);
+
+        let mut file = F::create(&path).unwrap();
+        assert!(F::create(&path).is_err());
+        let mut reservation = file.reserve(10).unwrap();
+        assert_eq!(reservation.take(), Some(0));
+        drop(file);
+        assert_eq!(F::open(&path).unwrap().state().next_index(), 10);
+
+        // Indices reserved before a crash are skipped.
+        let mut file = F::open(&path).unwrap();
+        assert_eq!(file.reserve(1).unwrap().take(), Some(10));
+        assert!(file.reserve(State::<GravitySmall>::capacity()).is_err());
+        drop(file);
+        assert_eq!(F::open(&path).unwrap().state().next_index(), 11);
+
+        fs::write(&path, [0; 9]).unwrap();
+        assert!(F::open(&path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_state_file_lock() {
+        type F = StateFile<GravitySmall>;
+        let dir = std::env::temp_dir().join(format!("gravity-lock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state");
+        let lock = dir.join("state.lock");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&lock);
+
+        // A second open fails while the file is open, even from another thread.
+        let mut file = F::create(&path).unwrap();
+        assert!(lock.exists());
+        let err = std::thread::scope(|s| s.spawn(|| F::open(&path)).join().unwrap());
+        assert_eq!(err.err().unwrap().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(file.reserve(5).unwrap().take(), Some(0));
+        drop(file);
+        assert!(!lock.exists());
+
+        let mut file = F::open(&path).unwrap();
+        assert!(F::open(&path).is_err());
+        assert_eq!(file.reserve(1).unwrap().take(), Some(5));
+        drop(file);
+
+        // A lock left by a crash must be removed by hand.
+        fs::write(&lock, []).unwrap();
+        assert!(F::open(&path).is_err());
+        fs::remove_file(&lock).unwrap();
+        assert_eq!(F::open(&path).unwrap().state().next_index(), 6);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,77 +1,122 @@
 use crate::address;
+use crate::array::Array;
 use crate::config::*;
-use crate::hash::Hash;
+use crate::hash::{Hash, Tweak, TweakKind};
 use crate::merkle;
-use crate::prng;
 use crate::wots;
-use std::array;
-use std::marker::PhantomData;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+use core::marker::PhantomData;
+#[cfg(test)]
+use subtle::{Choice, ConstantTimeEq};
 
+#[cfg(feature = "alloc")]
 pub struct SecKey<'a, P: GravityParams> {
-    prng: &'a prng::Prng,
+    prng: &'a P::Prng,
+    seed: Option<&'a Hash>,
+    cache: Option<&'a Cache<P>>,
     _phantom: PhantomData<P>,
 }
 
+// Merkle trees of all the subtrees of the top layers of the hypertree, so that signing can read
+// their roots and authentication paths rather than regenerating their WOTS public keys.
+#[cfg(feature = "alloc")]
+pub struct Cache<P: GravityParams> {
+    layers: Vec<Vec<merkle::MerkleTree>>,
+    _phantom: PhantomData<P>,
+}
+
+#[cfg(feature = "alloc")]
 pub struct PubKey<P: GravityParams> {
     pub h: Hash,
     _phantom: PhantomData<P>,
 }
 
-pub struct Signature<P: GravityParams>
-where
-    [(); P::MERKLE_H]:,
-{
-    wots_sign: wots::Signature,
-    auth: [Hash; P::MERKLE_H],
+pub struct Signature<P: GravityParams> {
+    wots_sign: wots::Signature<P>,
+    auth: P::MerkleHArray<Hash>,
 }
 
+#[cfg(feature = "alloc")]
 impl<'a, P: GravityParams> SecKey<'a, P> {
-    pub fn new(prng: &'a prng::Prng) -> Self {
+    pub fn new(prng: &'a P::Prng, seed: Option<&'a Hash>) -> Self {
         Self {
             prng,
+            seed,
+            cache: None,
             _phantom: PhantomData,
         }
     }
 
+    pub fn with_cache(self, cache: Option<&'a Cache<P>>) -> Self {
+        Self { cache, ..self }
+    }
+
+    // Merkle tree of the subtree at the given (normalized) address.
+    fn gen_tree(&self, address: &address::Address) -> merkle::MerkleTree {
+        let mut tree = merkle::MerkleTree::new(P::MERKLE_H);
+        let tweak = Tweak::<P::HashFunction>::new(self.seed, address, TweakKind::SubtreeNode);
+        let layer = address.get_layer();
+        let instance = address.get_instance();
+
+        for (i, leaf) in tree.leaves().iter_mut().enumerate() {
+            let address = address::Address::new(layer, instance + i as u64);
+            let sk = wots::SecKey::<P>::new(self.prng, &address);
+            *leaf = sk.genpk(self.seed, &address).h;
+        }
+
+        tree.generate(&tweak);
+        tree
+    }
+
     pub fn genpk(&self, address: &address::Address) -> PubKey<P> {
         let mut buf = merkle::MerkleBuf::new(P::MERKLE_H);
         let (mut address, _) = address.normalize_index(P::MERKLE_H_MASK as u64);
+        let tweak = Tweak::<P::HashFunction>::new(self.seed, &address, TweakKind::SubtreeNode);
 
         for leaf in buf.slice_leaves_mut() {
-            let sk = wots::SecKey::new(self.prng, &address);
-            let pk = sk.genpk();
+            let sk = wots::SecKey::<P>::new(self.prng, &address);
+            let pk = sk.genpk(self.seed, &address);
             *leaf = pk.h;
             address.incr_instance();
         }
 
         let mut dst = Default::default();
-        merkle::merkle_compress_all(&mut dst, &mut buf);
+        merkle::merkle_compress_all(&tweak, &mut dst, &mut buf);
         PubKey {
             h: dst,
             _phantom: PhantomData,
         }
     }
 
-    pub fn sign(&self, address: &address::Address, msg: &Hash) -> (Hash, Signature<P>)
-    where
-        [(); P::MERKLE_H]:,
-    {
+    pub fn sign(&self, address: &address::Address, msg: &Hash) -> (Hash, Signature<P>) {
+        if let Some(tree) = self.cache.and_then(|cache| cache.get(address)) {
+            let (_, index) = address.normalize_index(P::MERKLE_H_MASK as u64);
+            let sk = wots::SecKey::<P>::new(self.prng, address);
+            let wots_sign = sk.sign(self.seed, address, msg);
+            let mut auth: P::MerkleHArray<Hash> = Array::from_fn(|_| Default::default());
+            tree.gen_auth(auth.as_mut(), index);
+            return (tree.root(), Signature { wots_sign, auth });
+        }
+
         let mut buf = merkle::MerkleBuf::new(P::MERKLE_H);
         let (mut address, index) = address.normalize_index(P::MERKLE_H_MASK as u64);
+        let tweak = Tweak::<P::HashFunction>::new(self.seed, &address, TweakKind::SubtreeNode);
 
         let mut wots_sign = None;
         for (i, leaf) in buf.slice_leaves_mut().iter_mut().enumerate() {
-            let sk = wots::SecKey::new(self.prng, &address);
-            let pk = sk.genpk();
+            let sk = wots::SecKey::<P>::new(self.prng, &address);
+            let pk = sk.genpk(self.seed, &address);
             *leaf = pk.h;
             if i == index {
-                wots_sign = Some(sk.sign(msg));
+                wots_sign = Some(sk.sign(self.seed, &address, msg));
             }
             address.incr_instance();
         }
 
-        let mut auth = [Default::default(); P::MERKLE_H];
-        let root = merkle::merkle_gen_auth(&mut auth, &mut buf, index);
+        let mut auth: P::MerkleHArray<Hash> = Array::from_fn(|_| Default::default());
+        let root = merkle::merkle_gen_auth(&tweak, auth.as_mut(), &mut buf, index);
 
         let sign = Signature {
             wots_sign: wots_sign.unwrap(),
@@ -81,37 +126,87 @@ impl<'a, P: GravityParams> SecKey<'a, P> {
     }
 }
 
+#[cfg(feature = "alloc")]
+impl<P: GravityParams> Cache<P> {
+    // Generate the trees of the top `layers` layers of the hypertree.
+    pub fn new(sk: &SecKey<'_, P>, layers: usize) -> Self {
+        assert!(layers <= P::GRAVITY_D);
+        let layers = (0..layers)
+            .map(|layer| {
+                let count = 1u64 << (P::GRAVITY_C + layer * P::MERKLE_H);
+                (0..count)
+                    .map(|i| {
+                        let address = address::Address::new(layer as u32, i << P::MERKLE_H);
+                        sk.gen_tree(&address)
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            layers,
+            _phantom: PhantomData,
+        }
+    }
+
+    // Memory used by the trees of the given layer, or `None` if it doesn't fit in a `usize`.
+    pub fn layer_bytes(layer: usize) -> Option<usize> {
+        let trees = 1usize.checked_shl((P::GRAVITY_C + layer * P::MERKLE_H) as u32)?;
+        let tree_bytes = (2 << P::MERKLE_H) * core::mem::size_of::<Hash>();
+        trees.checked_mul(tree_bytes)
+    }
+
+    // Number of top layers whose trees fit in the given memory budget, in bytes.
+    pub fn layers_within(budget: usize) -> usize {
+        let mut total = 0usize;
+        for layer in 0..P::GRAVITY_D {
+            match Self::layer_bytes(layer).and_then(|x| total.checked_add(x)) {
+                Some(x) if x <= budget => total = x,
+                _ => return layer,
+            }
+        }
+        P::GRAVITY_D
+    }
+
+    fn get(&self, address: &address::Address) -> Option<&merkle::MerkleTree> {
+        let trees = self.layers.get(address.get_layer() as usize)?;
+        trees.get((address.get_instance() >> P::MERKLE_H) as usize)
+    }
+}
+
+#[cfg(feature = "alloc")]
 impl<P: GravityParams> PubKey<P> {
     #[cfg(test)]
-    pub fn verify(&self, address: &address::Address, sign: &Signature<P>, msg: &Hash) -> bool
-    where
-        [(); P::MERKLE_H]:,
-    {
-        let h = sign.extract(address, msg);
-        self.h == h
+    pub fn verify(
+        &self,
+        seed: Option<&Hash>,
+        address: &address::Address,
+        sign: &Signature<P>,
+        msg: &Hash,
+    ) -> Choice {
+        let h = sign.extract(seed, address, msg);
+        self.h.ct_eq(&h)
     }
 }
 
-impl<P: GravityParams> Signature<P>
-where
-    [(); P::MERKLE_H]:,
-{
-    pub fn extract(&self, address: &address::Address, msg: &Hash) -> Hash {
-        let (_, index) = address.normalize_index(P::MERKLE_H_MASK as u64);
-        let mut h = self.wots_sign.extract(msg);
-        merkle::merkle_compress_auth(&mut h, &self.auth, P::MERKLE_H, index);
+impl<P: GravityParams> Signature<P> {
+    pub fn extract(&self, seed: Option<&Hash>, address: &address::Address, msg: &Hash) -> Hash {
+        let (normalized, index) = address.normalize_index(P::MERKLE_H_MASK as u64);
+        let mut h = self.wots_sign.extract(seed, address, msg);
+        let tweak = Tweak::<P::HashFunction>::new(seed, &normalized, TweakKind::SubtreeNode);
+        merkle::merkle_compress_auth(&tweak, &mut h, self.auth.as_ref(), P::MERKLE_H, index);
         h
     }
 
     #[cfg(test)]
     pub fn size_hashes() -> usize {
-        wots::Signature::size_hashes() + P::MERKLE_H
+        wots::Signature::<P>::size_hashes() + P::MERKLE_H
     }
 
+    #[cfg(feature = "alloc")]
     pub fn serialize(&self, output: &mut Vec<u8>) {
         self.wots_sign.serialize(output);
-        for x in self.auth.iter() {
-            x.serialize(output);
+        for x in self.auth.as_ref().iter() {
+            x.serialize(P::HASH_BYTES, output);
         }
     }
 
@@ -120,7 +215,7 @@ where
         I: Iterator<Item = &'a u8>,
     {
         let wots_sign = wots::Signature::deserialize(it)?;
-        let auth = array::try_from_fn(|_| Hash::deserialize(it))?;
+        let auth = Array::try_from_fn(|_| Hash::deserialize(P::HASH_BYTES, it))?;
 
         Some(Signature { wots_sign, auth })
     }
@@ -130,6 +225,7 @@ where
 mod tests {
     use super::super::hash;
     use super::*;
+    use crate::prng::SecretPrng;
 
     macro_rules! all_tests {
         ( $mod:ident, $params:ty ) => {
@@ -140,95 +236,162 @@ mod tests {
     all_tests!(small, GravitySmall);
     all_tests!(medium, GravityMedium);
     all_tests!(large, GravityLarge);
-
-    fn test_signature_size<P: GravityParams>()
-    where
-        [(); P::MERKLE_H]:,
-    {
+    all_tests!(small_tweaked, GravitySmallTweaked);
+    all_tests!(small_sha2, GravitySmallSha2);
+    all_tests!(small_shake, GravitySmallShake);
+    all_tests!(small_128, GravitySmall128);
+    all_tests!(small_192, GravitySmall192);
+    all_tests!(small_w4, SmallW4);
+    all_tests!(small_w256, SmallW256);
+
+    fn test_signature_size<P: GravityParams>() {
         let expected_hashes = match P::config_type() {
-            ConfigType::S | ConfigType::M | ConfigType::L => 72,
+            ConfigType::S
+            | ConfigType::M
+            | ConfigType::L
+            | ConfigType::ST
+            | ConfigType::SSha2
+            | ConfigType::SShake => 72,
+            ConfigType::S128 => 40,
+            ConfigType::S192 => 56,
+            ConfigType::SW4 => 138,
+            ConfigType::SW256 => 39,
             ConfigType::Unknown => unimplemented!(),
         };
         assert_eq!(Signature::<P>::size_hashes(), expected_hashes);
     }
 
-    fn test_sign_verify<P: GravityParams>()
-    where
-        [(); P::MERKLE_H]:,
-    {
+    crate::tests::param_tests!(cached_small, GravitySmall, test_sign_cached,);
+    crate::tests::param_tests!(cached_small_tweaked, GravitySmallTweaked, test_sign_cached,);
+
+    fn test_sign_cached<P: GravityParams>() {
+        let seed = hash::tests::HASH_ELEMENT;
+        let pub_seed = P::TWEAKED.then_some(&seed);
+        let prng = P::Prng::new(&seed);
+        let sk = SecKey::<P>::new(&prng, pub_seed);
+        let cache = Cache::new(&sk, 1);
+        let cached_sk = SecKey::<P>::new(&prng, pub_seed).with_cache(Some(&cache));
+
+        let msg = hash::tests::HASH_ELEMENT;
+        // The last address is below the cached layer.
+        for (layer, instance) in [(0, 0), (0, 31), (0, 0x1ab), (0, 0x7fff), (1, 0x12345)] {
+            let address = address::Address::new(layer, instance);
+            let (root, sign) = sk.sign(&address, &msg);
+            let (cached_root, cached_sign) = cached_sk.sign(&address, &msg);
+            assert_eq!(root, cached_root);
+
+            let mut bytes = Vec::new();
+            sign.serialize(&mut bytes);
+            let mut cached_bytes = Vec::new();
+            cached_sign.serialize(&mut cached_bytes);
+            assert_eq!(bytes, cached_bytes);
+        }
+    }
+
+    #[test]
+    fn test_cache_budget() {
+        let layer_bytes = (1 << 10) * 64 * 32;
+        assert_eq!(Cache::<GravitySmall>::layer_bytes(0), Some(layer_bytes));
+        assert_eq!(Cache::<GravitySmall>::layers_within(0), 0);
+        assert_eq!(Cache::<GravitySmall>::layers_within(layer_bytes - 1), 0);
+        assert_eq!(Cache::<GravitySmall>::layers_within(layer_bytes), 1);
+        assert_eq!(Cache::<GravitySmall>::layers_within(usize::MAX), 1);
+
+        assert_eq!(
+            Cache::<GravityMedium>::layer_bytes(1),
+            Some((1 << 20) * 64 * 32)
+        );
+        assert_eq!(Cache::<GravityMedium>::layers_within(1 << 30), 1);
+        assert_eq!(Cache::<GravityLarge>::layer_bytes(9), None);
+        assert!(Cache::<GravityLarge>::layers_within(usize::MAX) < GravityLarge::GRAVITY_D);
+    }
+
+    fn test_sign_verify<P: GravityParams>() {
         let seed = hash::tests::HASH_ELEMENT;
         let layer: u32 = 0x01020304;
         let instance: u64 = 0x05060708090a0b0c;
 
-        let prng = prng::Prng::new(&seed);
+        let pub_seed = P::TWEAKED.then_some(&seed);
+        let prng = P::Prng::new(&seed);
         let address = address::Address::new(layer, instance);
 
-        let sk = SecKey::<P>::new(&prng);
+        let sk = SecKey::<P>::new(&prng, pub_seed);
         let pk = sk.genpk(&address);
         let msg = hash::tests::HASH_ELEMENT;
         let (root, sign) = sk.sign(&address, &msg);
         assert_eq!(root, pk.h);
-        assert!(pk.verify(&address, &sign, &msg));
+        assert!(bool::from(pk.verify(pub_seed, &address, &sign, &msg)));
     }
 
-    macro_rules! all_benches {
-        ( $mod:ident, $params:ty ) => {
-            crate::tests::param_benches!($mod, $params, bench_genpk, bench_sign, bench_verify,);
-        };
-    }
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
 
-    all_benches!(benches_small, GravitySmall);
-    all_benches!(benches_medium, GravityMedium);
-    all_benches!(benches_large, GravityLarge);
+        macro_rules! all_benches {
+            ( $mod:ident, $params:ty ) => {
+                crate::tests::param_benches!($mod, $params, bench_genpk, bench_sign, bench_verify,);
+            };
+        }
 
-    use std::hint::black_box;
-    use test::Bencher;
+        all_benches!(benches_small, GravitySmall);
+        all_benches!(benches_medium, GravityMedium);
+        all_benches!(benches_large, GravityLarge);
+        all_benches!(benches_small_w4, SmallW4);
+        all_benches!(benches_small_w256, SmallW256);
 
-    fn bench_genpk<P: GravityParams>(b: &mut Bencher) {
-        let seed = hash::tests::HASH_ELEMENT;
-        let layer: u32 = 0x01020304;
-        let instance: u64 = 0x05060708090a0b0c;
+        use std::hint::black_box;
+        use test::Bencher;
 
-        let prng = prng::Prng::new(&seed);
-        let address = address::Address::new(layer, instance);
+        fn bench_genpk<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let layer: u32 = 0x01020304;
+            let instance: u64 = 0x05060708090a0b0c;
 
-        let sk = SecKey::<P>::new(&prng);
-        b.iter(|| sk.genpk(black_box(&address)));
-    }
+            let pub_seed = P::TWEAKED.then_some(&seed);
+            let prng = P::Prng::new(&seed);
+            let address = address::Address::new(layer, instance);
 
-    fn bench_sign<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::MERKLE_H]:,
-    {
-        let seed = hash::tests::HASH_ELEMENT;
-        let layer: u32 = 0x01020304;
-        let instance: u64 = 0x05060708090a0b0c;
+            let sk = SecKey::<P>::new(&prng, pub_seed);
+            b.iter(|| sk.genpk(black_box(&address)));
+        }
 
-        let prng = prng::Prng::new(&seed);
-        let address = address::Address::new(layer, instance);
+        fn bench_sign<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let layer: u32 = 0x01020304;
+            let instance: u64 = 0x05060708090a0b0c;
 
-        let sk = SecKey::<P>::new(&prng);
-        let msg = hash::tests::HASH_ELEMENT;
-        b.iter(|| sk.sign(black_box(&address), black_box(&msg)));
-    }
+            let pub_seed = P::TWEAKED.then_some(&seed);
+            let prng = P::Prng::new(&seed);
+            let address = address::Address::new(layer, instance);
 
-    fn bench_verify<P: GravityParams>(b: &mut Bencher)
-    where
-        [(); P::MERKLE_H]:,
-    {
-        let seed = hash::tests::HASH_ELEMENT;
-        let layer: u32 = 0x01020304;
-        let instance: u64 = 0x05060708090a0b0c;
+            let sk = SecKey::<P>::new(&prng, pub_seed);
+            let msg = hash::tests::HASH_ELEMENT;
+            b.iter(|| sk.sign(black_box(&address), black_box(&msg)));
+        }
 
-        let prng = prng::Prng::new(&seed);
-        let address = address::Address::new(layer, instance);
+        fn bench_verify<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let layer: u32 = 0x01020304;
+            let instance: u64 = 0x05060708090a0b0c;
+
+            let pub_seed = P::TWEAKED.then_some(&seed);
+            let prng = P::Prng::new(&seed);
+            let address = address::Address::new(layer, instance);
+
+            let sk = SecKey::<P>::new(&prng, pub_seed);
+            let pk = sk.genpk(&address);
+            let msg = hash::tests::HASH_ELEMENT;
+            let (_, sign) = sk.sign(&address, &msg);
+            b.iter(|| {
+                pk.verify(
+                    pub_seed,
+                    black_box(&address),
+                    black_box(&sign),
+                    black_box(&msg),
+                )
+            });
+        }
 
-        let sk = SecKey::<P>::new(&prng);
-        let pk = sk.genpk(&address);
-        let msg = hash::tests::HASH_ELEMENT;
-        let (_, sign) = sk.sign(&address, &msg);
-        b.iter(|| pk.verify(black_box(&address), black_box(&sign), black_box(&msg)));
+        // TODO: test vectors
     }
-
-    // TODO: test vectors
 }
@@ -1,110 +1,143 @@
 use crate::address;
+use crate::array::Array;
 use crate::config::*;
 use crate::hash;
-use crate::hash::Hash;
-use crate::ltree::ltree_leaves_ret;
-use crate::prng;
-use std::default;
+#[cfg(feature = "alloc")]
+use crate::hash::HashFunction;
+use crate::hash::{Hash, Tweak, TweakKind};
+use crate::ltree::ltree_leaves_mut;
+#[cfg(feature = "alloc")]
+use crate::prng::SecretPrng;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::default;
+#[cfg(feature = "alloc")]
+use core::marker::PhantomData;
+#[cfg(test)]
+use subtle::{Choice, ConstantTimeEq};
 
-pub struct SecKey([Hash; WOTS_ELL]);
-pub struct PubKey {
+#[cfg(feature = "alloc")]
+pub struct SecKey<P: GravityParams>(P::WotsArray<Hash>);
+#[cfg(feature = "alloc")]
+pub struct PubKey<P: GravityParams> {
     pub h: Hash,
+    _phantom: PhantomData<P>,
 }
-pub struct Signature([Hash; WOTS_ELL]);
+pub struct Signature<P: GravityParams>(P::WotsArray<Hash>);
 
-impl default::Default for Signature {
+impl<P: GravityParams> default::Default for Signature<P> {
     fn default() -> Self {
-        Signature([Default::default(); WOTS_ELL])
+        Signature(Array::from_fn(|_| Default::default()))
     }
 }
 
 // Split a message into a list of Winternitz indices (with checksum)
-#[allow(clippy::needless_range_loop)]
-fn split_msg(msg: &Hash) -> [usize; WOTS_ELL] {
-    const {
-        assert!(
-            WOTS_W == 16,
-            "Winternitz OTS is only implemented for WOTS_W = 16"
-        );
-    }
-
-    let mut result = [0; WOTS_ELL];
+fn split_msg<P: GravityParams>(msg: &Hash) -> P::WotsArray<usize> {
+    let mut result: P::WotsArray<usize> = Array::from_fn(|_| 0);
+    let result_mut = result.as_mut();
+    let digits_per_byte = 8 / P::WOTS_LOG_W;
     let mut checksum: usize = 0;
 
-    for j in 0..HASH_SIZE {
-        let v = msg.h[j];
-        let a = (v >> 4) as usize;
-        let b = (v & 0xF) as usize;
-        checksum += (WOTS_W - 1 - a) + (WOTS_W - 1 - b);
-
-        result[2 * j] = a;
-        result[2 * j + 1] = b;
+    for (j, &v) in msg.h[..P::HASH_BYTES].iter().enumerate() {
+        for k in 0..digits_per_byte {
+            let shift = 8 - P::WOTS_LOG_W * (k + 1);
+            let a = ((v as usize) >> shift) & (P::WOTS_W - 1);
+            checksum += P::WOTS_W - 1 - a;
+            result_mut[digits_per_byte * j + k] = a;
+        }
     }
 
-    for i in WOTS_ELL1..WOTS_ELL {
-        result[i] = checksum & 0xF;
-        checksum >>= 4;
+    for x in result_mut[P::WOTS_ELL1..].iter_mut() {
+        *x = checksum & (P::WOTS_W - 1);
+        checksum >>= P::WOTS_LOG_W;
     }
 
     result
 }
 
-impl SecKey {
-    pub fn new(prng: &prng::Prng, address: &address::Address) -> Self {
-        let mut sk = SecKey([Default::default(); WOTS_ELL]);
-        prng.genblocks(&mut sk.0, address);
+#[cfg(feature = "alloc")]
+impl<P: GravityParams> SecKey<P> {
+    pub fn new(prng: &P::Prng, address: &address::Address) -> Self {
+        let mut sk: SecKey<P> = SecKey(Array::from_fn(|_| Default::default()));
+        prng.genblocks(sk.0.as_mut(), address);
+        sk.0.as_mut().iter_mut().for_each(P::HashFunction::truncate);
         sk
     }
 
-    pub fn genpk(&self) -> PubKey {
-        let mut buf = [Default::default(); WOTS_ELL];
-        hash::hash_parallel_chains_all(&mut buf, &self.0, WOTS_W - 1);
+    pub fn genpk(&self, seed: Option<&Hash>, address: &address::Address) -> PubKey<P> {
+        let mut buf: P::WotsArray<Hash> = Array::from_fn(|_| Default::default());
+        let tweak = Tweak::<P::HashFunction>::new(seed, address, TweakKind::WotsChain);
+        hash::hash_parallel_chains_all(&tweak, buf.as_mut(), self.0.as_ref(), P::WOTS_W - 1);
         PubKey {
-            h: ltree_leaves_ret(&buf),
+            h: ltree_leaves_mut(
+                &Tweak::<P::HashFunction>::new(seed, address, TweakKind::LTree),
+                buf.as_mut(),
+            ),
+            _phantom: PhantomData,
         }
     }
 
-    #[allow(clippy::needless_range_loop)]
-    pub fn sign(&self, msg: &Hash) -> Signature {
-        let mut sign = Signature([Default::default(); WOTS_ELL]);
-        let lengths = split_msg(msg);
-
-        for i in 0..WOTS_ELL {
-            hash::hash_n_to_n_chain(&mut sign.0[i], &self.0[i], lengths[i]);
+    pub fn sign(
+        &self,
+        seed: Option<&Hash>,
+        address: &address::Address,
+        msg: &Hash,
+    ) -> Signature<P> {
+        let mut sign: Signature<P> = Default::default();
+        let lengths = split_msg::<P>(msg);
+        let tweak = Tweak::<P::HashFunction>::new(seed, address, TweakKind::WotsChain);
+
+        for i in 0..P::WOTS_ELL {
+            let chain_tweak = tweak.with_height(i);
+            hash::hash_n_to_n_chain(&chain_tweak, &mut sign.0[i], &self.0[i], 0, lengths[i]);
         }
 
         sign
     }
 }
 
-impl PubKey {
+#[cfg(feature = "alloc")]
+impl<P: GravityParams> PubKey<P> {
     #[cfg(test)]
-    pub fn verify(&self, sign: &Signature, msg: &Hash) -> bool {
-        let h = sign.extract(msg);
-        self.h == h
+    pub fn verify(
+        &self,
+        seed: Option<&Hash>,
+        address: &address::Address,
+        sign: &Signature<P>,
+        msg: &Hash,
+    ) -> Choice {
+        let h = sign.extract(seed, address, msg);
+        self.h.ct_eq(&h)
     }
 }
 
-impl Signature {
-    pub fn extract(&self, msg: &Hash) -> Hash {
-        let mut buf = [Default::default(); WOTS_ELL];
-        let lengths = split_msg(msg);
+impl<P: GravityParams> Signature<P> {
+    pub fn extract(&self, seed: Option<&Hash>, address: &address::Address, msg: &Hash) -> Hash {
+        let mut buf: P::WotsArray<Hash> = Array::from_fn(|_| Default::default());
+        let lengths = split_msg::<P>(msg);
+        let tweak = Tweak::<P::HashFunction>::new(seed, address, TweakKind::WotsChain);
 
-        for i in 0..WOTS_ELL {
-            hash::hash_n_to_n_chain(&mut buf[i], &self.0[i], WOTS_W - 1 - lengths[i]);
+        for i in 0..P::WOTS_ELL {
+            let chain_tweak = tweak.with_height(i);
+            let count = P::WOTS_W - 1 - lengths[i];
+            hash::hash_n_to_n_chain(&chain_tweak, &mut buf[i], &self.0[i], lengths[i], count);
         }
 
-        ltree_leaves_ret(&buf)
+        ltree_leaves_mut(
+            &Tweak::<P::HashFunction>::new(seed, address, TweakKind::LTree),
+            buf.as_mut(),
+        )
     }
 
     #[cfg(test)]
     pub fn size_hashes() -> usize {
-        WOTS_ELL
+        P::WOTS_ELL
     }
 
+    #[cfg(feature = "alloc")]
     pub fn serialize(&self, output: &mut Vec<u8>) {
-        for x in self.0.iter() {
-            x.serialize(output);
+        for x in self.0.as_ref().iter() {
+            x.serialize(P::HASH_BYTES, output);
         }
     }
 
@@ -112,9 +145,9 @@ impl Signature {
     where
         I: Iterator<Item = &'a u8>,
     {
-        let mut sign: Signature = Default::default();
-        for x in sign.0.iter_mut() {
-            *x = Hash::deserialize(it)?;
+        let mut sign: Signature<P> = Default::default();
+        for x in sign.0.as_mut().iter_mut() {
+            *x = Hash::deserialize(P::HASH_BYTES, it)?;
         }
         Some(sign)
     }
@@ -123,35 +156,102 @@ impl Signature {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::prng::AesCtrPrng;
+
+    macro_rules! all_tests {
+        ( $mod:ident, $params:ty ) => {
+            crate::tests::param_tests!(
+                $mod,
+                $params,
+                test_sign_verify,
+                test_sign_verify_tweaked,
+                test_split_msg_0,
+                test_split_msg_checksum,
+            );
+        };
+    }
 
-    #[test]
-    fn test_sign_verify() {
+    all_tests!(small, GravitySmall);
+    all_tests!(small_w4, SmallW4);
+    all_tests!(small_w256, SmallW256);
+
+    fn test_sign_verify<P: GravityParams>() {
         let seed = hash::tests::HASH_ELEMENT;
         let layer: u32 = 0;
         let instance: u64 = 0;
 
-        let prng = prng::Prng::new(&seed);
+        let prng = P::Prng::new(&seed);
         let address = address::Address::new(layer, instance);
-        let sk = SecKey::new(&prng, &address);
-        let pk = sk.genpk();
+        let sk = SecKey::<P>::new(&prng, &address);
+        let pk = sk.genpk(None, &address);
         let msg = hash::tests::HASH_ELEMENT;
-        let sign = sk.sign(&msg);
-        assert!(pk.verify(&sign, &msg));
+        let sign = sk.sign(None, &address, &msg);
+        assert!(bool::from(pk.verify(None, &address, &sign, &msg)));
     }
 
-    #[test]
-    fn test_split_msg_0() {
+    fn test_sign_verify_tweaked<P: GravityParams>() {
+        let seed = hash::tests::HASH_ELEMENT;
+        let address = address::Address::new(1, 2);
+        let other = address::Address::new(1, 3);
+
+        let prng = P::Prng::new(&seed);
+        let sk = SecKey::<P>::new(&prng, &address);
+        let pk = sk.genpk(Some(&seed), &address);
+        assert_ne!(pk.h, sk.genpk(None, &address).h);
+
+        let msg = hash::tests::HASH_ELEMENT;
+        let sign = sk.sign(Some(&seed), &address, &msg);
+        assert!(bool::from(pk.verify(Some(&seed), &address, &sign, &msg)));
+        assert!(!bool::from(pk.verify(Some(&seed), &other, &sign, &msg)));
+        let other_seed = Hash {
+            h: seed.h.map(|x| x ^ 1),
+        };
+        assert!(!bool::from(pk.verify(
+            Some(&other_seed),
+            &address,
+            &sign,
+            &msg
+        )));
+    }
+
+    // Value of the checksum digits, which are in little-endian order.
+    fn checksum_value<P: GravityParams>(lengths: &[usize]) -> usize {
+        lengths[P::WOTS_ELL1..]
+            .iter()
+            .rev()
+            .fold(0, |acc, &x| acc * P::WOTS_W + x)
+    }
+
+    fn test_split_msg_0<P: GravityParams>() {
         let msg = Hash { h: [0; HASH_SIZE] };
-        let lengths = split_msg(&msg);
-        let mut expect: [usize; WOTS_ELL] = [0; WOTS_ELL];
-        expect[64] = 0x0;
-        expect[65] = 0xC;
-        expect[66] = 0x3;
+        let lengths = split_msg::<P>(&msg);
+        assert!(lengths.as_ref()[..P::WOTS_ELL1].iter().all(|&x| x == 0));
         assert_eq!(
-            expect[64] + expect[65] * WOTS_W + expect[66] * WOTS_W * WOTS_W,
-            WOTS_ELL1 * (WOTS_W - 1)
+            checksum_value::<P>(lengths.as_ref()),
+            P::WOTS_ELL1 * (P::WOTS_W - 1)
         );
-        assert_eq!(lengths, expect);
+    }
+
+    fn test_split_msg_checksum<P: GravityParams>() {
+        let msg = Hash {
+            h: core::array::from_fn(|i| (i * 37 + 11) as u8),
+        };
+        let lengths = split_msg::<P>(&msg);
+        assert!(lengths.as_ref().iter().all(|&x| x < P::WOTS_W));
+
+        // The message digits, read in big-endian order, give back the message.
+        let mut bytes = [0u8; HASH_SIZE];
+        for (i, &x) in lengths.as_ref()[..P::WOTS_ELL1].iter().enumerate() {
+            let bit = i * P::WOTS_LOG_W;
+            bytes[bit / 8] |= (x << (8 - P::WOTS_LOG_W - bit % 8)) as u8;
+        }
+        assert_eq!(bytes, msg.h);
+
+        let checksum: usize = lengths.as_ref()[..P::WOTS_ELL1]
+            .iter()
+            .map(|&x| P::WOTS_W - 1 - x)
+            .sum();
+        assert_eq!(checksum_value::<P>(lengths.as_ref()), checksum);
     }
 
     #[test]
@@ -162,8 +262,8 @@ mod tests {
                   \x10\x11\x12\x13\x14\x15\x16\x17\
                   \x18\x19\x1a\x1b\x1c\x1d\x1e\x1f",
         };
-        let lengths = split_msg(&msg);
-        let expect: [usize; WOTS_ELL] = [
+        let lengths = split_msg::<GravitySmall>(&msg);
+        let expect: [usize; GravitySmall::WOTS_ELL] = [
             0, 0, 0, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0, 7, 0, 8, 0, 9, 0, 10, 0, 11, 0, 12, 0, 13,
             0, 14, 0, 15, 1, 0, 1, 1, 1, 2, 1, 3, 1, 4, 1, 5, 1, 6, 1, 7, 1, 8, 1, 9, 1, 10, 1, 11,
             1, 12, 1, 13, 1, 14, 1, 15, 0, 12, 2,
@@ -172,87 +272,167 @@ mod tests {
             + 16 * 14 // ones
             + 15 * 16; // sequence
         assert_eq!(
-            expect[64] + expect[65] * WOTS_W + expect[66] * WOTS_W * WOTS_W,
+            expect[64] + expect[65] * 16 + expect[66] * 16 * 16,
             checksum
         );
         assert_eq!(lengths, expect);
     }
 
-    use std::hint::black_box;
-    use test::Bencher;
-
-    #[bench]
-    fn bench_keypair(b: &mut Bencher) {
-        let seed = hash::tests::HASH_ELEMENT;
-        let layer: u32 = 0;
-        let instance: u64 = 0;
-
-        let prng = prng::Prng::new(&seed);
-        b.iter(|| {
-            let address = black_box(address::Address::new(layer, instance));
-            let sk = SecKey::new(&prng, &address);
-            sk.genpk()
-        });
+    #[test]
+    fn test_split_msg_w4() {
+        let mut msg = Hash { h: [0; HASH_SIZE] };
+        msg.h[0] = 0b00_01_10_11;
+        msg.h[31] = 0xFF;
+        let lengths = split_msg::<SmallW4>(&msg);
+        assert_eq!(lengths[..4], [0, 1, 2, 3]);
+        assert_eq!(lengths[124..128], [3, 3, 3, 3]);
+        // checksum = 128 * 3 - 6 - 12 = 366 = 0x16E
+        assert_eq!(lengths[128..], [2, 3, 2, 1, 1]);
     }
 
-    #[bench]
-    fn bench_gensk(b: &mut Bencher) {
-        let seed = hash::tests::HASH_ELEMENT;
-        let layer: u32 = 0;
-        let instance: u64 = 0;
-
-        let prng = prng::Prng::new(&seed);
-        b.iter(|| {
-            let address = black_box(address::Address::new(layer, instance));
-            SecKey::new(&prng, &address)
-        });
+    #[test]
+    fn test_split_msg_w256() {
+        let msg = hash::tests::HASH_ELEMENT;
+        let lengths = split_msg::<SmallW256>(&msg);
+        for (&x, &v) in lengths[..HASH_SIZE].iter().zip(msg.h.iter()) {
+            assert_eq!(x, v as usize);
+        }
     }
 
-    #[bench]
-    fn bench_genpk(b: &mut Bencher) {
-        let seed = hash::tests::HASH_ELEMENT;
-        let layer: u32 = 0;
-        let instance: u64 = 0;
+    #[test]
+    fn test_split_msg_unchanged() {
+        // Same derivation as the original implementation, for w = 16.
+        let msg = hash::tests::HASH_ELEMENT;
+        let lengths = split_msg::<GravitySmall>(&msg);
+        let mut checksum = 0;
+        for j in 0..HASH_SIZE {
+            let a = (msg.h[j] >> 4) as usize;
+            let b = (msg.h[j] & 0xF) as usize;
+            checksum += 30 - a - b;
+            assert_eq!(lengths[2 * j], a);
+            assert_eq!(lengths[2 * j + 1], b);
+        }
+        assert_eq!(
+            lengths[64..],
+            [checksum & 0xF, (checksum >> 4) & 0xF, checksum >> 8]
+        );
+    }
 
-        let prng = prng::Prng::new(&seed);
-        let address = address::Address::new(layer, instance);
-        let sk = SecKey::new(&prng, &address);
-        b.iter(|| sk.genpk());
+    #[test]
+    fn test_signature_sizes() {
+        assert_eq!(Signature::<GravitySmall>::size_hashes(), 67);
+        assert_eq!(Signature::<SmallW4>::size_hashes(), 133);
+        assert_eq!(Signature::<SmallW256>::size_hashes(), 34);
     }
 
-    #[bench]
-    fn bench_sign(b: &mut Bencher) {
+    #[test]
+    fn test_signature_mismatch() {
         let seed = hash::tests::HASH_ELEMENT;
-        let layer: u32 = 0;
-        let instance: u64 = 0;
-
-        let prng = prng::Prng::new(&seed);
-        let address = address::Address::new(layer, instance);
-        let sk = SecKey::new(&prng, &address);
+        let address = address::Address::new(0, 0);
+        let prng = AesCtrPrng::new(&seed);
+        let sk = SecKey::<GravitySmall>::new(&prng, &address);
+        let pk = sk.genpk(None, &address);
         let msg = hash::tests::HASH_ELEMENT;
-        b.iter(|| sk.sign(black_box(&msg)));
+        let sign = sk.sign(None, &address, &msg);
+        let other = Hash {
+            h: msg.h.map(|x| x ^ 0x10),
+        };
+        assert!(!bool::from(pk.verify(None, &address, &sign, &other)));
     }
 
-    #[bench]
-    fn bench_verify(b: &mut Bencher) {
-        let seed = hash::tests::HASH_ELEMENT;
-        let layer: u32 = 0;
-        let instance: u64 = 0;
+    #[cfg(feature = "bench")]
+    mod benches {
+        use super::*;
+
+        use std::hint::black_box;
+        use test::Bencher;
+
+        macro_rules! all_benches {
+            ( $mod:ident, $params:ty ) => {
+                crate::tests::param_benches!(
+                    $mod,
+                    $params,
+                    bench_keypair,
+                    bench_gensk,
+                    bench_genpk,
+                    bench_sign,
+                    bench_verify,
+                    bench_split_msg,
+                );
+            };
+        }
 
-        let prng = prng::Prng::new(&seed);
-        let address = address::Address::new(layer, instance);
-        let sk = SecKey::new(&prng, &address);
-        let pk = sk.genpk();
-        let msg = hash::tests::HASH_ELEMENT;
-        let sign = sk.sign(&msg);
-        b.iter(|| pk.verify(black_box(&sign), black_box(&msg)));
-    }
+        all_benches!(benches_small, GravitySmall);
+        all_benches!(benches_small_w4, SmallW4);
+        all_benches!(benches_small_w256, SmallW256);
+
+        fn bench_keypair<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let layer: u32 = 0;
+            let instance: u64 = 0;
+
+            let prng = P::Prng::new(&seed);
+            b.iter(|| {
+                let address = black_box(address::Address::new(layer, instance));
+                let sk = SecKey::<P>::new(&prng, &address);
+                sk.genpk(None, &address)
+            });
+        }
 
-    #[bench]
-    fn bench_split_msg(b: &mut Bencher) {
-        let msg = Hash { h: [0; HASH_SIZE] };
-        b.iter(|| split_msg(black_box(&msg)));
-    }
+        fn bench_gensk<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let layer: u32 = 0;
+            let instance: u64 = 0;
+
+            let prng = P::Prng::new(&seed);
+            b.iter(|| {
+                let address = black_box(address::Address::new(layer, instance));
+                SecKey::<P>::new(&prng, &address)
+            });
+        }
+
+        fn bench_genpk<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let layer: u32 = 0;
+            let instance: u64 = 0;
 
-    // TODO: test vectors
+            let prng = P::Prng::new(&seed);
+            let address = address::Address::new(layer, instance);
+            let sk = SecKey::<P>::new(&prng, &address);
+            b.iter(|| sk.genpk(None, &address));
+        }
+
+        fn bench_sign<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let layer: u32 = 0;
+            let instance: u64 = 0;
+
+            let prng = P::Prng::new(&seed);
+            let address = address::Address::new(layer, instance);
+            let sk = SecKey::<P>::new(&prng, &address);
+            let msg = hash::tests::HASH_ELEMENT;
+            b.iter(|| sk.sign(None, &address, black_box(&msg)));
+        }
+
+        fn bench_verify<P: GravityParams>(b: &mut Bencher) {
+            let seed = hash::tests::HASH_ELEMENT;
+            let layer: u32 = 0;
+            let instance: u64 = 0;
+
+            let prng = P::Prng::new(&seed);
+            let address = address::Address::new(layer, instance);
+            let sk = SecKey::<P>::new(&prng, &address);
+            let pk = sk.genpk(None, &address);
+            let msg = hash::tests::HASH_ELEMENT;
+            let sign = sk.sign(None, &address, &msg);
+            b.iter(|| pk.verify(None, &address, black_box(&sign), black_box(&msg)));
+        }
+
+        fn bench_split_msg<P: GravityParams>(b: &mut Bencher) {
+            let msg = Hash { h: [0; HASH_SIZE] };
+            b.iter(|| split_msg::<P>(black_box(&msg)));
+        }
+
+        // TODO: test vectors
+    }
 }